@@ -4,25 +4,157 @@ export type Bone = {
  * Orientation of joint
  */
 local_orientation: [number, number, number, number], tail_world_position: [number, number, number], parent: BoneLocation | null, };
+/**
+ * The values are sent to the SteamVR driver so they must not change
+ */
 export type BoneLocation = "CenterHip" | "LeftUpperLeg" | "RightUpperLeg" | "LeftLowerLeg" | "RightLowerLeg" | "LeftFoot" | "RightFoot" | "Waist" | "Chest" | "UpperChest" | "Neck" | "Head" | "LeftShoulder" | "RightShoulder" | "LeftUpperArm" | "RightUpperArm" | "LeftLowerArm" | "RightLowerArm" | "LeftHand" | "RightHand" | "LeftHip" | "RightHip";
 /**
  * Offset type for a specific body part used to offset the bone (joints) in meters
  * See BoneLocation::get_offset
  */
 export type BoneOffsetKind = "HeadLength" | "NeckLength" | "WaistLength" | "ChestLength" | "UpperChestLength" | "HipsWidth" | "UpperLegLength" | "LowerLegLength" | "ShouldersWidth" | "ShoulderOffset" | "UpperArmLength" | "LowerArmLength" | "FootLength" | "HandLength";
-export type GlobalConfig = { trackers: { [key in string]?: TrackerConfig }, vmc: VmcConfig, vrchat: VrChatConfig, skeleton: SkeletonConfig, interface: InterfaceConfig, };
+export type CommandResult = { tracker_id: string, command: DeviceCommand, 
+/**
+ * None if the device has run the command
+ */
+error: string | null, };
+export type DeviceCommand = { "type": "BlinkLed" } | { "type": "Reboot" } | { "type": "SetSampleRate", rate_hz: number, } | { "type": "StartCalibration" } | { "type": "PowerOff" } | { "type": "SetSensorMode", mode: SensorMode, };
+/**
+ * Sent by the device when it connects
+ */
+export type DeviceInfo = { protocol_version: number, board: string, imu: string, sensor_count: number, };
+export type DeviceLog = { mac: string, level: DeviceLogLevel, message: string, };
+export type DeviceLogLevel = "Info" | "Warn" | "Error";
+export type FirmwareUpdateStatus = { "type": "Uploading", progress: number, } | { "type": "Done", version: string, } | { "type": "Failed", error: string, };
+export type GlobalConfig = { trackers: { [key in string]?: TrackerConfig }, vmc: VmcConfig, vrchat: VrChatConfig, steamvr: SteamVrConfig, skeleton: SkeletonConfig, serial: SerialConfig, udp: UdpConfig, interface: InterfaceConfig, main_loop: LoopConfig, };
 export type InterfaceConfig = { hide_in_system_tray: boolean, };
+export type LatencyStats = { min_ms: number, avg_ms: number, p95_ms: number, 
+/**
+ * Average difference between consecutive pings
+ */
+jitter_ms: number, };
+/**
+ * Health of the connection to a udp device since the last upkeep
+ */
+export type LinkQuality = { latency?: LatencyStats, packets_per_sec: number, bytes_per_sec: number, loss_rate: number, out_of_order: number, };
+export type LoopConfig = { 
+/**
+ * Updates per second, between 30 and 240
+ */
+tick_rate: number, 
+/**
+ * Update as soon as new tracker data arrives instead of waiting for the next tick,
+ * the tick rate is still used when nothing arrives
+ */
+event_driven: boolean, };
+/**
+ * Timing of the main loop over the last second
+ */
+export type LoopMetrics = { 
+/**
+ * Updates that actually happened per second
+ */
+tick_rate: number, avg_update_ms: number, max_update_ms: number, 
+/**
+ * Updates that took longer than the tick interval
+ */
+overruns: number, };
+/**
+ * How often an output sends its bundles, independent of the rate the server updates at
+ */
+export type OscSendRate = { 
+/**
+ * Bundles sent per second, 0 sends one every update. At most one bundle is sent per update,
+ * so rates above the tick rate of the main loop send one every update too
+ */
+per_second: number, 
+/**
+ * Skip bundles that are the same as the last one sent
+ */
+only_on_change: boolean, };
+export type PendingDevice = { mac: string, address: string, device_info?: DeviceInfo, firmware_version?: string, };
+export type SensorMode = "SixAxis" | "NineAxis";
+export type SerialCommand = { "type": "SetWifi", ssid: string, password: string, } | { "type": "GetDeviceInfo" } | { "type": "SetServerIp", ip: string, } | { "type": "FactoryReset" } | { "type": "Restart" } | { "type": "SelfTest" };
+export type SerialConfig = { baud_rate: number, 
+/**
+ * Only list and automatically connect to ports that look like a tracker
+ */
+only_known_devices: boolean, };
+export type SerialPortInfo = { name: string, vid: number, pid: number, serial_number?: string, product?: string, 
+/**
+ * The USB ids match a known tracker board
+ */
+known_device: boolean, connected: boolean, };
+export type SerialResponse = { "type": "WifiConnecting" } | { "type": "WifiConnectOk" } | { "type": "WifiConnectTimeout" } | { "type": "Connected" } | { "type": "Restarting" } | { "type": "DeviceInfo", mac: string, firmware_version: string, board: string, } | { "type": "SelfTest", passed: boolean, message: string, } | { "type": "Paired", mac: string, } | { "type": "CommandOk", command: string, } | { "type": "CommandError", command: string, error: string, };
 export type SkeletonConfig = { 
 /**
  * Contains the length offset in meters from a bone to its connecting one
  */
 offsets: { [key in BoneOffsetKind]?: number }, user_height: number, };
+export type SteamVrConfig = { enabled: boolean, send_port: number, bones_to_send: Array<BoneLocation>, };
 export type Tracker = { info: TrackerInfo, data: TrackerData, };
 export type TrackerConfig = { name?: string, location?: BoneLocation, };
 export type TrackerData = { orientation: [number, number, number, number], acceleration: [number, number, number], position: [number, number, number], };
-export type TrackerInfo = { to_be_removed: boolean, status: TrackerStatus, latency_ms?: number, battery_level: number, address?: string, };
+export type TrackerInfo = { to_be_removed: boolean, status: TrackerStatus, latency_ms?: number, battery_level: number, address?: string, 
+/**
+ * None when the tracker only exists in the config and hasn't connected yet
+ */
+source?: TrackerSource, device?: DeviceInfo, firmware_version?: string, 
+/**
+ * A newer firmware version that can be installed on the device
+ */
+firmware_update?: string, wifi_rssi?: number, 
+/**
+ * In celsius
+ */
+imu_temperature?: number, 
+/**
+ * Packets from the device are authenticated with its pairing key
+ */
+authenticated: boolean, 
+/**
+ * Fraction of the packets from the device that were lost since the last upkeep
+ */
+packet_loss?: number, };
+/**
+ * Where the tracker data is coming from
+ */
+export type TrackerSource = "Udp" | "Serial" | "Vmc" | "Synthetic" | "Bvh";
 export type TrackerStatus = "Ok" | "Error" | "Off" | "TimedOut";
-export type VmcConfig = { enabled: boolean, send_port: number, receive_port: number, };
-export type VrChatConfig = { enabled: boolean, send_port: number, bones_to_send: Array<BoneLocation>, };
-export type WebsocketClientMessage = { "type": "SerialSend", data: string, } | { "type": "RemoveTracker", id: string, } | { "type": "UpdateConfig", config: GlobalConfig, } | { "type": "ResetTrackerOrientations" } | { "type": "StartRecord" } | { "type": "StopRecord", save_path: string, };
-export type WebsocketServerMessage = { "type": "TrackerUpdate", trackers: { [key in string]?: Tracker }, } | { "type": "InitialState", config: GlobalConfig, port_name?: string, default_config: GlobalConfig, trackers: { [key in string]?: Tracker }, } | { "type": "SkeletonUpdate", bones: { [key in BoneLocation]?: Bone }, } | { "type": "ConfigUpdate", config: GlobalConfig, } | { "type": "SerialLog", log: string, } | { "type": "SerialPortChanged", port_name?: string, } | { "type": "Error", error: string, };
+export type UdpConfig = { 
+/**
+ * Use `::` to also accept IPv6 devices
+ */
+bind_address: string, port: number, 
+/**
+ * Name of the network interface to receive multicast on, the system chooses if not set
+ */
+multicast_interface?: string, 
+/**
+ * Advertise the server with mDNS for networks that filter the multicast discovery
+ */
+advertise_mdns: boolean, 
+/**
+ * Only accept devices that have been paired over serial
+ */
+require_pairing: boolean, 
+/**
+ * Mac addresses of the devices that are not allowed to connect
+ */
+ignored_devices: Array<string>, 
+/**
+ * Only accept devices that have trackers from before, have been paired or approved
+ */
+only_known_devices: boolean, 
+/**
+ * New devices have to be approved by the user before they can connect
+ */
+require_approval: boolean, 
+/**
+ * Mac addresses of the devices the user has approved
+ */
+approved_devices: Array<string>, };
+export type VmcConfig = { enabled: boolean, send_port: number, receive_enabled: boolean, receive_port: number, send_rate: OscSendRate, };
+export type VrChatConfig = { enabled: boolean, send_port: number, bones_to_send: Array<BoneLocation>, send_rate: OscSendRate, };
+export type WebsocketClientMessage = { "type": "SerialSend", port_name: string, data: string, } | { "type": "SerialCommand", port_name: string, command: SerialCommand, } | { "type": "FlashSerialFirmware", port_name: string, path: string, } | { "type": "PairSerialDevice", port_name: string, } | { "type": "UnpairDevice", mac: string, } | { "type": "UnignoreDevice", mac: string, } | { "type": "ApproveDevice", mac: string, } | { "type": "RejectDevice", mac: string, } | { "type": "ConnectSerialPort", port_name: string, } | { "type": "DisconnectSerialPort", port_name: string, } | { "type": "RemoveTracker", id: string, } | { "type": "StartFirmwareUpdate", mac: string, } | { "type": "TrackerCommand", id: string, command: DeviceCommand, } | { "type": "UpdateConfig", config: GlobalConfig, } | { "type": "ResetTrackerOrientations" } | { "type": "StartRecord" } | { "type": "StopRecord", save_path: string, };
+export type WebsocketServerMessage = { "type": "TrackerUpdate", trackers: { [key in string]?: Tracker }, } | { "type": "InitialState", config: GlobalConfig, serial_ports: Array<SerialPortInfo>, default_config: GlobalConfig, trackers: { [key in string]?: Tracker }, } | { "type": "SkeletonUpdate", bones: { [key in BoneLocation]?: Bone }, } | { "type": "ConfigUpdate", config: GlobalConfig, } | { "type": "SerialLog", port_name: string, log: string, } | { "type": "SerialResponse", port_name: string, response: SerialResponse, } | { "type": "SerialFlash", port_name: string, status: FirmwareUpdateStatus, } | { "type": "SerialPortsUpdate", ports: Array<SerialPortInfo>, } | { "type": "FirmwareUpdate", mac: string, status: FirmwareUpdateStatus, } | { "type": "DeviceLog", log: DeviceLog, } | { "type": "CommandResult", result: CommandResult, } | { "type": "LinkQualityUpdate", qualities: { [key in string]?: LinkQuality }, } | { "type": "PendingDevices", devices: Array<PendingDevice>, } | { "type": "LoopMetrics", metrics: LoopMetrics, } | { "type": "Error", error: string, };
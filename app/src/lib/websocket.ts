@@ -2,7 +2,14 @@ import { get, writable } from "svelte/store";
 import { confirmPopup, errorToast, infoToast } from "./toast";
 import type {
     BoneLocation,
+    DeviceCommand,
+    DeviceLog,
     GlobalConfig,
+    LinkQuality,
+    LoopMetrics,
+    PendingDevice,
+    SerialPortInfo,
+    SerialResponse,
     Tracker,
     TrackerConfig,
     WebsocketClientMessage,
@@ -21,8 +28,22 @@ export const bones = writable<BoneDict>();
 export const globalConfig = writable<GlobalConfig>();
 export let defaultConfig: GlobalConfig;
 
-export const serialPortName = writable<string | undefined>();
-export const serialLog = writable<string[]>([]);
+export const serialPorts = writable<SerialPortInfo[]>([]);
+export const selectedSerialPort = writable<string | undefined>();
+export const serialLogs = writable<{ [port in string]?: string[] }>({});
+// Maps a port name to the progress of the firmware being flashed
+export const serialFlashes = writable<{ [port in string]?: number }>({});
+
+// Maps a device mac address to the health of its connection
+export const linkQualities = writable<{ [mac in string]?: LinkQuality }>({});
+// Maps a device mac address to its recent logs
+export const deviceLogs = writable<{ [mac in string]?: DeviceLog[] }>({});
+// Maps a device mac address to the progress of its firmware update
+export const firmwareUpdates = writable<{ [mac in string]?: number }>({});
+// Udp devices waiting for the user to approve them
+export const pendingDevices = writable<PendingDevice[]>([]);
+// Timing of the server main loop over the last second
+export const loopMetrics = writable<LoopMetrics | undefined>();
 
 export let websocket: WebSocket | undefined;
 export const websocketConnected = writable(false);
@@ -100,6 +121,65 @@ export async function removeTracker(id: string) {
     });
 }
 
+export async function startFirmwareUpdate(id: string) {
+    await confirmPopup(
+        "Are you sure you want to update the firmware?",
+        "The device will restart once the update is done.",
+    );
+    sendWebsocket({
+        type: "StartFirmwareUpdate",
+        // Tracker ids are the device mac address followed by the tracker index
+        mac: id.split("/")[0],
+    });
+}
+
+export async function sendTrackerCommand(id: string, command: DeviceCommand) {
+    if (command.type == "Reboot" || command.type == "PowerOff") {
+        await confirmPopup(
+            `Are you sure you want to ${command.type == "Reboot" ? "reboot" : "power off"} the device?`,
+            "Every tracker of the device will disconnect.",
+        );
+    }
+
+    sendWebsocket({
+        type: "TrackerCommand",
+        id,
+        command,
+    });
+}
+
+export function pairSerialDevice(port_name: string) {
+    sendWebsocket({
+        type: "PairSerialDevice",
+        port_name,
+    });
+}
+
+export async function unpairDevice(mac: string) {
+    await confirmPopup(
+        "Are you sure you want to unpair the device?",
+        "The device will have to be paired over serial again to connect while pairing is required.",
+    );
+    sendWebsocket({
+        type: "UnpairDevice",
+        mac,
+    });
+}
+
+export function unignoreDevice(mac: string) {
+    sendWebsocket({
+        type: "UnignoreDevice",
+        mac,
+    });
+}
+
+export function approveDevice(mac: string, approve: boolean) {
+    sendWebsocket({
+        type: approve ? "ApproveDevice" : "RejectDevice",
+        mac,
+    });
+}
+
 globalConfig.subscribe((config) => {
     if (config) {
         invoke("update_interface_config", { config: config.interface });
@@ -112,28 +192,95 @@ function handleMessage(message: WebsocketServerMessage) {
             errorToast(message.error);
             console.error("Error from server: " + message.error);
             break;
-        case "SerialPortChanged":
-            serialPortName.set(message.port_name);
-            if (message.port_name) {
-                infoToast(`Serial port ${message.port_name} has been connected`);
-            } else {
-                infoToast(`Serial port has been disconnected`);
+        case "SerialFlash":
+            const flashStatus = message.status;
+            serialFlashes.update((flashes) => {
+                flashes[message.port_name] =
+                    flashStatus.type == "Uploading" ? flashStatus.progress : undefined;
+                return flashes;
+            });
+
+            if (flashStatus.type == "Done") {
+                infoToast(`Flashed firmware ${flashStatus.version} to ${message.port_name}`);
+            } else if (flashStatus.type == "Failed") {
+                errorToast(`Flashing ${message.port_name} failed: ${flashStatus.error}`);
             }
             break;
+        case "SerialPortsUpdate":
+            setSerialPorts(message.ports);
+            break;
         case "SerialLog":
-            serialLog.update((log) => {
+            serialLogs.update((logs) => {
+                const log = (logs[message.port_name] ??= []);
                 if (log.length > 100) {
                     // Keep log size to less than
                     log.shift();
                 }
 
                 log.push(message.log);
-                return log;
+                return logs;
+            });
+            break;
+        case "SerialResponse":
+            const response = getSerialResponseMessage(message.response);
+            if (message.response.type == "CommandError") {
+                errorToast(`${message.port_name}: ${response}`);
+            } else {
+                infoToast(`${message.port_name}: ${response}`);
+            }
+            break;
+        case "FirmwareUpdate":
+            const status = message.status;
+            firmwareUpdates.update((updates) => {
+                updates[message.mac] = status.type == "Uploading" ? status.progress : undefined;
+                return updates;
+            });
+
+            switch (status.type) {
+                case "Done":
+                    infoToast(`${message.mac} updated to firmware ${status.version}`);
+                    break;
+                case "Failed":
+                    errorToast(`Firmware update of ${message.mac} failed: ${status.error}`);
+                    break;
+            }
+            break;
+        case "DeviceLog":
+            deviceLogs.update((logs) => {
+                const log = (logs[message.log.mac] ??= []);
+                if (log.length > 20) {
+                    log.shift();
+                }
+
+                log.push(message.log);
+                return logs;
             });
 
-            const status = getSerialStatusMessage(message.log);
-            if (status) {
-                infoToast(`Serial device: ${status}`);
+            if (message.log.level == "Error") {
+                errorToast(`${message.log.mac}: ${message.log.message}`);
+            }
+            break;
+        case "LinkQualityUpdate":
+            linkQualities.update((qualities) => ({ ...qualities, ...message.qualities }));
+            break;
+        case "PendingDevices":
+            const newDevices = message.devices.filter(
+                (device) => !get(pendingDevices).some((pending) => pending.mac == device.mac),
+            );
+            for (const device of newDevices) {
+                infoToast(`Device ${device.mac} is waiting for approval`);
+            }
+            pendingDevices.set(message.devices);
+            break;
+        case "LoopMetrics":
+            loopMetrics.set(message.metrics);
+            break;
+        case "CommandResult":
+            const result = message.result;
+            if (result.error) {
+                errorToast(`${result.command.type} on ${result.tracker_id} failed: ${result.error}`);
+            } else {
+                infoToast(`${result.command.type} sent to ${result.tracker_id}`);
             }
             break;
         case "SkeletonUpdate":
@@ -144,7 +291,7 @@ function handleMessage(message: WebsocketServerMessage) {
             break;
         case "InitialState":
             globalConfig.set(message.config);
-            serialPortName.set(message.port_name);
+            setSerialPorts(message.serial_ports);
             defaultConfig = message.default_config;
             trackers.set(message.trackers);
             break;
@@ -168,8 +315,23 @@ function handleMessage(message: WebsocketServerMessage) {
     }
 }
 
-function getSerialStatusMessage(message: string): string {
-    switch (message) {
+function setSerialPorts(ports: SerialPortInfo[]) {
+    const previous = get(serialPorts);
+    ports
+        .filter((port) => port.connected)
+        .filter((port) => !previous.some((prev) => prev.name == port.name && prev.connected))
+        .forEach((port) => infoToast(`Serial port ${port.name} has been connected`));
+    serialPorts.set(ports);
+
+    // Select the first connected port if the selected one went away
+    const selected = get(selectedSerialPort);
+    if (!ports.some((port) => port.name == selected && port.connected)) {
+        selectedSerialPort.set(ports.find((port) => port.connected)?.name);
+    }
+}
+
+function getSerialResponseMessage(response: SerialResponse): string {
+    switch (response.type) {
         case "WifiConnecting":
             return "Connecting to the WiFi network";
         case "WifiConnectOk":
@@ -180,7 +342,13 @@ function getSerialStatusMessage(message: string): string {
             return "Connected to the server";
         case "Restarting":
             return "Restarting";
-        default:
-            return "";
+        case "DeviceInfo":
+            return `${response.board} with firmware ${response.firmware_version} (${response.mac})`;
+        case "SelfTest":
+            return `Self test ${response.passed ? "passed" : "failed"} ${response.message}`;
+        case "CommandOk":
+            return `${response.command} succeeded`;
+        case "CommandError":
+            return `${response.command} failed: ${response.error}`;
     }
 }
@@ -4,9 +4,13 @@ use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::{
+    looper::LoopConfig,
     osc::{vmc_connector::VmcConfig, vrchat_connector::VrChatConfig},
+    serial::SerialConfig,
     skeleton::SkeletonConfig,
+    steamvr::steamvr_connector::SteamVrConfig,
     tracker::TrackerConfig,
+    udp::server::UdpConfig,
 };
 
 #[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, TS)]
@@ -21,8 +25,12 @@ pub struct GlobalConfig {
     pub trackers: HashMap<Arc<str>, TrackerConfig>,
     pub vmc: VmcConfig,
     pub vrchat: VrChatConfig,
+    pub steamvr: SteamVrConfig,
     pub skeleton: SkeletonConfig,
+    pub serial: SerialConfig,
+    pub udp: UdpConfig,
     pub interface: InterfaceConfig,
+    pub main_loop: LoopConfig,
 }
 
 impl GlobalConfig {
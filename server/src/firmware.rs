@@ -0,0 +1,188 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::config::get_config_dir;
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(tag = "type")]
+pub enum FirmwareUpdateStatus {
+    Uploading { progress: f32 },
+    Done { version: Box<str> },
+    Failed { error: Box<str> },
+}
+
+/// A firmware file in the firmware folder, named `<board>-<version>.bin`
+#[derive(Debug, Clone)]
+pub struct FirmwareFile {
+    pub board: Box<str>,
+    pub version: Box<str>,
+    pub path: PathBuf,
+}
+
+impl FirmwareFile {
+    pub fn from_path(path: PathBuf) -> Option<Self> {
+        if path.extension()? != "bin" {
+            return None;
+        }
+
+        let name = path.file_stem()?.to_str()?;
+        // Board names can have dashes in them so the version is after the last one
+        let (board, version) = name.rsplit_once('-')?;
+        Some(Self {
+            board: board.into(),
+            version: version.into(),
+            path,
+        })
+    }
+
+    pub fn is_newer_than(&self, version: &str) -> bool {
+        parse_version(&self.version) > parse_version(version)
+    }
+
+    pub fn load(&self) -> anyhow::Result<FirmwareImage> {
+        FirmwareImage::new(self.version.clone(), std::fs::read(&self.path)?)
+    }
+}
+
+/// Firmware files that were added while running are picked up after this long
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The firmware files found in the firmware folder, cached so that the folder isn't read for
+/// every device
+#[derive(Debug, Default)]
+pub struct FirmwareFiles {
+    files: Vec<FirmwareFile>,
+    last_scan_time: Option<Instant>,
+    scan_failed: bool,
+}
+
+impl FirmwareFiles {
+    pub fn rescan(&mut self) {
+        match scan_firmware_dir() {
+            Ok(files) => {
+                self.files = files;
+                self.scan_failed = false;
+            }
+            Err(err) => {
+                // Only warn once instead of on every scan while the folder stays unreadable
+                if !self.scan_failed {
+                    log::warn!("Failed to scan the firmware folder: {err}");
+                }
+                self.files.clear();
+                self.scan_failed = true;
+            }
+        }
+        self.last_scan_time = Some(Instant::now());
+    }
+
+    pub fn rescan_if_stale(&mut self) {
+        if self
+            .last_scan_time
+            .is_none_or(|time| time.elapsed() > RESCAN_INTERVAL)
+        {
+            self.rescan();
+        }
+    }
+
+    /// Finds the newest firmware that can be installed on the board
+    pub fn find_latest(&self, board: &str) -> Option<&FirmwareFile> {
+        self.files
+            .iter()
+            .filter(|file| &*file.board == board)
+            .max_by(|a, b| parse_version(&a.version).cmp(&parse_version(&b.version)))
+    }
+}
+
+pub struct FirmwareImage {
+    pub version: Box<str>,
+    pub data: Vec<u8>,
+    /// Used by the device to check that the whole image was received correctly
+    pub md5: [u8; 16],
+}
+
+impl FirmwareImage {
+    pub fn new(version: Box<str>, data: Vec<u8>) -> anyhow::Result<Self> {
+        if data.is_empty() {
+            anyhow::bail!("Firmware image {version} is empty");
+        }
+
+        Ok(Self {
+            version,
+            md5: md5::compute(&data).0,
+            data,
+        })
+    }
+
+    /// Loads an image from anywhere, the version is taken from the file name if it has one
+    pub fn load_file(path: &Path) -> anyhow::Result<Self> {
+        let version = FirmwareFile::from_path(path.to_path_buf())
+            .map(|file| file.version)
+            .unwrap_or_else(|| "unknown".into());
+        Self::new(version, std::fs::read(path)?)
+    }
+}
+
+/// Limits progress updates to every percent so the websocket doesn't get flooded
+#[derive(Default)]
+pub struct ProgressReporter {
+    last_percent: Option<u32>,
+}
+
+impl ProgressReporter {
+    /// Returns the progress if it changed by at least a percent since it was last returned
+    pub fn update(&mut self, progress: f32) -> Option<f32> {
+        let percent = (progress * 100.) as u32;
+        if self.last_percent == Some(percent) {
+            return None;
+        }
+
+        self.last_percent = Some(percent);
+        Some(progress)
+    }
+}
+
+/// Firmware images are hosted from the firmware folder in the config directory
+pub fn get_firmware_dir() -> anyhow::Result<PathBuf> {
+    let dir = get_config_dir()?.join("firmware");
+    if !dir.exists() {
+        std::fs::create_dir_all(&dir)?;
+    }
+    Ok(dir)
+}
+
+fn scan_firmware_dir() -> anyhow::Result<Vec<FirmwareFile>> {
+    let files = std::fs::read_dir(get_firmware_dir()?)?
+        .filter_map(|entry| FirmwareFile::from_path(entry.ok()?.path()))
+        .collect();
+    Ok(files)
+}
+
+/// Splits a version like 0.2.10 into [0, 2, 10] so that it can be compared
+fn parse_version(version: &str) -> Vec<u32> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn firmware_file_name() {
+        let file = FirmwareFile::from_path("firmware/esp32-c3-0.2.10.bin".into()).unwrap();
+        assert_eq!(&*file.board, "esp32-c3");
+        assert_eq!(&*file.version, "0.2.10");
+        assert!(file.is_newer_than("0.2.9"));
+        assert!(!file.is_newer_than("v0.2.10"));
+
+        assert!(FirmwareFile::from_path("firmware/esp8266-0.1.0.txt".into()).is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    input::InputSource,
+    main_server::MainServer,
+    tracker::{TrackerRef, TrackerSource, TrackerStatus},
+};
+
+struct BvhJoint {
+    name: Box<str>,
+    parent: Option<usize>,
+    /// Axes of the rotation channels in the order they are applied
+    rotation_axes: Vec<glam::Vec3>,
+    /// Index of the first channel of the joint in a frame
+    channel_offset: usize,
+}
+
+/// A motion recording in the format written by `BvhSaver`
+struct BvhMotion {
+    joints: Vec<BvhJoint>,
+    frames: Vec<Vec<f32>>,
+    frame_time: Duration,
+}
+
+impl BvhMotion {
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut joints: Vec<BvhJoint> = Vec::new();
+        let mut parents = Vec::new();
+        let mut channel_count = 0;
+        let mut in_end_site = false;
+        let mut lines = text.lines().map(str::trim);
+
+        for line in lines.by_ref() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("ROOT" | "JOINT") => {
+                    let name = words
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Joint without a name"))?;
+                    joints.push(BvhJoint {
+                        name: name.into(),
+                        parent: parents.last().copied(),
+                        rotation_axes: Vec::new(),
+                        channel_offset: channel_count,
+                    });
+                }
+                Some("End") => in_end_site = true,
+                Some("{") if !in_end_site => {
+                    let joint = joints
+                        .len()
+                        .checked_sub(1)
+                        .ok_or_else(|| anyhow::anyhow!("Brace outside of a joint"))?;
+                    parents.push(joint);
+                }
+                Some("}") if in_end_site => in_end_site = false,
+                Some("}") => {
+                    parents.pop();
+                }
+                Some("CHANNELS") => {
+                    let joint = joints
+                        .last_mut()
+                        .ok_or_else(|| anyhow::anyhow!("Channels outside of a joint"))?;
+                    // Skip the amount
+                    words.next();
+                    joint.channel_offset = channel_count;
+                    for channel in words {
+                        channel_count += 1;
+                        let axis = match channel.to_ascii_lowercase().as_str() {
+                            "xrotation" => glam::Vec3::X,
+                            "yrotation" => glam::Vec3::Y,
+                            "zrotation" => glam::Vec3::Z,
+                            // Positions are skipped, trackers only have an orientation
+                            _ => glam::Vec3::ZERO,
+                        };
+                        joint.rotation_axes.push(axis);
+                    }
+                }
+                Some("MOTION") => break,
+                _ => {}
+            }
+        }
+
+        let mut frame_time = None;
+        let mut frames = Vec::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            if line.starts_with("Frames:") {
+                continue;
+            }
+            if let Some(time) = line.strip_prefix("Frame Time:") {
+                frame_time = Some(Duration::from_secs_f32(time.trim().parse()?));
+                continue;
+            }
+
+            let frame = line
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<Vec<f32>, _>>()?;
+            if frame.len() != channel_count {
+                anyhow::bail!(
+                    "Frame has {} channels instead of {channel_count}",
+                    frame.len()
+                );
+            }
+            frames.push(frame);
+        }
+
+        if frames.is_empty() {
+            anyhow::bail!("Motion has no frames");
+        }
+
+        Ok(Self {
+            joints,
+            frames,
+            frame_time: frame_time
+                .filter(|time| !time.is_zero())
+                .ok_or_else(|| anyhow::anyhow!("Missing frame time"))?,
+        })
+    }
+
+    /// Returns the orientation of every joint relative to the root of the skeleton
+    fn orientations(&self, frame: &[f32]) -> Vec<glam::Quat> {
+        let mut orientations: Vec<glam::Quat> = Vec::with_capacity(self.joints.len());
+        for joint in &self.joints {
+            let channels = &frame[joint.channel_offset..];
+            let local = joint
+                .rotation_axes
+                .iter()
+                .zip(channels)
+                .filter(|(axis, _)| **axis != glam::Vec3::ZERO)
+                .fold(glam::Quat::IDENTITY, |orientation, (axis, degrees)| {
+                    orientation * glam::Quat::from_axis_angle(*axis, degrees.to_radians())
+                });
+
+            let parent = joint
+                .parent
+                .map_or(glam::Quat::IDENTITY, |i| orientations[i]);
+            orientations.push(parent * local);
+        }
+        orientations
+    }
+}
+
+/// Plays back a BVH recording with a tracker on every joint, on repeat
+/// Enabled by setting the environment variable MICAP_BVH_PLAYBACK to the path of the file
+pub struct BvhPlayback {
+    motion: BvhMotion,
+    trackers: Vec<TrackerRef>,
+    start_time: Instant,
+}
+
+impl BvhPlayback {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let motion = BvhMotion::parse(&std::fs::read_to_string(path)?)?;
+        Ok(Self {
+            motion,
+            trackers: Vec::new(),
+            start_time: Instant::now(),
+        })
+    }
+
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("MICAP_BVH_PLAYBACK")?;
+        match Self::load(Path::new(&path)) {
+            Ok(playback) => {
+                log::info!("Playing back {path:?}");
+                Some(playback)
+            }
+            Err(err) => {
+                log::error!("Failed to load {path:?} for playback: {err}");
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl InputSource for BvhPlayback {
+    async fn update(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+        if self.trackers.is_empty() {
+            for joint in &self.motion.joints {
+                let id: Arc<str> = format!("bvh/{}", joint.name).into();
+                if let Some(tracker) = main.add_tracker(&id, TrackerSource::Bvh) {
+                    tracker.lock().unwrap().update_info().status = TrackerStatus::Ok;
+                    self.trackers.push(tracker);
+                }
+            }
+        }
+
+        let elapsed = self.start_time.elapsed().as_secs_f32();
+        let frame_index = (elapsed / self.motion.frame_time.as_secs_f32()) as usize;
+        let frame = &self.motion.frames[frame_index % self.motion.frames.len()];
+        let orientations = self.motion.orientations(frame);
+        for (tracker, orientation) in self.trackers.iter().zip(orientations) {
+            tracker
+                .lock()
+                .unwrap()
+                .update_data(glam::Vec3A::ZERO, orientation);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn play_saved_recording() -> anyhow::Result<()> {
+        let mut playback = BvhPlayback::load(Path::new("data/test.bvh"))?;
+        assert_eq!(playback.motion.frames.len(), 1);
+
+        let mut main = MainServer::default();
+        playback.update(&mut main).await?;
+        assert_eq!(main.trackers.len(), playback.motion.joints.len());
+
+        // Only the hip is rotated in the recording
+        let tracker = main.trackers["bvh/RightHip"].lock().unwrap();
+        assert_eq!(tracker.info().source, Some(TrackerSource::Bvh));
+        let expected = glam::Quat::from_euler(glam::EulerRot::ZXY, 0.5, 0.8, 0.8);
+        assert!(tracker.data().orientation.angle_between(expected) < 0.01);
+        Ok(())
+    }
+}
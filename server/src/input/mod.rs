@@ -0,0 +1,26 @@
+//! Sources of tracker data that the main loop updates through `ServerModules::input_sources`
+//!
+//! The UDP server and the serial ports implement `InputSource` too, but are deliberately left out
+//! of the list and kept as their own fields in `ServerModules`, since the websocket server and
+//! the main loop need to reach them directly for pairing, commands, firmware updates and waking
+//! the loop.
+
+use async_trait::async_trait;
+
+use crate::{config::GlobalConfig, main_server::MainServer};
+
+pub mod bvh_playback;
+pub mod synthetic;
+
+/// Something that creates trackers and feeds data into them
+/// Trackers should be registered through MainServer::add_tracker with the source's TrackerSource
+#[async_trait]
+pub trait InputSource: Send {
+    /// Called whenever the config gets changed
+    async fn apply_config(&mut self, _config: &GlobalConfig) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called every update to receive new tracker data
+    async fn update(&mut self, main: &mut MainServer) -> anyhow::Result<()>;
+}
@@ -0,0 +1,64 @@
+use std::{sync::Arc, time::Instant};
+
+use async_trait::async_trait;
+
+use crate::{
+    input::InputSource,
+    main_server::MainServer,
+    tracker::{TrackerRef, TrackerSource, TrackerStatus},
+};
+
+/// Creates trackers that move around by themselves for testing without any hardware
+/// Enabled by setting the environment variable MICAP_SYNTHETIC_TRACKERS to the amount of trackers
+pub struct SyntheticSource {
+    trackers: Vec<TrackerRef>,
+    count: u8,
+    start_time: Instant,
+}
+
+impl SyntheticSource {
+    pub fn new(count: u8) -> Self {
+        Self {
+            trackers: Vec::new(),
+            count,
+            start_time: Instant::now(),
+        }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        let count = std::env::var("MICAP_SYNTHETIC_TRACKERS")
+            .ok()
+            .and_then(|var| var.parse().ok())
+            .filter(|count| *count > 0)?;
+        log::info!("Using {count} synthetic trackers");
+        Some(Self::new(count))
+    }
+}
+
+#[async_trait]
+impl InputSource for SyntheticSource {
+    async fn update(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+        if self.trackers.is_empty() {
+            for i in 0..self.count {
+                let id: Arc<str> = format!("synthetic/{i}").into();
+                if let Some(tracker) = main.add_tracker(&id, TrackerSource::Synthetic) {
+                    tracker.lock().unwrap().update_info().status = TrackerStatus::Ok;
+                    self.trackers.push(tracker);
+                }
+            }
+        }
+
+        let time = self.start_time.elapsed().as_secs_f32();
+        for (i, tracker) in self.trackers.iter().enumerate() {
+            // Offset each tracker a bit so they don't all move the same
+            let phase = time + i as f32;
+            let orientation = glam::Quat::from_axis_angle(glam::Vec3::Z, f32::sin(phase) * 2.);
+            tracker
+                .lock()
+                .unwrap()
+                .update_data(glam::Vec3A::ZERO, orientation);
+        }
+
+        Ok(())
+    }
+}
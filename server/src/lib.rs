@@ -1,11 +1,15 @@
 pub mod config;
+mod firmware;
+mod input;
 mod looper;
 mod main_server;
 mod math;
 mod osc;
+mod output;
 mod record;
 mod serial;
 mod skeleton;
+pub mod steamvr;
 pub mod tracker;
 pub mod udp;
 pub mod websocket;
@@ -30,28 +34,26 @@ pub fn setup_log() {
 
 pub async fn start_server() -> anyhow::Result<()> {
     // Seperate out  main and modules to prevent multiple borrow
-    let mut main = MainServer::default();
-    let mut modules = ServerModules::new().await?;
-
-    main.config = GlobalConfig::load()
-        .inspect_err(|err| log::warn!("Failed to load config: {err}"))
-        .unwrap_or_default();
-
+    let mut main = MainServer {
+        config: GlobalConfig::load()
+            .inspect_err(|err| log::warn!("Failed to load config: {err}"))
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+
+    // The udp server is bound with the loaded config right away
+    let mut modules = ServerModules::new(&main.config).await?;
     main.apply_config(&mut modules).await?;
 
     let mut looper = Looper::default();
+    looper.wake_on(modules.udp_server.packet_received());
 
     loop {
         looper.loop_start();
+        main.updates.loop_metrics = looper.take_metrics();
 
-        let result = main.update(&mut modules).await;
-        main.updates = Default::default();
-
-        if let Err(err) = result {
-            log::error!("{err:?}");
-            main.updates.error = Some(err.to_string().into());
-        }
-
+        main.tick(&mut modules).await;
+        looper.set_config(&main.config.main_loop);
         looper.loop_end_wait().await;
     }
 }
@@ -1,10 +1,66 @@
-use std::time::{Duration, Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use ts_rs::TS;
+
+const MIN_TICK_RATE: u32 = 30;
+const MAX_TICK_RATE: u32 = 240;
+/// How often the loop metrics are calculated
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(default)]
+pub struct LoopConfig {
+    /// Updates per second, between 30 and 240
+    pub tick_rate: u32,
+    /// Update as soon as new tracker data arrives instead of waiting for the next tick,
+    /// the tick rate is still used when nothing arrives
+    pub event_driven: bool,
+}
+
+impl Default for LoopConfig {
+    fn default() -> Self {
+        Self {
+            tick_rate: 60,
+            event_driven: false,
+        }
+    }
+}
+
+impl LoopConfig {
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.tick_rate.clamp(MIN_TICK_RATE, MAX_TICK_RATE)
+    }
+}
+
+/// Timing of the main loop over the last second
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+pub struct LoopMetrics {
+    /// Updates that actually happened per second
+    pub tick_rate: f32,
+    pub avg_update_ms: f32,
+    pub max_update_ms: f32,
+    /// Updates that took longer than the tick interval
+    pub overruns: u32,
+}
 
 pub struct Looper {
     update_start_time: Instant,
     loop_count: u32,
     delta_total: Duration,
     print_loop_time_rate: u32,
+    config: LoopConfig,
+    /// Notified when new data arrives in event driven mode
+    wake: Option<Arc<Notify>>,
+    metrics_start_time: Instant,
+    metrics_loop_count: u32,
+    metrics_delta_total: Duration,
+    metrics_delta_max: Duration,
+    metrics_overruns: u32,
 }
 
 impl Default for Looper {
@@ -17,24 +73,65 @@ impl Default for Looper {
                 .and_then(|var| var.parse().ok())
                 .unwrap_or(0),
             loop_count: 0,
+            config: LoopConfig::default(),
+            wake: None,
+            metrics_start_time: Instant::now(),
+            metrics_loop_count: 0,
+            metrics_delta_total: Duration::ZERO,
+            metrics_delta_max: Duration::ZERO,
+            metrics_overruns: 0,
         }
     }
 }
 
 impl Looper {
-    pub const TARGET_LOOP_DELTA: Duration = Duration::from_millis(1000 / 60);
+    /// Never update faster than the max tick rate even when data keeps arriving
+    const MIN_EVENT_DELTA: Duration = Duration::from_nanos(1_000_000_000 / MAX_TICK_RATE as u64);
+
+    pub fn set_config(&mut self, config: &LoopConfig) {
+        self.config = config.clone();
+    }
+
+    /// Wakes the loop early when notified in event driven mode
+    pub fn wake_on(&mut self, notify: Arc<Notify>) {
+        self.wake = Some(notify);
+    }
 
     pub fn loop_start(&mut self) {
         self.update_start_time = Instant::now();
     }
 
+    /// Returns the metrics once every second
+    pub fn take_metrics(&mut self) -> Option<LoopMetrics> {
+        let elapsed = self.metrics_start_time.elapsed();
+        if elapsed < METRICS_INTERVAL || self.metrics_loop_count == 0 {
+            return None;
+        }
+
+        let metrics = LoopMetrics {
+            tick_rate: self.metrics_loop_count as f32 / elapsed.as_secs_f32(),
+            avg_update_ms: self.metrics_delta_total.as_secs_f32() * 1000.
+                / self.metrics_loop_count as f32,
+            max_update_ms: self.metrics_delta_max.as_secs_f32() * 1000.,
+            overruns: self.metrics_overruns,
+        };
+
+        self.metrics_start_time = Instant::now();
+        self.metrics_loop_count = 0;
+        self.metrics_delta_total = Duration::ZERO;
+        self.metrics_delta_max = Duration::ZERO;
+        self.metrics_overruns = 0;
+        Some(metrics)
+    }
+
     pub async fn loop_end_wait(&mut self) {
         let loop_delta = self.update_start_time.elapsed();
+        let target_delta = self.config.tick_interval();
 
         if self.print_loop_time_rate > 0 {
             self.delta_total += loop_delta;
             self.loop_count += 1;
-            if self.loop_count % self.print_loop_time_rate == 0 {
+            if self.loop_count.is_multiple_of(self.print_loop_time_rate) {
                 log::info!(
                     "Loop time: {:?}",
                     self.delta_total / self.print_loop_time_rate
@@ -43,14 +140,59 @@ impl Looper {
             }
         }
 
-        if let Some(sleep_duration) = Self::TARGET_LOOP_DELTA.checked_sub(loop_delta) {
-            tokio::time::sleep(sleep_duration).await;
-        } else {
+        self.metrics_loop_count += 1;
+        self.metrics_delta_total += loop_delta;
+        self.metrics_delta_max = self.metrics_delta_max.max(loop_delta);
+
+        let Some(sleep_duration) = target_delta.checked_sub(loop_delta) else {
+            self.metrics_overruns += 1;
             log::warn!(
                 "Main server loop took {:?} which is longer than target {:?}",
                 loop_delta,
-                Self::TARGET_LOOP_DELTA
+                target_delta
             );
+            return;
+        };
+
+        match &self.wake {
+            Some(wake) if self.config.event_driven => {
+                let min_sleep = Self::MIN_EVENT_DELTA.saturating_sub(loop_delta);
+                tokio::time::sleep(min_sleep).await;
+
+                let remaining = sleep_duration.saturating_sub(min_sleep);
+                let _ = tokio::time::timeout(remaining, wake.notified()).await;
+            }
+            _ => tokio::time::sleep(sleep_duration).await,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn event_driven_wake() {
+        let notify = Arc::new(Notify::new());
+        let mut looper = Looper::default();
+        looper.set_config(&LoopConfig {
+            tick_rate: MIN_TICK_RATE,
+            event_driven: true,
+        });
+        looper.wake_on(notify.clone());
+
+        // Nothing arrived so it waits for the whole tick
+        looper.loop_start();
+        let start = Instant::now();
+        looper.loop_end_wait().await;
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        // Data that arrived during the update wakes the loop right away
+        looper.loop_start();
+        notify.notify_one();
+        let start = Instant::now();
+        looper.loop_end_wait().await;
+        assert!(start.elapsed() >= Looper::MIN_EVENT_DELTA);
+        assert!(start.elapsed() < Duration::from_millis(30));
+    }
+}
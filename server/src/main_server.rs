@@ -4,36 +4,57 @@ use anyhow::Context;
 
 use crate::{
     config::GlobalConfig,
-    osc::{vmc_connector::VmcConnector, vrchat_connector::VrChatConnector},
+    firmware::FirmwareUpdateStatus,
+    input::{bvh_playback::BvhPlayback, synthetic::SyntheticSource, InputSource},
+    looper::LoopMetrics,
+    osc::vmc_receiver::VmcReceiver,
+    output::OutputRegistry,
     record::MotionRecorder,
+    serial::SerialPortManager,
     skeleton::SkeletonManager,
     tracker::*,
-    udp::server::{UdpServer, UDP_PORT},
+    udp::{
+        auth::PairingKey,
+        command::{CommandResult, DeviceCommand},
+        diagnostics::DeviceLog,
+        link::LinkQuality,
+        pending::PendingDevice,
+        server::UdpServer,
+    },
     websocket::{WebsocketServer, WEBSOCKET_PORT},
 };
 
 pub struct ServerModules {
     pub udp_server: UdpServer,
-    pub vmc_connector: VmcConnector,
-    pub vrchat_connector: VrChatConnector,
+    pub serial_manager: SerialPortManager,
+    /// Input sources other than the UDP server and the serial ports
+    pub input_sources: Vec<Box<dyn InputSource>>,
     pub websocket_server: WebsocketServer,
 }
 
 impl ServerModules {
-    pub async fn new() -> anyhow::Result<Self> {
+    pub async fn new(config: &GlobalConfig) -> anyhow::Result<Self> {
         fn get_context(server: &str, port: u16) -> String {
             format!("Failed to start {server} server!\nNote: Port {port} needs to be open, check if another instance is already runnning")
         }
 
+        let mut input_sources: Vec<Box<dyn InputSource>> = vec![Box::new(VmcReceiver::default())];
+        if let Some(source) = SyntheticSource::from_env() {
+            input_sources.push(Box::new(source));
+        }
+        if let Some(source) = BvhPlayback::from_env() {
+            input_sources.push(Box::new(source));
+        }
+
         Ok(Self {
+            input_sources,
+            serial_manager: SerialPortManager::default(),
             websocket_server: WebsocketServer::new()
                 .await
                 .with_context(|| get_context("Websocket", WEBSOCKET_PORT))?,
-            udp_server: UdpServer::new()
+            udp_server: UdpServer::new(&config.udp)
                 .await
-                .with_context(|| get_context("UDP", UDP_PORT))?,
-            vmc_connector: VmcConnector::new().await?,
-            vrchat_connector: VrChatConnector::new().await?,
+                .with_context(|| get_context("UDP", config.udp.port))?,
         })
     }
 }
@@ -42,6 +63,34 @@ impl ServerModules {
 pub struct ServerUpdates {
     pub error: Option<Box<str>>,
     pub config: Option<GlobalConfig>,
+    /// Latest firmware update status of each device that hasn't been sent to the websocket yet
+    pub firmware_update_statuses: HashMap<Arc<str>, FirmwareUpdateStatus>,
+    /// Logs from udp devices that haven't been sent to the websocket yet
+    pub device_logs: Vec<DeviceLog>,
+    /// Results of the tracker commands that haven't been sent to the websocket yet
+    pub command_results: Vec<CommandResult>,
+    /// Latest link quality of each udp device that hasn't been sent to the websocket yet
+    pub link_qualities: HashMap<Arc<str>, LinkQuality>,
+    /// Devices waiting for approval if they have changed since being sent to the websocket
+    pub pending_devices: Option<Vec<PendingDevice>>,
+    /// Timing of the main loop, set once every second
+    pub loop_metrics: Option<LoopMetrics>,
+}
+
+/// Requests for the modules that are kept until they have been handled, unlike `ServerUpdates`
+/// which only last until the end of the update they were made in
+#[derive(Default)]
+pub struct ServerRequests {
+    /// Mac addresses of the udp devices that should start a firmware update
+    pub firmware_updates: Vec<Arc<str>>,
+    /// Commands that should be sent to the udp device of the tracker with the id
+    pub tracker_commands: Vec<(Arc<str>, DeviceCommand)>,
+    /// Mac addresses and keys of devices that were just paired over serial
+    pub new_pairings: Vec<(Arc<str>, PairingKey)>,
+    /// Mac addresses of the devices that should be unpaired
+    pub unpair_requests: Vec<Arc<str>>,
+    /// Mac addresses of pending devices and if the user has approved or rejected them
+    pub device_approvals: Vec<(Arc<str>, bool)>,
 }
 
 #[derive(Default)]
@@ -50,14 +99,34 @@ pub struct MainServer {
     pub trackers: HashMap<Arc<str>, TrackerRef>,
     pub skeleton_manager: SkeletonManager,
     pub motion_recorder: MotionRecorder,
+    pub outputs: OutputRegistry,
     pub config: GlobalConfig,
     pub updates: ServerUpdates,
+    pub requests: ServerRequests,
 }
 
 impl MainServer {
+    /// Runs one iteration of the main loop, the updates are cleared afterwards
+    pub async fn tick(&mut self, modules: &mut ServerModules) {
+        let result = self.update(modules).await;
+        self.updates = Default::default();
+
+        if let Err(err) = result {
+            log::error!("{err:?}");
+            self.updates.error = Some(err.to_string().into());
+        }
+    }
+
     pub async fn update(&mut self, modules: &mut ServerModules) -> anyhow::Result<()> {
         modules.udp_server.update(self).await?;
-        modules.websocket_server.update(self).await?;
+        modules.serial_manager.update(self).await?;
+        for source in &mut modules.input_sources {
+            source.update(self).await?;
+        }
+        modules
+            .websocket_server
+            .update(self, &mut modules.serial_manager)
+            .await?;
 
         if let Some(config) = self.updates.config.take() {
             self.config = config;
@@ -65,8 +134,13 @@ impl MainServer {
             self.config.save()?;
         }
 
-        self.skeleton_manager.update();
-        modules.vmc_connector.update(self).await?;
+        // Event driven updates only solve the skeleton once new tracker data has arrived
+        if !self.config.main_loop.event_driven || self.any_tracker_updated() {
+            self.skeleton_manager.update();
+        }
+        self.outputs
+            .update(&self.skeleton_manager, &self.config)
+            .await?;
 
         self.motion_recorder.update(&self.skeleton_manager);
 
@@ -82,6 +156,12 @@ impl MainServer {
         Ok(())
     }
 
+    fn any_tracker_updated(&self) -> bool {
+        self.trackers
+            .values()
+            .any(|tracker| tracker.lock().unwrap().internal.was_updated)
+    }
+
     // Returns a tracker id if that tracker should be removed
     async fn upkeep_trackers(&mut self) -> Option<Arc<str>> {
         for (id, tracker) in &self.trackers {
@@ -117,19 +197,27 @@ impl MainServer {
             .apply_tracker_config(&config.trackers, &self.trackers);
         self.skeleton_manager
             .apply_skeleton_config(&config.skeleton);
-        modules.vrchat_connector.apply_config(&config.vrchat).await;
-        modules.vmc_connector.apply_config(&config.vmc).await;
+        // The other modules still get the config when an output fails
+        let outputs_result = self.outputs.apply_config(config).await;
+        modules.udp_server.apply_config(config).await?;
+        modules.serial_manager.apply_config(config).await?;
+        for source in &mut modules.input_sources {
+            source.apply_config(config).await?;
+        }
         modules.websocket_server.send_config(config).await?;
-        Ok(())
+        outputs_result
     }
 
-    pub fn add_tracker(&mut self, id: &Arc<str>) -> Option<TrackerRef> {
+    /// Should be used by every input source to register its trackers
+    pub fn add_tracker(&mut self, id: &Arc<str>, source: TrackerSource) -> Option<TrackerRef> {
         if !self.trackers.contains_key(id) {
             let tracker = TrackerRef::default();
             // Note: we only set the config once the user does
             self.trackers.insert(id.clone(), tracker.clone());
         }
 
-        self.trackers.get(id).cloned()
+        let tracker = self.trackers.get(id)?;
+        tracker.lock().unwrap().update_info().source = Some(source);
+        Some(tracker.clone())
     }
 }
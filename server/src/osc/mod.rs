@@ -1,33 +1,188 @@
-use std::{net::Ipv4Addr, time::SystemTime};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::Ipv4Addr,
+    time::{Duration, Instant, SystemTime},
+};
 use tokio::net::UdpSocket;
+use ts_rs::TS;
 
 pub mod vmc_connector;
+pub mod vmc_receiver;
 pub mod vrchat_connector;
 
+/// Bundles are still sent this often when only sending changes so receivers don't time out
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often an output sends its bundles, independent of the rate the server updates at
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(default)]
+pub struct OscSendRate {
+    /// Bundles sent per second, 0 sends one every update. At most one bundle is sent per update,
+    /// so rates above the tick rate of the main loop send one every update too
+    pub per_second: u32,
+    /// Skip bundles that are the same as the last one sent
+    pub only_on_change: bool,
+}
+
+impl Default for OscSendRate {
+    fn default() -> Self {
+        Self {
+            per_second: 60,
+            only_on_change: false,
+        }
+    }
+}
+
+impl OscSendRate {
+    fn interval(&self) -> Option<Duration> {
+        (self.per_second > 0).then(|| Duration::from_secs(1) / self.per_second)
+    }
+}
+
+/// Keeps track of when and what was sent last
+#[derive(Default)]
+struct SendLimiter {
+    next_send_time: Option<Instant>,
+    last_sent: Vec<rosc::OscPacket>,
+    last_sent_time: Option<Instant>,
+}
+
+impl SendLimiter {
+    fn is_due(&self, rate: &OscSendRate, now: Instant) -> bool {
+        match (self.next_send_time, rate.interval()) {
+            // Allow sending a bit early so updates that are just as fast as the rate aren't skipped
+            (Some(next), Some(interval)) => now + interval / 4 >= next,
+            _ => true,
+        }
+    }
+
+    fn should_send(&self, rate: &OscSendRate, packets: &[rosc::OscPacket], now: Instant) -> bool {
+        if !self.is_due(rate, now) {
+            return false;
+        }
+
+        let keepalive_due = self
+            .last_sent_time
+            .is_none_or(|time| now.duration_since(time) >= KEEPALIVE_INTERVAL);
+        !rate.only_on_change || keepalive_due || self.last_sent != packets
+    }
+
+    fn sent(&mut self, rate: &OscSendRate, packets: Vec<rosc::OscPacket>, now: Instant) {
+        self.next_send_time = rate.interval().map(|interval| {
+            let next = self.next_send_time.unwrap_or(now) + interval;
+            // Don't try to catch up after falling behind
+            if next < now {
+                now + interval
+            } else {
+                next
+            }
+        });
+        self.last_sent = packets;
+        self.last_sent_time = Some(now);
+    }
+}
+
+/// Only has a socket when connected
+#[derive(Default)]
 pub struct OscConnector {
-    socket: UdpSocket,
+    socket: Option<UdpSocket>,
+    limiter: SendLimiter,
 }
 
 impl OscConnector {
-    pub async fn new() -> anyhow::Result<Self> {
-        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
-        Ok(Self { socket })
+    /// Used to skip making the bundle when it wouldn't be sent anyway
+    pub fn is_send_due(&self, rate: &OscSendRate) -> bool {
+        self.socket.is_some() && self.limiter.is_due(rate, Instant::now())
     }
 
     pub async fn send_bundle(
         &mut self,
         messages: impl Iterator<Item = rosc::OscPacket>,
+        rate: &OscSendRate,
     ) -> anyhow::Result<()> {
+        let Some(socket) = self.socket.as_ref() else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let content: Vec<_> = messages.collect();
+        if !self.limiter.should_send(rate, &content, now) {
+            return Ok(());
+        }
+
         let msg_buf = rosc::encoder::encode(&rosc::OscPacket::Bundle(rosc::OscBundle {
             timetag: SystemTime::now().try_into()?,
-            content: messages.collect(),
+            content: content.clone(),
         }))?;
-        self.socket.send(&msg_buf).await?;
+        socket.send(&msg_buf).await?;
+        self.limiter.sent(rate, content, now);
         Ok(())
     }
 
-    pub async fn connect(&mut self, port: u16) {
-        let _ = self.socket.connect((Ipv4Addr::LOCALHOST, port)).await;
-        log::info!("Sending OSC packets to {:?}", self.socket.peer_addr());
+    pub async fn connect(&mut self, port: u16) -> anyhow::Result<()> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        socket.connect((Ipv4Addr::LOCALHOST, port)).await?;
+        log::info!("Sending OSC packets to {:?}", socket.peer_addr());
+        self.socket = Some(socket);
+        // The new receiver gets the first bundle right away even when nothing changed
+        self.limiter = SendLimiter::default();
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) {
+        self.socket.take();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(value: f32) -> Vec<rosc::OscPacket> {
+        vec![rosc::OscPacket::Message(rosc::OscMessage {
+            addr: "/test".to_string(),
+            args: vec![rosc::OscType::Float(value)],
+        })]
+    }
+
+    #[test]
+    fn send_rate() {
+        let rate = OscSendRate {
+            per_second: 50,
+            only_on_change: false,
+        };
+        let mut limiter = SendLimiter::default();
+        let start = Instant::now();
+
+        // Updating at 100Hz only sends every other update
+        let sent = (0..100)
+            .map(|i| start + Duration::from_millis(i * 10))
+            .filter(|&now| {
+                let send = limiter.should_send(&rate, &message(0.), now);
+                if send {
+                    limiter.sent(&rate, message(0.), now);
+                }
+                send
+            })
+            .count();
+        assert_eq!(sent, 50);
+    }
+
+    #[test]
+    fn only_on_change() {
+        let rate = OscSendRate {
+            per_second: 0,
+            only_on_change: true,
+        };
+        let mut limiter = SendLimiter::default();
+        let now = Instant::now();
+
+        assert!(limiter.should_send(&rate, &message(0.), now));
+        limiter.sent(&rate, message(0.), now);
+        assert!(!limiter.should_send(&rate, &message(0.), now));
+        assert!(limiter.should_send(&rate, &message(1.), now));
+
+        // Sent again after a while even without changes
+        assert!(limiter.should_send(&rate, &message(0.), now + KEEPALIVE_INTERVAL));
     }
 }
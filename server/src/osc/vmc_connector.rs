@@ -1,14 +1,22 @@
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-use crate::{main_server::MainServer, osc::OscConnector};
+use crate::{
+    config::GlobalConfig,
+    osc::{OscConnector, OscSendRate},
+    output::OutputConnector,
+    skeleton::SkeletonManager,
+};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
 #[serde(default)]
 pub struct VmcConfig {
     pub enabled: bool,
     pub send_port: u16,
+    pub receive_enabled: bool,
     pub receive_port: u16,
+    pub send_rate: OscSendRate,
 }
 
 impl Default for VmcConfig {
@@ -16,28 +24,42 @@ impl Default for VmcConfig {
         Self {
             enabled: false,
             send_port: 39539,
+            receive_enabled: false,
             receive_port: 39540,
+            send_rate: OscSendRate::default(),
         }
     }
 }
 
+#[derive(Default)]
 pub struct VmcConnector {
     osc: OscConnector,
 }
 
-impl VmcConnector {
-    pub async fn new() -> anyhow::Result<Self> {
-        Ok(VmcConnector {
-            osc: OscConnector::new().await?,
-        })
+#[async_trait]
+impl OutputConnector for VmcConnector {
+    type Config = VmcConfig;
+
+    const NAME: &'static str = "VMC";
+
+    fn get_config(config: &GlobalConfig) -> &Self::Config {
+        &config.vmc
     }
 
-    pub async fn update(&mut self, main: &MainServer) -> anyhow::Result<()> {
-        if !main.config.vmc.enabled {
+    fn is_enabled(config: &Self::Config) -> bool {
+        config.enabled
+    }
+
+    async fn update(
+        &mut self,
+        skeleton: &SkeletonManager,
+        config: &Self::Config,
+    ) -> anyhow::Result<()> {
+        if !self.osc.is_send_due(&config.send_rate) {
             return Ok(());
         }
 
-        let bones = &main.skeleton_manager.bones;
+        let bones = &skeleton.bones;
 
         let osc_messages = std::iter::once(rosc::OscPacket::Message(rosc::OscMessage {
             addr: "/VMC/Ext/OK".to_string(),
@@ -57,14 +79,20 @@ impl VmcConnector {
             }))
         }));
 
-        self.osc.send_bundle(osc_messages).await.ok();
+        self.osc
+            .send_bundle(osc_messages, &config.send_rate)
+            .await
+            .ok();
         Ok(())
     }
 
-    pub async fn apply_config(&mut self, config: &VmcConfig) {
+    async fn apply_config(&mut self, config: &Self::Config) -> anyhow::Result<()> {
         if config.enabled {
-            self.osc.connect(config.send_port).await;
+            self.osc.connect(config.send_port).await?;
+        } else {
+            self.osc.disconnect();
         }
+        Ok(())
     }
 }
 
@@ -0,0 +1,144 @@
+use async_trait::async_trait;
+use futures_util::FutureExt;
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::net::UdpSocket;
+
+use crate::{
+    config::GlobalConfig,
+    input::InputSource,
+    main_server::MainServer,
+    tracker::{TrackerRef, TrackerSource, TrackerStatus},
+};
+
+struct VmcTracker {
+    tracker: TrackerRef,
+    last_received_time: Instant,
+}
+
+/// Receives virtual trackers from another VMC application
+#[derive(Default)]
+pub struct VmcReceiver {
+    /// Only exists when enabled
+    socket: Option<UdpSocket>,
+    // Maps the VMC tracker serial to the tracker
+    trackers: HashMap<Arc<str>, VmcTracker>,
+}
+
+impl VmcReceiver {
+    const TIMEOUT: Duration = Duration::from_millis(2000);
+
+    fn handle_packet(&mut self, packet: rosc::OscPacket, main: &mut MainServer) {
+        match packet {
+            rosc::OscPacket::Bundle(bundle) => {
+                for packet in bundle.content {
+                    self.handle_packet(packet, main);
+                }
+            }
+            rosc::OscPacket::Message(message) => {
+                if message.addr == "/VMC/Ext/Tra/Pos" {
+                    self.handle_tracker_message(message.args, main);
+                }
+            }
+        }
+    }
+
+    // Format is (string){serial} (float){p.x} (float){p.y} (float){p.z} (float){q.x} (float){q.y} (float){q.z} (float){q.w}
+    fn handle_tracker_message(&mut self, args: Vec<rosc::OscType>, main: &mut MainServer) {
+        let mut args = args.into_iter();
+        let Some(rosc::OscType::String(serial)) = args.next() else {
+            return;
+        };
+
+        let mut values = [0_f32; 7];
+        for value in &mut values {
+            match args.next() {
+                Some(rosc::OscType::Float(float)) => *value = float,
+                _ => return,
+            }
+        }
+
+        let serial: Arc<str> = serial.into();
+        if !self.trackers.contains_key(&serial) {
+            let id: Arc<str> = format!("vmc/{serial}").into();
+            let Some(tracker) = main.add_tracker(&id, TrackerSource::Vmc) else {
+                return;
+            };
+
+            tracker.lock().unwrap().update_info().status = TrackerStatus::Ok;
+            log::info!("New VMC tracker {serial}");
+            self.trackers.insert(
+                serial.clone(),
+                VmcTracker {
+                    tracker,
+                    last_received_time: Instant::now(),
+                },
+            );
+        }
+
+        let vmc_tracker = self.trackers.get_mut(&serial).unwrap();
+        vmc_tracker.last_received_time = Instant::now();
+
+        // Flip back the same way as the VMC sender does
+        let [_, _, _, x, y, z, w] = values;
+        let orientation = glam::Quat::from_xyzw(x, y, -z, -w);
+        vmc_tracker
+            .tracker
+            .lock()
+            .unwrap()
+            .update_data(glam::Vec3A::ZERO, orientation);
+    }
+}
+
+#[async_trait]
+impl InputSource for VmcReceiver {
+    async fn apply_config(&mut self, config: &GlobalConfig) -> anyhow::Result<()> {
+        let config = &config.vmc;
+        if !config.receive_enabled {
+            self.socket.take();
+            return Ok(());
+        }
+
+        let port = self
+            .socket
+            .as_ref()
+            .and_then(|socket| socket.local_addr().ok())
+            .map(|address| address.port());
+        if port != Some(config.receive_port) {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.receive_port)).await?;
+            log::info!("Receiving VMC trackers on {}", socket.local_addr()?);
+            self.socket = Some(socket);
+        }
+
+        Ok(())
+    }
+
+    async fn update(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+        for vmc_tracker in self.trackers.values() {
+            let timed_out = vmc_tracker.last_received_time.elapsed() > Self::TIMEOUT;
+            vmc_tracker.tracker.lock().unwrap().set_timed_out(timed_out);
+        }
+
+        let mut buffer = [0; rosc::decoder::MTU];
+        loop {
+            let Some(socket) = self.socket.as_ref() else {
+                return Ok(());
+            };
+
+            // Try and get all the packets that were received
+            match socket.recv(&mut buffer).now_or_never() {
+                Some(Ok(amount)) => match rosc::decoder::decode_udp(&buffer[0..amount]) {
+                    Ok((_, packet)) => self.handle_packet(packet, main),
+                    Err(err) => log::trace!("Received invalid VMC packet: {err:?}"),
+                },
+                // No new data currently
+                None => return Ok(()),
+                Some(Err(e)) => return Err(e)?,
+            }
+        }
+    }
+}
@@ -1,9 +1,13 @@
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::{
-    main_server::MainServer, math::to_euler_angles, osc::OscConnector,
-    skeleton::BoneLocation,
+    config::GlobalConfig,
+    math::to_euler_angles,
+    osc::{OscConnector, OscSendRate},
+    output::OutputConnector,
+    skeleton::{BoneLocation, SkeletonManager},
 };
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
@@ -12,6 +16,7 @@ pub struct VrChatConfig {
     enabled: bool,
     send_port: u16,
     bones_to_send: Vec<BoneLocation>,
+    send_rate: OscSendRate,
 }
 
 impl Default for VrChatConfig {
@@ -28,48 +33,69 @@ impl Default for VrChatConfig {
                 LeftUpperArm,
                 RightUpperArm,
             ],
+            send_rate: OscSendRate::default(),
         }
     }
 }
 
+#[derive(Default)]
 pub struct VrChatConnector {
     osc: OscConnector,
 }
 
-impl VrChatConnector {
-    pub async fn new() -> anyhow::Result<Self> {
-        Ok(Self {
-            osc: OscConnector::new().await?,
-        })
+#[async_trait]
+impl OutputConnector for VrChatConnector {
+    type Config = VrChatConfig;
+
+    const NAME: &'static str = "VRChat";
+
+    fn get_config(config: &GlobalConfig) -> &Self::Config {
+        &config.vrchat
+    }
+
+    fn is_enabled(config: &Self::Config) -> bool {
+        config.enabled
     }
 
-    pub async fn update(&mut self, main: &MainServer) -> anyhow::Result<()> {
-        if !main.config.vrchat.enabled {
+    async fn update(
+        &mut self,
+        skeleton: &SkeletonManager,
+        config: &Self::Config,
+    ) -> anyhow::Result<()> {
+        if !self.osc.is_send_due(&config.send_rate) {
             return Ok(());
         }
 
-        let bones = &main.skeleton_manager.bones;
-
-        let bones_to_send = &main.config.vrchat.bones_to_send;
+        let bones = &skeleton.bones;
 
-        let osc_messages = bones_to_send.iter().enumerate().flat_map(|(i, location)| {
-            let bone = &bones[location];
-            let position = bone.tail_world_position;
-            let rotation = to_euler_angles(bone.world_orientation, glam::EulerRot::ZXY);
-            [
-                make_pos_message(format!("/tracking/trackers/{i}/position"), position),
-                make_pos_message(format!("/tracking/trackers/{i}/rotation"), rotation),
-            ]
-        });
+        let osc_messages = config
+            .bones_to_send
+            .iter()
+            .enumerate()
+            .flat_map(|(i, location)| {
+                let bone = &bones[location];
+                let position = bone.tail_world_position;
+                let rotation = to_euler_angles(bone.world_orientation, glam::EulerRot::ZXY);
+                [
+                    make_pos_message(format!("/tracking/trackers/{i}/position"), position),
+                    make_pos_message(format!("/tracking/trackers/{i}/rotation"), rotation),
+                ]
+            });
 
-        self.osc.send_bundle(osc_messages).await.ok();
+        self.osc
+            .send_bundle(osc_messages, &config.send_rate)
+            .await
+            .ok();
         Ok(())
     }
 
-    pub async fn apply_config(&mut self, config: &VrChatConfig) {
+    async fn apply_config(&mut self, config: &Self::Config) -> anyhow::Result<()> {
         if config.enabled {
-            self.osc.connect(config.send_port).await;
+            self.osc.connect(config.send_port).await?;
+        } else {
+            self.osc.disconnect();
         }
+        Ok(())
     }
 }
 
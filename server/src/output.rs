@@ -0,0 +1,209 @@
+use async_trait::async_trait;
+
+use crate::{
+    config::GlobalConfig,
+    osc::{vmc_connector::VmcConnector, vrchat_connector::VrChatConnector},
+    skeleton::SkeletonManager,
+    steamvr::steamvr_connector::SteamVrConnector,
+};
+
+/// Something that sends the skeleton to another application every update
+#[async_trait]
+pub trait OutputConnector: Send {
+    type Config: Send + Sync;
+
+    /// Used to identify the output in logs
+    const NAME: &'static str;
+
+    /// Gets the config for this output from the global config
+    fn get_config(config: &GlobalConfig) -> &Self::Config;
+
+    fn is_enabled(config: &Self::Config) -> bool;
+
+    /// Called whenever the config gets changed, including when the output gets enabled or disabled
+    async fn apply_config(&mut self, config: &Self::Config) -> anyhow::Result<()>;
+
+    /// Called every update only when the output is enabled
+    async fn update(
+        &mut self,
+        skeleton: &SkeletonManager,
+        config: &Self::Config,
+    ) -> anyhow::Result<()>;
+}
+
+/// Object safe version of OutputConnector to be able to store different outputs together
+#[async_trait]
+trait DynOutputConnector: Send {
+    fn name(&self) -> &'static str;
+    fn is_enabled(&self, config: &GlobalConfig) -> bool;
+    async fn apply_config(&mut self, config: &GlobalConfig) -> anyhow::Result<()>;
+    async fn update(
+        &mut self,
+        skeleton: &SkeletonManager,
+        config: &GlobalConfig,
+    ) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl<T: OutputConnector> DynOutputConnector for T {
+    fn name(&self) -> &'static str {
+        T::NAME
+    }
+
+    fn is_enabled(&self, config: &GlobalConfig) -> bool {
+        T::is_enabled(T::get_config(config))
+    }
+
+    async fn apply_config(&mut self, config: &GlobalConfig) -> anyhow::Result<()> {
+        OutputConnector::apply_config(self, T::get_config(config)).await
+    }
+
+    async fn update(
+        &mut self,
+        skeleton: &SkeletonManager,
+        config: &GlobalConfig,
+    ) -> anyhow::Result<()> {
+        OutputConnector::update(self, skeleton, T::get_config(config)).await
+    }
+}
+
+struct RegisteredOutput {
+    connector: Box<dyn DynOutputConnector>,
+    enabled: bool,
+}
+
+pub struct OutputRegistry {
+    outputs: Vec<RegisteredOutput>,
+}
+
+impl Default for OutputRegistry {
+    fn default() -> Self {
+        let mut this = Self {
+            outputs: Vec::new(),
+        };
+
+        // Add new outputs here
+        this.register(VmcConnector::default());
+        this.register(VrChatConnector::default());
+        this.register(SteamVrConnector::default());
+        this
+    }
+}
+
+impl OutputRegistry {
+    pub fn register(&mut self, connector: impl OutputConnector + 'static) {
+        self.outputs.push(RegisteredOutput {
+            connector: Box::new(connector),
+            enabled: false,
+        });
+    }
+
+    /// Applies the config to every output even if some fail, the errors are returned together
+    pub async fn apply_config(&mut self, config: &GlobalConfig) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+        for output in &mut self.outputs {
+            let enabled = output.connector.is_enabled(config);
+            if enabled != output.enabled {
+                log::info!(
+                    "{} output {}",
+                    output.connector.name(),
+                    if enabled { "enabled" } else { "disabled" }
+                );
+                output.enabled = enabled;
+            }
+
+            if let Err(err) = output.connector.apply_config(config).await {
+                errors.push(format!("{} output: {err}", output.connector.name()));
+            }
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("Failed to apply config to {}", errors.join(", "));
+        }
+        Ok(())
+    }
+
+    pub async fn update(
+        &mut self,
+        skeleton: &SkeletonManager,
+        config: &GlobalConfig,
+    ) -> anyhow::Result<()> {
+        for output in &mut self.outputs {
+            if output.enabled {
+                output.connector.update(skeleton, config).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    struct TestOutput {
+        fail: bool,
+        applied: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl OutputConnector for TestOutput {
+        type Config = GlobalConfig;
+
+        const NAME: &'static str = "Test";
+
+        fn get_config(config: &GlobalConfig) -> &Self::Config {
+            config
+        }
+
+        fn is_enabled(_config: &Self::Config) -> bool {
+            true
+        }
+
+        async fn apply_config(&mut self, _config: &Self::Config) -> anyhow::Result<()> {
+            self.applied.store(true, Ordering::Relaxed);
+            if self.fail {
+                anyhow::bail!("Port in use");
+            }
+            Ok(())
+        }
+
+        async fn update(
+            &mut self,
+            _skeleton: &SkeletonManager,
+            _config: &Self::Config,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn apply_config_after_error() {
+        let mut registry = OutputRegistry {
+            outputs: Vec::new(),
+        };
+        let applied: [Arc<AtomicBool>; 2] = Default::default();
+        for (fail, applied) in [true, false].into_iter().zip(&applied) {
+            registry.register(TestOutput {
+                fail,
+                applied: applied.clone(),
+            });
+        }
+
+        // The output after the failed one still gets the config
+        let err = registry
+            .apply_config(&GlobalConfig::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Test output: Port in use"));
+        assert!(applied
+            .iter()
+            .all(|applied| applied.load(Ordering::Relaxed)));
+    }
+}
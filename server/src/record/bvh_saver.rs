@@ -1,9 +1,8 @@
 use std::io::Write;
 
 use crate::{
-    looper::Looper,
     math::to_euler_angles,
-    record::motion_recorder::MotionFrame,
+    record::motion_recorder::{MotionFrame, FRAME_TIME},
     skeleton::{BoneLocation, SkeletonManager},
 };
 
@@ -40,10 +39,7 @@ impl<'a, W: Write> BvhSaver<'a, W> {
 
         self.write("MOTION")?;
         self.write(format!("Frames: {}", frames.len()))?;
-        self.write(format!(
-            "Frame Time: {:.6}",
-            Looper::TARGET_LOOP_DELTA.as_secs_f32()
-        ))?;
+        self.write(format!("Frame Time: {:.6}", FRAME_TIME.as_secs_f32()))?;
 
         for frame in frames {
             // Add the root_position and all the orientations
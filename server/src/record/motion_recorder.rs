@@ -1,5 +1,10 @@
+use std::time::{Duration, Instant};
+
 use crate::skeleton::{BoneLocation, SkeletonManager};
 
+/// Time between the recorded frames, independent of the tick rate of the server
+pub const FRAME_TIME: Duration = Duration::from_millis(1000 / 60);
+
 #[derive(Default)]
 pub struct MotionFrame {
     pub orientations: [glam::Quat; BoneLocation::COUNT],
@@ -26,6 +31,7 @@ impl MotionFrame {
 pub struct MotionRecorder {
     frames: Vec<MotionFrame>,
     recording: bool,
+    next_frame_time: Option<Instant>,
 }
 
 impl MotionRecorder {
@@ -33,6 +39,7 @@ impl MotionRecorder {
         log::info!("Started recording");
         self.frames.clear();
         self.recording = true;
+        self.next_frame_time = None;
     }
 
     pub fn stop_record(&mut self) -> &Vec<MotionFrame> {
@@ -46,6 +53,18 @@ impl MotionRecorder {
             return;
         }
 
+        // Updates can be faster or slower than the frame time, so skip or repeat frames to keep
+        // the timing of the recording right
+        let now = Instant::now();
+        let mut next_frame_time = self.next_frame_time.unwrap_or(now);
+        while next_frame_time <= now {
+            next_frame_time += FRAME_TIME;
+            self.record_frame(skeleton);
+        }
+        self.next_frame_time = Some(next_frame_time);
+    }
+
+    fn record_frame(&mut self, skeleton: &SkeletonManager) {
         let mut frame = MotionFrame {
             root_position: skeleton.root_position,
             ..Default::default()
@@ -0,0 +1,217 @@
+//! Commands sent to a tracker device over serial and the responses it sends back
+//!
+//! Commands are a line of the command name followed by each argument, all seperated by a null byte:
+//! `Wifi\0<ssid>\0<password>\n`
+//! Responses use the same format and are mixed in with the log lines:
+//! `DeviceInfo\0<mac>\0<firmware version>\0<board>\n`
+//! `SelfTest\0<1 if passed, 0 if failed>\0<message>\n`
+//! `Ok\0<command name>\n`
+//! `Error\0<command name>\0<error message>\n`
+
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::udp::auth::{self, PairingKey};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, TS)]
+#[serde(tag = "type")]
+pub enum SerialCommand {
+    SetWifi {
+        ssid: Box<str>,
+        password: Box<str>,
+    },
+    GetDeviceInfo,
+    SetServerIp {
+        ip: Ipv4Addr,
+    },
+    FactoryReset,
+    Restart,
+    SelfTest,
+    /// Gives the device the key it authenticates its udp packets with, only sent by the server
+    #[serde(skip)]
+    #[ts(skip)]
+    Pair(PairingKey),
+}
+
+impl SerialCommand {
+    /// Name of the command used in the serial protocol
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SetWifi { .. } => "Wifi",
+            Self::GetDeviceInfo => "DeviceInfo",
+            Self::SetServerIp { .. } => "ServerIp",
+            Self::FactoryReset => "FactoryReset",
+            Self::Restart => "Restart",
+            Self::SelfTest => "SelfTest",
+            Self::Pair(_) => "Pair",
+        }
+    }
+
+    /// Returns true if the device should respond with a SerialResponse with the same name
+    pub fn expects_response(&self) -> bool {
+        matches!(
+            self,
+            Self::GetDeviceInfo | Self::SetServerIp { .. } | Self::SelfTest | Self::Pair(_)
+        )
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let args = match self {
+            Self::SetWifi { ssid, password } => vec![ssid.to_string(), password.to_string()],
+            Self::SetServerIp { ip } => vec![ip.to_string()],
+            Self::Pair(key) => vec![auth::key_to_hex(key)],
+            Self::GetDeviceInfo | Self::FactoryReset | Self::Restart | Self::SelfTest => Vec::new(),
+        };
+
+        let mut bytes = self.name().as_bytes().to_vec();
+        for arg in args {
+            if arg.contains(['\0', '\n']) {
+                anyhow::bail!("Invalid character in {} command argument", self.name());
+            }
+
+            bytes.push(b'\0');
+            bytes.extend(arg.as_bytes());
+        }
+
+        bytes.push(b'\n');
+        Ok(bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[serde(tag = "type")]
+pub enum SerialResponse {
+    WifiConnecting,
+    WifiConnectOk,
+    WifiConnectTimeout,
+    /// Connected to the server
+    Connected,
+    Restarting,
+    DeviceInfo {
+        mac: Box<str>,
+        firmware_version: Box<str>,
+        board: Box<str>,
+    },
+    SelfTest {
+        passed: bool,
+        message: Box<str>,
+    },
+    /// The device has stored the pairing key
+    Paired {
+        mac: Box<str>,
+    },
+    CommandOk {
+        command: Box<str>,
+    },
+    CommandError {
+        command: Box<str>,
+        error: Box<str>,
+    },
+}
+
+impl SerialResponse {
+    /// Returns none if the line is just a log
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut args = line.split('\0');
+        let mut next_arg = || args.next().map(Box::<str>::from);
+
+        Some(match next_arg()?.as_ref() {
+            "WifiConnecting" => Self::WifiConnecting,
+            "WifiConnectOk" => Self::WifiConnectOk,
+            "WifiConnectTimeout" => Self::WifiConnectTimeout,
+            "Connected" => Self::Connected,
+            "Restarting" => Self::Restarting,
+            "DeviceInfo" => Self::DeviceInfo {
+                mac: next_arg()?,
+                firmware_version: next_arg()?,
+                board: next_arg()?,
+            },
+            "SelfTest" => Self::SelfTest {
+                passed: next_arg()?.as_ref() == "1",
+                message: next_arg().unwrap_or_default(),
+            },
+            "Paired" => Self::Paired {
+                mac: next_arg()?.to_lowercase().into(),
+            },
+            "Ok" => Self::CommandOk {
+                command: next_arg()?,
+            },
+            "Error" => Self::CommandError {
+                command: next_arg()?,
+                error: next_arg().unwrap_or_default(),
+            },
+            _ => return None,
+        })
+    }
+
+    /// Gets the name of the command this is responding to
+    pub fn command_name(&self) -> Option<&str> {
+        match self {
+            Self::DeviceInfo { .. } => Some("DeviceInfo"),
+            Self::SelfTest { .. } => Some("SelfTest"),
+            Self::Paired { .. } => Some("Pair"),
+            Self::CommandOk { command } | Self::CommandError { command, .. } => Some(command),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_to_bytes() {
+        let command = SerialCommand::SetWifi {
+            ssid: "ssid".into(),
+            password: "pass".into(),
+        };
+        assert_eq!(command.to_bytes().unwrap(), b"Wifi\0ssid\0pass\n");
+
+        let command = SerialCommand::SetServerIp {
+            ip: Ipv4Addr::new(192, 168, 0, 2),
+        };
+        assert_eq!(command.to_bytes().unwrap(), b"ServerIp\x00192.168.0.2\n");
+
+        let command = SerialCommand::Pair([0xab; 32]);
+        let mut expected = b"Pair\0".to_vec();
+        expected.extend([b'a', b'b'].repeat(32));
+        expected.push(b'\n');
+        assert_eq!(command.to_bytes().unwrap(), expected);
+
+        let command = SerialCommand::SetWifi {
+            ssid: "bad\n".into(),
+            password: "".into(),
+        };
+        assert!(command.to_bytes().is_err());
+    }
+
+    #[test]
+    fn parse_response() {
+        assert_eq!(
+            SerialResponse::parse("DeviceInfo\0aa:bb\x000.1.0\0esp8266"),
+            Some(SerialResponse::DeviceInfo {
+                mac: "aa:bb".into(),
+                firmware_version: "0.1.0".into(),
+                board: "esp8266".into(),
+            })
+        );
+        assert_eq!(
+            SerialResponse::parse("Error\0ServerIp\0Invalid"),
+            Some(SerialResponse::CommandError {
+                command: "ServerIp".into(),
+                error: "Invalid".into(),
+            })
+        );
+        assert_eq!(
+            SerialResponse::parse("Paired\0AA:BB"),
+            Some(SerialResponse::Paired {
+                mac: "aa:bb".into()
+            })
+        );
+        assert_eq!(SerialResponse::parse("DeviceInfo\0aa:bb"), None);
+        assert_eq!(SerialResponse::parse("Some log"), None);
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serialport::SerialPort;
+
+use crate::{
+    firmware::{FirmwareImage, FirmwareUpdateStatus},
+    main_server::MainServer,
+    serial::{
+        command::{SerialCommand, SerialResponse},
+        device::SerialDevice,
+        flasher::{SerialFlasher, FLASH_BAUD_RATE},
+        slip, NativePort,
+    },
+    udp::auth::{self, PairingKey},
+};
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Things received from the port that haven't been sent over to the websocket yet
+#[derive(Default)]
+pub struct SerialReceived {
+    pub logs: Vec<Box<str>>,
+    pub responses: Vec<SerialResponse>,
+    /// Latest status of the firmware being flashed
+    pub flash_status: Option<FirmwareUpdateStatus>,
+}
+
+/// An opened serial port
+pub struct SerialConnection {
+    port: NativePort,
+    line_buffer: Vec<u8>,
+    slip_decoder: slip::SlipDecoder,
+    device: SerialDevice,
+    /// Name of the command that is waiting for a response and when it was sent
+    pending_command: Option<(&'static str, Instant)>,
+    flasher: Option<SerialFlasher>,
+    /// The baud rate to go back to after flashing
+    baud_rate_after_flash: u32,
+    /// Key that was sent to the device and is waiting to be confirmed
+    pairing_key: Option<PairingKey>,
+    /// Mac address and key of the device once it has confirmed the pairing
+    paired: Option<(Arc<str>, PairingKey)>,
+}
+
+impl SerialConnection {
+    pub fn new(port: NativePort) -> Self {
+        Self {
+            port,
+            line_buffer: Vec::new(),
+            slip_decoder: slip::SlipDecoder::default(),
+            device: SerialDevice::default(),
+            pending_command: None,
+            flasher: None,
+            baud_rate_after_flash: 0,
+            pairing_key: None,
+            paired: None,
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.port.write_all(data)?;
+        Ok(())
+    }
+
+    /// Writes a packet framed with SLIP
+    pub fn write_packet(&mut self, packet: &[u8]) -> anyhow::Result<()> {
+        let mut bytes = Vec::new();
+        slip::encode(packet, &mut bytes);
+        self.write(&bytes)
+    }
+
+    pub fn send_command(&mut self, command: &SerialCommand) -> anyhow::Result<()> {
+        self.write(&command.to_bytes()?)?;
+
+        if command.expects_response() {
+            self.pending_command = Some((command.name(), Instant::now()));
+        }
+
+        Ok(())
+    }
+
+    /// Gives the device a new key that it authenticates its udp packets with
+    pub fn pair(&mut self) -> anyhow::Result<()> {
+        let key = auth::generate_key();
+        self.send_command(&SerialCommand::Pair(key))?;
+        self.pairing_key = Some(key);
+        Ok(())
+    }
+
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> anyhow::Result<()> {
+        if self.flasher.is_some() {
+            self.baud_rate_after_flash = baud_rate;
+        } else if self.port.baud_rate()? != baud_rate {
+            self.port.set_baud_rate(baud_rate)?;
+        }
+        Ok(())
+    }
+
+    pub fn flash_firmware(&mut self, image: FirmwareImage) -> anyhow::Result<()> {
+        if self.flasher.is_some() {
+            anyhow::bail!("Firmware is already being flashed");
+        }
+
+        self.baud_rate_after_flash = self.port.baud_rate()?;
+        self.port.set_baud_rate(FLASH_BAUD_RATE)?;
+        // The trackers won't be sending anything until the new firmware starts
+        self.device.disconnect();
+        self.flasher = Some(SerialFlasher::new(image, &mut self.port));
+        Ok(())
+    }
+
+    /// Returns false if the port can't be read from anymore
+    pub fn is_connected(&self) -> bool {
+        self.port.bytes_to_read().is_ok()
+    }
+
+    /// Should be called when the port gets closed
+    pub fn disconnect(&mut self) {
+        self.device.disconnect();
+    }
+
+    /// Reads all the available bytes and seperates them into log lines and packets
+    pub fn update(
+        &mut self,
+        main: &mut MainServer,
+        received: &mut SerialReceived,
+    ) -> anyhow::Result<()> {
+        let mut flash_result = Ok(());
+        let available = self.port.bytes_to_read()? as usize;
+        if available > 0 {
+            let mut bytes = vec![0; available];
+            let amount = self.port.read(&mut bytes)?;
+
+            let mut packet_responses = Vec::new();
+            for byte in &bytes[0..amount] {
+                if *byte == slip::END || self.slip_decoder.in_frame() {
+                    let Some(frame) = self.slip_decoder.push(*byte) else {
+                        continue;
+                    };
+
+                    // Frames are bootloader responses while flashing
+                    if let Some(flasher) = self.flasher.as_mut() {
+                        flash_result = flash_result
+                            .and_then(|_| flasher.handle_response(&mut self.port, frame));
+                    } else {
+                        match self.device.handle_packet(frame, main) {
+                            Ok(Some(response)) => packet_responses.push(response),
+                            Ok(None) => {}
+                            Err(err) => log::trace!("Received invalid serial packet: {err:?}"),
+                        }
+                    }
+                } else {
+                    self.push_log_byte(*byte, received);
+                }
+            }
+
+            for response in packet_responses {
+                self.write_packet(&response)?;
+            }
+        }
+
+        if let Some(flasher) = self.flasher.as_mut() {
+            let result = flash_result.and_then(|_| flasher.poll(&mut self.port));
+            if let Some(progress) = flasher.take_progress() {
+                received.flash_status = Some(FirmwareUpdateStatus::Uploading { progress });
+            }
+
+            if result.is_err() || flasher.is_done() {
+                self.finish_flash(result, received)?;
+            }
+        }
+
+        if let Some(paired) = self.paired.take() {
+            main.requests.new_pairings.push(paired);
+        }
+
+        if let Some((name, sent_time)) = self.pending_command {
+            // Reported like any other failed command so the other ports still get updated
+            if sent_time.elapsed() > COMMAND_TIMEOUT {
+                self.pending_command = None;
+                log::warn!("Serial device did not respond to the {name} command");
+                received.responses.push(SerialResponse::CommandError {
+                    command: name.into(),
+                    error: "Device did not respond".into(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish_flash(
+        &mut self,
+        result: anyhow::Result<()>,
+        received: &mut SerialReceived,
+    ) -> anyhow::Result<()> {
+        let Some(flasher) = self.flasher.take() else {
+            return Ok(());
+        };
+
+        received.flash_status = Some(match result {
+            Ok(()) => {
+                log::info!("Flashed firmware {}", flasher.version());
+                FirmwareUpdateStatus::Done {
+                    version: flasher.version().into(),
+                }
+            }
+            Err(err) => {
+                log::warn!("Failed to flash firmware: {err}");
+                FirmwareUpdateStatus::Failed {
+                    error: err.to_string().into(),
+                }
+            }
+        });
+
+        self.port.set_baud_rate(self.baud_rate_after_flash)?;
+        Ok(())
+    }
+
+    // Only add the log when new line is reached to prevent cut off messages
+    fn push_log_byte(&mut self, byte: u8, received: &mut SerialReceived) {
+        if byte != b'\n' {
+            self.line_buffer.push(byte);
+            return;
+        }
+
+        // Only allow valid utf8 (becomes owned if not valid)
+        if let Cow::Borrowed(line) = String::from_utf8_lossy(&self.line_buffer) {
+            if let Some(response) = SerialResponse::parse(line) {
+                if response.command_name().is_some()
+                    && response.command_name() == self.pending_command.map(|(name, _)| name)
+                {
+                    self.pending_command = None;
+                }
+
+                if let SerialResponse::Paired { mac } = &response {
+                    if let Some(key) = self.pairing_key.take() {
+                        self.paired = Some((mac.as_ref().into(), key));
+                    }
+                }
+
+                received.responses.push(response);
+            }
+
+            received.logs.push(line.into());
+        }
+
+        self.line_buffer.clear();
+    }
+}
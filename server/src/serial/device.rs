@@ -0,0 +1,123 @@
+use std::sync::{Arc, MutexGuard};
+
+use crate::{
+    main_server::MainServer,
+    tracker::{DeviceInfo, Tracker, TrackerRef, TrackerSource},
+    udp::packet::{DataEncoding, UdpPacket},
+};
+
+/// A tracker device that is sending the same packets as udp devices but through a serial port
+#[derive(Default)]
+pub struct SerialDevice {
+    /// Set after the handshake packet
+    mac: Option<Arc<str>>,
+    device_info: Option<DeviceInfo>,
+    firmware_version: Option<Box<str>>,
+    global_trackers: Vec<Option<TrackerRef>>,
+}
+
+impl SerialDevice {
+    /// Returns the bytes that should be sent back to the device
+    pub fn handle_packet(
+        &mut self,
+        mut bytes: &[u8],
+        main: &mut MainServer,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        // The packet number is not needed since serial is already ordered
+        let (packet, _) = UdpPacket::parse(&mut bytes)?;
+
+        if let UdpPacket::Handshake(packet) = packet {
+            log::info!("New serial device {}", packet.mac_address);
+            let response = packet.to_response();
+            self.mac = Some(packet.mac_address);
+            self.device_info = packet.device_info;
+            self.firmware_version = packet.firmware_version;
+            return Ok(Some(response));
+        }
+
+        let mac = self
+            .mac
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Serial device has not sent a handshake"))?;
+
+        match packet {
+            UdpPacket::Handshake(_)
+            | UdpPacket::PingPong(_)
+            | UdpPacket::FirmwareVersion(_)
+            | UdpPacket::OtaAck(_)
+            | UdpPacket::DeviceLog(_)
+            | UdpPacket::DeviceError(_)
+            | UdpPacket::Diagnostics(_)
+            | UdpPacket::CommandAck(_)
+            | UdpPacket::Reliable(_)
+            | UdpPacket::ReliableAck(_)
+            | UdpPacket::Discovery(_) => {}
+            UdpPacket::TrackerData(mut packet) => {
+                while let Some(data) = packet.next_data(DataEncoding::Full)? {
+                    if let Some(mut tracker) = self.get_tracker(data.tracker_index) {
+                        tracker.update_data(data.acceleration, data.orientation);
+                    }
+                }
+            }
+            // Serial has no delay worth correcting so the samples are used as they arrive
+            UdpPacket::TrackerSamples(mut packet) => {
+                while let Some(sample) = packet.next_sample(DataEncoding::Full)? {
+                    let data = sample.data;
+                    if let Some(mut tracker) = self.get_tracker(data.tracker_index) {
+                        tracker.update_data(data.acceleration, data.orientation);
+                    }
+                }
+            }
+            UdpPacket::TrackerStatus(packet) => {
+                let index = packet.tracker_index as usize;
+                if index >= self.global_trackers.len() {
+                    self.global_trackers.resize(index + 1, None);
+                }
+
+                // Uses the same id as udp so the tracker keeps its config when switching to wireless
+                if self.global_trackers[index].is_none() {
+                    let id: Arc<str> = format!("{mac}/{index}").into();
+                    self.global_trackers[index] = main.add_tracker(&id, TrackerSource::Serial);
+                }
+
+                if let Some(mut tracker) = self.get_tracker(packet.tracker_index) {
+                    tracker.reset_data();
+                    tracker.update_info().status = packet.tracker_status;
+                    tracker.update_info().address = None;
+                    tracker.update_info().device = self.device_info.clone();
+                    tracker.update_info().firmware_version = self.firmware_version.clone();
+                }
+
+                return Ok(Some(packet.to_response().to_vec()));
+            }
+            UdpPacket::BatteryLevel(packet) => {
+                for mut tracker in self.global_trackers_iter() {
+                    tracker.update_info().battery_level = packet.battery_level;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Should be called when the serial port gets disconnected
+    pub fn disconnect(&mut self) {
+        for mut tracker in self.global_trackers_iter() {
+            tracker.set_timed_out(true);
+        }
+    }
+
+    fn get_tracker(&self, local_index: u8) -> Option<MutexGuard<'_, Tracker>> {
+        self.global_trackers
+            .get(local_index as usize)?
+            .as_ref()?
+            .lock()
+            .ok()
+    }
+
+    fn global_trackers_iter(&self) -> impl Iterator<Item = MutexGuard<'_, Tracker>> {
+        self.global_trackers
+            .iter()
+            .filter_map(|tracker| tracker.as_ref()?.lock().ok())
+    }
+}
@@ -0,0 +1,343 @@
+//! Flashes firmware through the ESP ROM bootloader, using the same serial protocol as esptool
+//!
+//! Commands are SLIP framed: `0x00, command, data length (u16), checksum (u32), data`
+//! Responses are SLIP framed: `0x01, command, data length (u16), value (u32), data`
+//! where the data of a response ends with a status byte followed by an error byte.
+//! The image gets written at address 0 so it should include the bootloader (eg. a merged image).
+//!
+//! After syncing the chip is detected from a magic register since the ROM bootloaders differ:
+//! everything newer than the ESP8266 has to attach the SPI flash first, and the newer chips expect
+//! a fifth word in the begin command saying if the image is encrypted.
+
+use std::time::{Duration, Instant};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use serialport::SerialPort;
+
+use crate::{
+    firmware::{FirmwareImage, ProgressReporter},
+    serial::{slip, NativePort},
+};
+
+pub const FLASH_BAUD_RATE: u32 = 115200;
+
+pub const COMMAND_FLASH_BEGIN: u8 = 0x02;
+pub const COMMAND_FLASH_DATA: u8 = 0x03;
+pub const COMMAND_FLASH_END: u8 = 0x04;
+pub const COMMAND_SYNC: u8 = 0x08;
+pub const COMMAND_READ_REG: u8 = 0x0a;
+pub const COMMAND_SPI_ATTACH: u8 = 0x0d;
+
+/// Register that has a different value for every chip
+pub const CHIP_DETECT_MAGIC_REG: u32 = 0x4000_1000;
+
+const BLOCK_SIZE: usize = 0x400;
+const CHECKSUM_SEED: u8 = 0xef;
+
+/// How long the pins are held during a reset
+const RESET_TIME: Duration = Duration::from_millis(100);
+const BOOT_TIME: Duration = Duration::from_millis(50);
+
+const SYNC_TIMEOUT: Duration = Duration::from_millis(100);
+const SYNC_ATTEMPTS: u32 = 20;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+/// Erasing the flash happens on the begin command which can take a while
+const ERASE_TIMEOUT: Duration = Duration::from_secs(30);
+const COMMAND_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Chip {
+    Esp8266,
+    Esp32,
+    Esp32S2,
+    Esp32S3,
+    Esp32C3,
+}
+
+impl Chip {
+    fn from_magic(magic: u32) -> Option<Self> {
+        match magic {
+            0xfff0_c101 => Some(Self::Esp8266),
+            0x00f0_1d83 => Some(Self::Esp32),
+            0x0000_07c6 => Some(Self::Esp32S2),
+            0x0000_0009 => Some(Self::Esp32S3),
+            0x6921_506f | 0x1b31_506f | 0x4881_606f | 0x4361_606f => Some(Self::Esp32C3),
+            _ => None,
+        }
+    }
+
+    fn needs_spi_attach(self) -> bool {
+        self != Self::Esp8266
+    }
+
+    /// The begin command has a fifth word for if the image is encrypted
+    fn has_encrypted_flag(self) -> bool {
+        matches!(self, Self::Esp32S2 | Self::Esp32S3 | Self::Esp32C3)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum FlashState {
+    /// Holding the chip in reset with the boot pin high
+    Reset {
+        since: Instant,
+    },
+    /// Chip is out of reset with the boot pin low so that it starts the bootloader
+    Boot {
+        since: Instant,
+    },
+    Sync,
+    DetectChip,
+    SpiAttach,
+    Begin,
+    Data {
+        sequence: u32,
+    },
+    End,
+    /// Resetting the chip so it runs the new firmware
+    HardReset {
+        since: Instant,
+    },
+    Done,
+}
+
+pub struct SerialFlasher {
+    image: FirmwareImage,
+    state: FlashState,
+    /// When the current command was sent, None if it still needs to be sent
+    sent_time: Option<Instant>,
+    attempts: u32,
+    /// Known once the bootloader has responded to the detect command
+    chip: Option<Chip>,
+    progress_reporter: ProgressReporter,
+}
+
+impl SerialFlasher {
+    /// Resets the chip into the bootloader and starts flashing the image
+    pub fn new(image: FirmwareImage, port: &mut NativePort) -> Self {
+        // The boot pin is connected to DTR and the enable pin is connected to RTS
+        set_pins(port, false, true);
+
+        Self {
+            image,
+            state: FlashState::Reset {
+                since: Instant::now(),
+            },
+            sent_time: None,
+            attempts: 0,
+            chip: None,
+            progress_reporter: ProgressReporter::default(),
+        }
+    }
+
+    pub fn version(&self) -> &str {
+        &self.image.version
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == FlashState::Done
+    }
+
+    /// Sends the next command when the previous one has been responded to
+    pub fn poll(&mut self, port: &mut NativePort) -> anyhow::Result<()> {
+        match self.state {
+            FlashState::Reset { since } if since.elapsed() > RESET_TIME => {
+                set_pins(port, true, false);
+                self.state = FlashState::Boot {
+                    since: Instant::now(),
+                };
+            }
+            FlashState::Boot { since } if since.elapsed() > BOOT_TIME => {
+                set_pins(port, false, false);
+                self.state = FlashState::Sync;
+            }
+            FlashState::HardReset { since } if since.elapsed() > RESET_TIME => {
+                set_pins(port, false, false);
+                self.state = FlashState::Done;
+            }
+            FlashState::Sync
+            | FlashState::DetectChip
+            | FlashState::SpiAttach
+            | FlashState::Begin
+            | FlashState::Data { .. }
+            | FlashState::End => {
+                self.send_command(port)?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn handle_response(
+        &mut self,
+        port: &mut NativePort,
+        mut frame: &[u8],
+    ) -> anyhow::Result<()> {
+        let direction = frame.read_u8()?;
+        let command = frame.read_u8()?;
+        let size = frame.read_u16::<LittleEndian>()? as usize;
+        let value = frame.read_u32::<LittleEndian>()?;
+
+        // Sync gets responded to multiple times so ignore anything we're not waiting on
+        if direction != 0x01 || Some(command) != self.command() || self.sent_time.is_none() {
+            return Ok(());
+        }
+
+        match frame.get(0..size.min(frame.len())) {
+            Some([0, ..]) => {}
+            Some([_, error, ..]) => {
+                anyhow::bail!("Bootloader failed command 0x{command:02x} with error 0x{error:02x}")
+            }
+            _ => anyhow::bail!("Bootloader sent an invalid response"),
+        }
+
+        self.sent_time = None;
+        self.attempts = 0;
+        self.state = match self.state {
+            FlashState::Sync => FlashState::DetectChip,
+            FlashState::DetectChip => {
+                let chip = Chip::from_magic(value).ok_or_else(|| {
+                    anyhow::anyhow!("Unsupported chip with magic value 0x{value:08x}")
+                })?;
+                log::info!("Flashing a {chip:?}");
+                self.chip = Some(chip);
+                if chip.needs_spi_attach() {
+                    FlashState::SpiAttach
+                } else {
+                    FlashState::Begin
+                }
+            }
+            FlashState::SpiAttach => FlashState::Begin,
+            FlashState::Begin => FlashState::Data { sequence: 0 },
+            FlashState::Data { sequence } if sequence + 1 < self.block_count() => {
+                FlashState::Data {
+                    sequence: sequence + 1,
+                }
+            }
+            FlashState::Data { .. } => FlashState::End,
+            FlashState::End => {
+                set_pins(port, false, true);
+                FlashState::HardReset {
+                    since: Instant::now(),
+                }
+            }
+            state => state,
+        };
+
+        Ok(())
+    }
+
+    /// Returns the progress if it changed by at least a percent since the last call
+    pub fn take_progress(&mut self) -> Option<f32> {
+        let progress = match self.state {
+            FlashState::Data { sequence } => sequence as f32 / self.block_count() as f32,
+            FlashState::End | FlashState::HardReset { .. } | FlashState::Done => 1.,
+            _ => 0.,
+        };
+        self.progress_reporter.update(progress)
+    }
+
+    fn send_command(&mut self, port: &mut NativePort) -> anyhow::Result<()> {
+        let (timeout, max_attempts) = match self.state {
+            FlashState::Sync => (SYNC_TIMEOUT, SYNC_ATTEMPTS),
+            FlashState::Begin => (ERASE_TIMEOUT, COMMAND_ATTEMPTS),
+            _ => (COMMAND_TIMEOUT, COMMAND_ATTEMPTS),
+        };
+
+        if let Some(sent_time) = self.sent_time {
+            if sent_time.elapsed() < timeout {
+                return Ok(());
+            }
+
+            if self.attempts >= max_attempts {
+                anyhow::bail!("Bootloader did not respond, check that the device is in boot mode");
+            }
+        }
+
+        let Some(command) = self.command() else {
+            return Ok(());
+        };
+
+        let (data, checksum) = self.command_data();
+        let mut packet = vec![0x00, command];
+        packet.extend((data.len() as u16).to_le_bytes());
+        packet.extend(checksum.to_le_bytes());
+        packet.extend(data);
+
+        let mut bytes = Vec::new();
+        slip::encode(&packet, &mut bytes);
+        std::io::Write::write_all(port, &bytes)?;
+
+        self.sent_time = Some(Instant::now());
+        self.attempts += 1;
+        Ok(())
+    }
+
+    fn command(&self) -> Option<u8> {
+        match self.state {
+            FlashState::Sync => Some(COMMAND_SYNC),
+            FlashState::DetectChip => Some(COMMAND_READ_REG),
+            FlashState::SpiAttach => Some(COMMAND_SPI_ATTACH),
+            FlashState::Begin => Some(COMMAND_FLASH_BEGIN),
+            FlashState::Data { .. } => Some(COMMAND_FLASH_DATA),
+            FlashState::End => Some(COMMAND_FLASH_END),
+            _ => None,
+        }
+    }
+
+    /// Returns the data of the current command and its checksum
+    fn command_data(&self) -> (Vec<u8>, u32) {
+        let words = |words: &[u32]| words.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+        match self.state {
+            FlashState::Sync => {
+                let mut data = vec![0x07, 0x07, 0x12, 0x20];
+                data.extend([0x55; 32]);
+                (data, 0)
+            }
+            FlashState::DetectChip => (words(&[CHIP_DETECT_MAGIC_REG]), 0),
+            // Attach the default SPI flash pins
+            FlashState::SpiAttach => (words(&[0, 0]), 0),
+            FlashState::Begin => {
+                let size = self.image.data.len() as u32;
+                let mut begin = vec![size, self.block_count(), BLOCK_SIZE as u32, 0];
+                if self.chip.is_some_and(Chip::has_encrypted_flag) {
+                    begin.push(0);
+                }
+                (words(&begin), 0)
+            }
+            FlashState::Data { sequence } => {
+                let start = sequence as usize * BLOCK_SIZE;
+                let end = (start + BLOCK_SIZE).min(self.image.data.len());
+
+                // The last block gets padded to the block size
+                let mut block = self.image.data[start..end].to_vec();
+                block.resize(BLOCK_SIZE, 0xff);
+
+                let checksum = block.iter().fold(CHECKSUM_SEED, |sum, byte| sum ^ byte);
+                let mut data: Vec<u8> = words(&[BLOCK_SIZE as u32, sequence, 0, 0]);
+                data.extend(block);
+                (data, checksum as u32)
+            }
+            // Stay in the bootloader since the chip is reset afterwards anyways
+            _ => (words(&[1]), 0),
+        }
+    }
+
+    fn block_count(&self) -> u32 {
+        self.image.data.len().div_ceil(BLOCK_SIZE) as u32
+    }
+}
+
+/// Sets the DTR and RTS lines, which are inverted by the transistors on the board
+fn set_pins(port: &mut NativePort, dtr: bool, rts: bool) {
+    let result = port
+        .write_data_terminal_ready(dtr)
+        .and_then(|_| port.write_request_to_send(rts));
+
+    // Some adapters don't have the lines so the device has to be put into boot mode manually
+    if let Err(err) = result {
+        log::debug!("Failed to set serial control lines: {err}");
+    }
+}
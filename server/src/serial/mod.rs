@@ -0,0 +1,320 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::mpsc::{Receiver, TrySendError},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    config::GlobalConfig,
+    firmware::FirmwareImage,
+    input::InputSource,
+    main_server::MainServer,
+    serial::{command::SerialCommand, connection::SerialConnection},
+};
+
+pub mod command;
+mod connection;
+mod device;
+pub mod flasher;
+pub mod slip;
+
+pub use connection::SerialReceived;
+
+#[cfg(unix)]
+pub type NativePort = serialport::TTYPort;
+#[cfg(windows)]
+pub type NativePort = serialport::COMPort;
+
+/// Max amount of log lines kept per port if they're not being taken
+const MAX_LOGS: usize = 100;
+
+/// USB vendor and product ids of the USB to serial chips used on tracker boards
+const KNOWN_DEVICE_IDS: &[(u16, u16)] = &[
+    // CH340
+    (0x1a86, 0x7523),
+    // CP210x
+    (0x10c4, 0xea60),
+    // ESP32-S3/C3 native USB
+    (0x303a, 0x1001),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(default)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    /// Only list and automatically connect to ports that look like a tracker
+    pub only_known_devices: bool,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            baud_rate: 14400,
+            only_known_devices: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+pub struct SerialPortInfo {
+    pub name: Box<str>,
+    pub vid: u16,
+    pub pid: u16,
+    #[ts(optional)]
+    pub serial_number: Option<Box<str>>,
+    #[ts(optional)]
+    pub product: Option<Box<str>>,
+    /// The USB ids match a known tracker board
+    pub known_device: bool,
+    pub connected: bool,
+}
+
+impl SerialPortInfo {
+    fn from_usb(name: &str, info: &serialport::UsbPortInfo) -> Self {
+        Self {
+            name: name.into(),
+            vid: info.vid,
+            pid: info.pid,
+            serial_number: info.serial_number.as_deref().map(Box::from),
+            product: info.product.as_deref().map(Box::from),
+            known_device: KNOWN_DEVICE_IDS.contains(&(info.vid, info.pid)),
+            connected: false,
+        }
+    }
+}
+
+pub struct SerialPortManager {
+    // Maps a port name to an opened port
+    connections: HashMap<Box<str>, SerialConnection>,
+    available_ports: Vec<SerialPortInfo>,
+    ports_rx: Receiver<Vec<SerialPortInfo>>,
+    /// Ports the user has disconnected from so they don't get automatically connected again
+    manually_closed: HashSet<Box<str>>,
+    // Maps a port name to what was received from it
+    received: HashMap<Box<str>, SerialReceived>,
+    config: SerialConfig,
+    ports_changed: bool,
+}
+
+impl Default for SerialPortManager {
+    fn default() -> Self {
+        let (ports_tx, ports_rx) = std::sync::mpsc::sync_channel(1);
+
+        // Scanning ports blocks a bit so put it in a seperate task
+        tokio::spawn(async move {
+            loop {
+                let ports = tokio::task::block_in_place(find_usb_ports);
+                // Stop when the manager has been dropped
+                if let Err(TrySendError::Disconnected(_)) = ports_tx.try_send(ports) {
+                    return;
+                }
+
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+        });
+
+        Self {
+            connections: HashMap::new(),
+            available_ports: Vec::new(),
+            ports_rx,
+            manually_closed: HashSet::new(),
+            received: HashMap::new(),
+            config: SerialConfig::default(),
+            ports_changed: false,
+        }
+    }
+}
+
+impl SerialPortManager {
+    pub fn write(&mut self, port_name: &str, data: &[u8]) -> anyhow::Result<()> {
+        self.get_connection(port_name)?.write(data)
+    }
+
+    pub fn send_command(&mut self, port_name: &str, command: &SerialCommand) -> anyhow::Result<()> {
+        log::info!("Sending serial command {command:?} to {port_name}");
+        self.get_connection(port_name)?.send_command(command)
+    }
+
+    pub fn flash_firmware(&mut self, port_name: &str, image: FirmwareImage) -> anyhow::Result<()> {
+        log::info!("Flashing firmware {} to {port_name}", image.version);
+        self.get_connection(port_name)?.flash_firmware(image)
+    }
+
+    /// Pairs the device on the port, the key is handed to the udp server once the device has
+    /// stored it
+    pub fn pair(&mut self, port_name: &str) -> anyhow::Result<()> {
+        log::info!("Pairing the device on {port_name}");
+        self.get_connection(port_name)?.pair()
+    }
+
+    /// Gets the logs and responses received from each port since the last call
+    pub fn take_received(&mut self) -> HashMap<Box<str>, SerialReceived> {
+        std::mem::take(&mut self.received)
+    }
+
+    /// Returns true if the list of ports or their state changed since the last call
+    pub fn take_ports_changed(&mut self) -> bool {
+        std::mem::take(&mut self.ports_changed)
+    }
+
+    /// Gets the available ports, filtered by the config
+    pub fn ports(&self) -> Vec<SerialPortInfo> {
+        self.available_ports
+            .iter()
+            .filter(|port| port.known_device || !self.config.only_known_devices)
+            .map(|port| SerialPortInfo {
+                connected: self.connections.contains_key(&port.name),
+                ..port.clone()
+            })
+            .collect()
+    }
+
+    pub fn connect(&mut self, port_name: &str) -> anyhow::Result<()> {
+        if self.connections.contains_key(port_name) {
+            return Ok(());
+        }
+
+        let port = serialport::new(port_name, self.config.baud_rate)
+            .timeout(Duration::from_millis(5))
+            .open_native()?;
+
+        log::info!("Connected to serial port {port_name}");
+        self.manually_closed.remove(port_name);
+        self.add_port(port_name.into(), port);
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self, port_name: &str) {
+        self.manually_closed.insert(port_name.into());
+        self.remove_port(port_name);
+    }
+
+    pub(crate) fn add_port(&mut self, port_name: Box<str>, port: NativePort) {
+        self.connections
+            .insert(port_name, SerialConnection::new(port));
+        self.ports_changed = true;
+    }
+
+    fn remove_port(&mut self, port_name: &str) {
+        if let Some(mut connection) = self.connections.remove(port_name) {
+            connection.disconnect();
+            log::info!("Serial port {port_name} disconnected");
+            self.ports_changed = true;
+        }
+    }
+
+    fn get_connection(&mut self, port_name: &str) -> anyhow::Result<&mut SerialConnection> {
+        self.connections
+            .get_mut(port_name)
+            .ok_or_else(|| anyhow::anyhow!("Serial port {port_name} is not connected"))
+    }
+
+    fn update_available_ports(&mut self, ports: Vec<SerialPortInfo>) {
+        if ports != self.available_ports {
+            self.ports_changed = true;
+        }
+
+        // Allow the port to be automatically connected again after being plugged back in
+        self.manually_closed
+            .retain(|name| ports.iter().any(|port| port.name == *name));
+        self.available_ports = ports;
+
+        let to_connect = self
+            .ports()
+            .into_iter()
+            .filter(|port| {
+                port.known_device && !port.connected && !self.manually_closed.contains(&port.name)
+            })
+            .collect::<Vec<_>>();
+
+        for port in to_connect {
+            if let Err(err) = self.connect(&port.name) {
+                // Usually because another program is using the port, so try again on the next scan
+                log::trace!("Failed to connect to serial port {}: {err}", port.name);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl InputSource for SerialPortManager {
+    async fn apply_config(&mut self, config: &GlobalConfig) -> anyhow::Result<()> {
+        self.config = config.serial.clone();
+        self.ports_changed = true;
+
+        for connection in self.connections.values_mut() {
+            connection.set_baud_rate(self.config.baud_rate)?;
+        }
+
+        Ok(())
+    }
+
+    async fn update(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+        if let Ok(ports) = self.ports_rx.try_recv() {
+            self.update_available_ports(ports);
+        }
+
+        let disconnected = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| !connection.is_connected())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for port_name in disconnected {
+            self.remove_port(&port_name);
+        }
+
+        let mut result = Ok(());
+        for (port_name, connection) in &mut self.connections {
+            let received = self.received.entry(port_name.clone()).or_default();
+            if let Err(err) = connection.update(main, received) {
+                result = Err(err.context(format!("Serial port {port_name}")));
+            }
+
+            // Only keep the most recent logs
+            let excess = received.logs.len().saturating_sub(MAX_LOGS);
+            received.logs.drain(0..excess);
+            let excess = received.responses.len().saturating_sub(MAX_LOGS);
+            received.responses.drain(0..excess);
+        }
+
+        result
+    }
+}
+
+fn find_usb_ports() -> Vec<SerialPortInfo> {
+    let ports = serialport::available_ports().unwrap_or_default();
+    ports
+        .iter()
+        .filter_map(|port| match &port.port_type {
+            serialport::SerialPortType::UsbPort(info) => {
+                Some(SerialPortInfo::from_usb(&port.port_name, info))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn known_device() {
+        let mut info = serialport::UsbPortInfo {
+            vid: 0x1a86,
+            pid: 0x7523,
+            serial_number: None,
+            manufacturer: None,
+            product: Some("USB Serial".into()),
+        };
+        assert!(SerialPortInfo::from_usb("/dev/ttyUSB0", &info).known_device);
+
+        info.vid = 0x1234;
+        assert!(!SerialPortInfo::from_usb("/dev/ttyUSB0", &info).known_device);
+    }
+}
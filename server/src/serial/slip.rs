@@ -0,0 +1,97 @@
+//! SLIP framing (RFC 1055) used to send binary packets over serial
+//! Every frame is started and ended with END so that frames can be mixed with plain text logs,
+//! since END (0xc0) can never be part of an ascii log line
+
+pub const END: u8 = 0xc0;
+pub const ESC: u8 = 0xdb;
+pub const ESC_END: u8 = 0xdc;
+pub const ESC_ESC: u8 = 0xdd;
+
+pub fn encode(data: &[u8], output: &mut Vec<u8>) {
+    output.push(END);
+    for byte in data {
+        match *byte {
+            END => output.extend([ESC, ESC_END]),
+            ESC => output.extend([ESC, ESC_ESC]),
+            byte => output.push(byte),
+        }
+    }
+    output.push(END);
+}
+
+#[derive(Default)]
+pub struct SlipDecoder {
+    buffer: Vec<u8>,
+    in_frame: bool,
+    escaped: bool,
+}
+
+impl SlipDecoder {
+    /// Returns true if the decoder is in the middle of a frame and the byte should be passed through push
+    pub fn in_frame(&self) -> bool {
+        self.in_frame
+    }
+
+    /// Returns the frame when the END byte of a frame is reached
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if byte == END {
+            self.escaped = false;
+            // Two END bytes in a row means the last frame ended and a new one started
+            if self.in_frame && !self.buffer.is_empty() {
+                self.in_frame = false;
+                return Some(&self.buffer);
+            }
+
+            self.buffer.clear();
+            self.in_frame = true;
+            return None;
+        }
+
+        if !self.in_frame {
+            return None;
+        }
+
+        if self.escaped {
+            self.escaped = false;
+            match byte {
+                ESC_END => self.buffer.push(END),
+                ESC_ESC => self.buffer.push(ESC),
+                // Invalid escape so drop the frame
+                _ => {
+                    self.buffer.clear();
+                    self.in_frame = false;
+                }
+            }
+        } else if byte == ESC {
+            self.escaped = true;
+        } else {
+            self.buffer.push(byte);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let data = [1, END, 2, ESC, 3, ESC_END];
+        let mut encoded = Vec::new();
+        encode(&data, &mut encoded);
+        assert_eq!(
+            encoded,
+            [END, 1, ESC, ESC_END, 2, ESC, ESC_ESC, 3, ESC_END, END]
+        );
+
+        let mut decoder = SlipDecoder::default();
+        let (last, rest) = encoded.split_last().unwrap();
+        for byte in rest {
+            assert_eq!(decoder.push(*byte), None);
+        }
+        assert_eq!(decoder.push(*last), Some(&data[..]));
+        assert!(!decoder.in_frame());
+    }
+}
@@ -5,33 +5,35 @@ use ts_rs::TS;
 
 use crate::skeleton::BoneOffsetKind;
 
+/// The values are sent to the SteamVR driver so they must not change
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[repr(u8)]
 pub enum BoneLocation {
     /// Also acts as the root joint
-    CenterHip,
-    LeftUpperLeg,
-    RightUpperLeg,
-    LeftLowerLeg,
-    RightLowerLeg,
-    LeftFoot,
-    RightFoot,
-    Waist,
-    Chest,
-    UpperChest,
-    Neck,
-    Head,
-    LeftShoulder,
-    RightShoulder,
-    LeftUpperArm,
-    RightUpperArm,
-    LeftLowerArm,
-    RightLowerArm,
-    LeftHand,
-    RightHand,
+    CenterHip = 0,
+    LeftUpperLeg = 1,
+    RightUpperLeg = 2,
+    LeftLowerLeg = 3,
+    RightLowerLeg = 4,
+    LeftFoot = 5,
+    RightFoot = 6,
+    Waist = 7,
+    Chest = 8,
+    UpperChest = 9,
+    Neck = 10,
+    Head = 11,
+    LeftShoulder = 12,
+    RightShoulder = 13,
+    LeftUpperArm = 14,
+    RightUpperArm = 15,
+    LeftLowerArm = 16,
+    RightLowerArm = 17,
+    LeftHand = 18,
+    RightHand = 19,
     /// Connects the hip to the left upper leg
-    LeftHip,
+    LeftHip = 20,
     /// Connects the hip to the right upper leg
-    RightHip,
+    RightHip = 21,
 }
 
 impl BoneLocation {
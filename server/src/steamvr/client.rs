@@ -0,0 +1,75 @@
+use std::net::Ipv4Addr;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use tokio::net::UdpSocket;
+
+use crate::{
+    skeleton::BoneLocation,
+    steamvr::{PROTOCOL_MAGIC, PROTOCOL_VERSION},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SteamVrPose {
+    pub location: BoneLocation,
+    pub position: glam::Vec3A,
+    pub orientation: glam::Quat,
+}
+
+/// Stands in for the SteamVR driver side of the protocol
+/// This client is mainly for testing purposes and will not be used in the app
+pub struct SteamVrDriverClient {
+    pub socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl SteamVrDriverClient {
+    pub async fn new(port: u16) -> anyhow::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).await?,
+            buffer: vec![0; u16::MAX as usize],
+        })
+    }
+
+    /// Waits for the next datagram and parses the poses inside of it
+    pub async fn receive_poses(&mut self) -> anyhow::Result<Vec<SteamVrPose>> {
+        let amount = self.socket.recv(&mut self.buffer).await?;
+        parse_poses(&self.buffer[0..amount])
+    }
+}
+
+pub fn parse_poses(mut bytes: &[u8]) -> anyhow::Result<Vec<SteamVrPose>> {
+    let mut magic = [0_u8; 4];
+    std::io::Read::read_exact(&mut bytes, &mut magic)?;
+    if &magic != PROTOCOL_MAGIC {
+        anyhow::bail!("Invalid magic {magic:?}");
+    }
+
+    let version = bytes.read_u8()?;
+    if version != PROTOCOL_VERSION {
+        anyhow::bail!("Unsupported protocol version {version}");
+    }
+
+    let count = bytes.read_u8()?;
+    let mut poses = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let index = bytes.read_u8()?;
+        let location = BoneLocation::SELF_AND_PARENT
+            .iter()
+            .map(|(location, _)| *location)
+            .find(|location| *location as u8 == index)
+            .ok_or_else(|| anyhow::anyhow!("Invalid bone location {index}"))?;
+
+        let mut position = [0_f32; 3];
+        bytes.read_f32_into::<LittleEndian>(&mut position)?;
+        let mut orientation = [0_f32; 4];
+        bytes.read_f32_into::<LittleEndian>(&mut orientation)?;
+
+        poses.push(SteamVrPose {
+            location,
+            position: glam::Vec3A::from_array(position),
+            orientation: glam::Quat::from_array(orientation),
+        });
+    }
+
+    Ok(poses)
+}
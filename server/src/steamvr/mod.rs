@@ -0,0 +1,25 @@
+//! Output for a SteamVR (OpenVR) driver to consume bone poses as virtual trackers
+//!
+//! The server sends one UDP datagram to `127.0.0.1:<send_port>` every update, the driver is
+//! expected to bind to that port and create a tracker device for every bone location it sees.
+//! All values are little endian.
+//!
+//! ```text
+//! Header (6 bytes):
+//!     magic       [u8; 4]   b"MCVR"
+//!     version     u8        PROTOCOL_VERSION
+//!     pose_count  u8        number of poses that follow
+//!
+//! Pose (29 bytes), repeated pose_count times:
+//!     location    u8        BoneLocation discriminant (see skeleton/bone.rs)
+//!     position    [f32; 3]  world position of the bone tail in meters (x, y, z), y is up
+//!     orientation [f32; 4]  world orientation quaternion (x, y, z, w)
+//! ```
+
+pub mod client;
+pub mod steamvr_connector;
+
+pub const PROTOCOL_MAGIC: &[u8; 4] = b"MCVR";
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 6;
+pub const POSE_SIZE: usize = 29;
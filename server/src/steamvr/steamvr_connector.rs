@@ -0,0 +1,110 @@
+use std::net::Ipv4Addr;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use ts_rs::TS;
+
+use crate::{
+    config::GlobalConfig,
+    output::OutputConnector,
+    skeleton::{BoneLocation, SkeletonManager},
+    steamvr::{HEADER_SIZE, POSE_SIZE, PROTOCOL_MAGIC, PROTOCOL_VERSION},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(default)]
+pub struct SteamVrConfig {
+    pub enabled: bool,
+    pub send_port: u16,
+    pub bones_to_send: Vec<BoneLocation>,
+}
+
+impl Default for SteamVrConfig {
+    fn default() -> Self {
+        use BoneLocation::*;
+        Self {
+            enabled: false,
+            send_port: 5830,
+            bones_to_send: vec![CenterHip, LeftFoot, RightFoot],
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SteamVrConnector {
+    /// Only exists when enabled
+    socket: Option<UdpSocket>,
+    buffer: Vec<u8>,
+}
+
+#[async_trait]
+impl OutputConnector for SteamVrConnector {
+    type Config = SteamVrConfig;
+
+    const NAME: &'static str = "SteamVR";
+
+    fn get_config(config: &GlobalConfig) -> &Self::Config {
+        &config.steamvr
+    }
+
+    fn is_enabled(config: &Self::Config) -> bool {
+        config.enabled
+    }
+
+    async fn update(
+        &mut self,
+        skeleton: &SkeletonManager,
+        config: &Self::Config,
+    ) -> anyhow::Result<()> {
+        let Some(socket) = self.socket.as_ref() else {
+            return Ok(());
+        };
+
+        let bones = &skeleton.bones;
+        // The pose count needs to fit in a byte
+        let locations = &config.bones_to_send[..config.bones_to_send.len().min(u8::MAX as usize)];
+
+        self.buffer.clear();
+        self.buffer
+            .reserve(HEADER_SIZE + locations.len() * POSE_SIZE);
+        self.buffer.extend(PROTOCOL_MAGIC);
+        self.buffer.push(PROTOCOL_VERSION);
+        self.buffer.push(locations.len() as u8);
+
+        for location in locations {
+            let bone = &bones[location];
+            self.buffer.push(*location as u8);
+            self.buffer.extend(
+                bone.tail_world_position
+                    .to_array()
+                    .iter()
+                    .flat_map(|x| x.to_le_bytes()),
+            );
+            self.buffer.extend(
+                bone.world_orientation
+                    .to_array()
+                    .iter()
+                    .flat_map(|x| x.to_le_bytes()),
+            );
+        }
+
+        // The driver might not be running so ignore errors
+        socket.send(&self.buffer).await.ok();
+        Ok(())
+    }
+
+    async fn apply_config(&mut self, config: &Self::Config) -> anyhow::Result<()> {
+        if config.enabled {
+            let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+            socket
+                .connect((Ipv4Addr::LOCALHOST, config.send_port))
+                .await?;
+            log::info!("Sending SteamVR poses to {:?}", socket.peer_addr());
+            self.socket = Some(socket);
+        } else {
+            self.socket.take();
+        }
+        Ok(())
+    }
+}
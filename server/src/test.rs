@@ -1,32 +1,125 @@
-use std::time::Duration;
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Context;
+use futures_util::SinkExt;
+use tokio_tungstenite::tungstenite::Message;
 
 use crate::{
+    firmware::{get_firmware_dir, FirmwareUpdateStatus},
+    input::InputSource,
     skeleton::BoneLocation,
-    tracker::{TrackerConfig, TrackerStatus},
+    steamvr::client::SteamVrDriverClient,
+    tracker::{DeviceInfo, TrackerConfig, TrackerSource, TrackerStatus},
     udp::{
+        auth,
         client::UdpTrackerClient,
-        packet::{UdpPacketPingPong, UdpTrackerData},
+        command::{CommandResult, DeviceCommand, SensorMode},
+        diagnostics::{DeviceLog, DeviceLogLevel, ResetReason},
+        discovery::{self, MDNS_SERVICE_TYPE},
+        packet::{
+            CommandAckStatus, DataEncoding, OtaAckStatus, UdpPacketPingPong, UdpTrackerData,
+            CAPABILITY_COMMANDS, CAPABILITY_COMPACT_DATA, CAPABILITY_COMPACT_DATA_32,
+            CAPABILITY_OTA, CAPABILITY_RELIABLE, CAPABILITY_TRACKER_SAMPLES, PACKET_COMMAND,
+            PACKET_DISCOVERY, PACKET_FIRMWARE_VERSION, PACKET_HANDSHAKE, PACKET_OTA_BEGIN,
+            PACKET_OTA_CHUNK, PACKET_OTA_END, PACKET_RELIABLE, PACKET_RELIABLE_ACK,
+            PACKET_TRACKER_STATUS, PROTOCOL_VERSION,
+        },
+        server::UDP_PORT,
     },
+    websocket::WEBSOCKET_PORT,
     *,
 };
 
 // Run tests sequentially since server requires listenting to the port
 #[tokio::test]
 async fn tests_sequential() -> anyhow::Result<()> {
+    std::env::set_var("MICAP_CONFIG_DIR", test_config_dir());
+
     // Use spawn to check for Send + Sync
     tokio::spawn(async {
         test_udp_tracker().await.context("test_udp_tracker")?;
+        test_udp_handshake().await.context("test_udp_handshake")?;
+        test_multi_sensor_device()
+            .await
+            .context("test_multi_sensor_device")?;
+        test_tracker_samples()
+            .await
+            .context("test_tracker_samples")?;
+        test_compact_tracker_data()
+            .await
+            .context("test_compact_tracker_data")?;
+        test_device_diagnostics()
+            .await
+            .context("test_device_diagnostics")?;
+        test_firmware_update()
+            .await
+            .context("test_firmware_update")?;
+        test_websocket_requests()
+            .await
+            .context("test_websocket_requests")?;
+        test_device_commands()
+            .await
+            .context("test_device_commands")?;
+        test_reliable_packets()
+            .await
+            .context("test_reliable_packets")?;
+        test_paired_device().await.context("test_paired_device")?;
+        test_ignored_devices()
+            .await
+            .context("test_ignored_devices")?;
+        test_device_approval()
+            .await
+            .context("test_device_approval")?;
+        test_udp_bind_config()
+            .await
+            .context("test_udp_bind_config")?;
+        test_server_discovery()
+            .await
+            .context("test_server_discovery")?;
         test_config().await.context("test_config")?;
-        Ok(())
+        test_steamvr().await.context("test_steamvr")?;
+        test_vmc_receiver().await.context("test_vmc_receiver")?;
+        #[cfg(unix)]
+        test_serial_tracker().await.context("test_serial_tracker")?;
+        #[cfg(unix)]
+        test_serial_command().await.context("test_serial_command")?;
+        #[cfg(unix)]
+        test_serial_flash().await.context("test_serial_flash")?;
+        anyhow::Ok(())
     })
-    .await?
+    .await??;
+
+    std::fs::remove_dir_all(test_config_dir())?;
+    Ok(())
+}
+
+/// The tests never touch the config of the user
+fn test_config_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("micap_test_{}", std::process::id()))
+}
+
+/// Gives a test its own empty config folder
+fn set_config_dir(name: &str) -> PathBuf {
+    let config_dir = test_config_dir().join(name);
+    std::env::set_var("MICAP_CONFIG_DIR", &config_dir);
+    config_dir
+}
+
+/// Removes the config folder of a test and goes back to the shared one
+fn remove_config_dir(config_dir: &Path) -> anyhow::Result<()> {
+    std::fs::remove_dir_all(config_dir)?;
+    std::env::set_var("MICAP_CONFIG_DIR", test_config_dir());
+    Ok(())
 }
 
 async fn test_udp_tracker() -> anyhow::Result<()> {
-    let mut main = MainServer::default();
-    let mut modules = ServerModules::new().await?;
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
     let mut client = UdpTrackerClient::new().await?;
 
     let mac = [0x69, 0x42, 0, 0, 0, 0];
@@ -39,7 +132,10 @@ async fn test_udp_tracker() -> anyhow::Result<()> {
         orientation: glam::Quat::from_xyzw(1., 2., 3., 4.),
         acceleration: glam::Vec3A::new(1., 2., 3.),
     };
+    let received = modules.udp_server.packet_received();
     client.send_tracker_data(&[&data]).await?;
+    // The main loop gets woken up as soon as the data arrives
+    tokio::time::timeout(Duration::from_millis(100), received.notified()).await?;
 
     client.send_battery_level(0.2).await?;
 
@@ -47,19 +143,24 @@ async fn test_udp_tracker() -> anyhow::Result<()> {
     client.socket.send(&packet.to_response()).await?;
 
     tokio::time::sleep(Duration::from_millis(200)).await;
+    let update_time = std::time::Instant::now();
     modules.udp_server.update(&mut main).await?;
 
     let tracker = &main.trackers["69:42:00:00:00:00/3"].clone();
     {
         let tracker = tracker.lock().unwrap();
+        // Timed by when the data arrived, not when it was handled
+        assert!(tracker.internal.time_data_last_updated < Some(update_time));
         assert_eq!(tracker.info().status, TrackerStatus::Ok);
         assert_eq!(tracker.info().battery_level, 0.2);
         assert_eq!(tracker.info().address, client.socket.local_addr().ok());
+        assert_eq!(tracker.info().source, Some(TrackerSource::Udp));
         assert_eq!(tracker.data().acceleration, glam::vec3a(1., 3., 2.));
         assert_eq!(tracker.data().orientation, glam::quat(-1., 2., 3., -4.));
     }
 
-    modules.udp_server.upkeep().await?;
+    modules.udp_server.upkeep(&mut main).await?;
+    assert_eq!(tracker.lock().unwrap().info().packet_loss, Some(0.));
 
     // Check for latency set
     client.send_ping(1).await?;
@@ -67,13 +168,937 @@ async fn test_udp_tracker() -> anyhow::Result<()> {
     tokio::time::sleep(Duration::from_millis(200)).await;
     modules.udp_server.update(&mut main).await?;
 
-    assert!(tracker.lock().unwrap().info().latency_ms == Some(100));
+    // Measured to when the pong arrived, not when the server got to handle it
+    assert_eq!(tracker.lock().unwrap().info().latency_ms, Some(0));
+
+    // Pongs aren't numbered so the packets after them aren't lost
+    client.send_battery_level(0.3).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+    modules.udp_server.upkeep(&mut main).await?;
+    assert_eq!(tracker.lock().unwrap().info().packet_loss, Some(0.));
+
+    // Packets that never arrived count towards the packet loss
+    client.skip_packets(3);
+    client.send_battery_level(0.3).await?;
+    client.send_battery_level(0.3).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+    modules.udp_server.upkeep(&mut main).await?;
+    assert_eq!(tracker.lock().unwrap().info().packet_loss, Some(0.6));
+
+    let quality = &main.updates.link_qualities["69:42:00:00:00:00"];
+    assert_eq!(quality.loss_rate, 0.6);
+    assert!(quality.packets_per_sec > 0. && quality.bytes_per_sec > 0.);
+    assert!(quality.latency.is_some());
+    Ok(())
+}
+
+async fn test_udp_handshake() -> anyhow::Result<()> {
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let mut client = UdpTrackerClient::new().await?;
+
+    // Original handshake gets the original response
+    client.send_handshake([0x69, 0x42, 0, 0, 0, 2]).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+    let response = client.receive_packet(PACKET_HANDSHAKE).await?;
+    assert_eq!(response, b"MCSVR");
+
+    let info = DeviceInfo {
+        protocol_version: 2,
+        board: "esp32-c3".into(),
+        imu: "bmi160".into(),
+        sensor_count: 2,
+    };
+    client
+        .send_handshake_info(
+            [0x69, 0x42, 0, 0, 0, 3],
+            &info,
+            "0.3.0",
+            CAPABILITY_OTA | 1 << 31,
+        )
+        .await?;
+    client.send_tracker_status(1, TrackerStatus::Ok).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    // Server only knows protocol version 1 and doesn't support the unknown capability
+    let response = client.receive_packet(PACKET_HANDSHAKE).await?;
+    let mut expected = b"MCSVR".to_vec();
+    expected.push(PROTOCOL_VERSION);
+    expected.extend(CAPABILITY_OTA.to_le_bytes());
+    assert_eq!(response, expected);
+
+    let tracker = main.trackers["69:42:00:00:00:03/1"].lock().unwrap();
+    assert_eq!(tracker.info().device, Some(info));
+    assert_eq!(tracker.info().firmware_version.as_deref(), Some("0.3.0"));
+    Ok(())
+}
+
+async fn test_multi_sensor_device() -> anyhow::Result<()> {
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let mut client = UdpTrackerClient::new().await?;
+
+    client.send_handshake([0x69, 0x42, 0, 0, 0, 0x10]).await?;
+    let datas: Vec<_> = (0..16)
+        .map(|tracker_index| UdpTrackerData {
+            tracker_index,
+            orientation: glam::Quat::IDENTITY,
+            acceleration: glam::Vec3A::new(tracker_index as f32, 0., 0.),
+        })
+        .collect();
+    for data in &datas {
+        client
+            .send_tracker_status(data.tracker_index, TrackerStatus::Ok)
+            .await?;
+    }
+    // Larger than the packets of devices with a few sensors
+    client
+        .send_tracker_data(&datas.iter().collect::<Vec<_>>())
+        .await?;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    for data in &datas {
+        let tracker = main.trackers[&*format!("69:42:00:00:00:10/{}", data.tracker_index)]
+            .lock()
+            .unwrap();
+        assert_eq!(tracker.data().acceleration, data.acceleration);
+    }
+    Ok(())
+}
+
+async fn test_tracker_samples() -> anyhow::Result<()> {
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let mut client = UdpTrackerClient::new().await?;
+
+    let mac = [0x69, 0x42, 0, 0, 0, 0x11];
+    connect_device(
+        &mut client,
+        &mut main,
+        &mut modules,
+        mac,
+        CAPABILITY_TRACKER_SAMPLES,
+    )
+    .await?;
+
+    let response = client.receive_packet(PACKET_HANDSHAKE).await?;
+    let capabilities = u32::from_le_bytes(response[6..10].try_into()?);
+    assert_eq!(capabilities, CAPABILITY_TRACKER_SAMPLES);
+
+    let sample = |orientation| UdpTrackerData {
+        tracker_index: 0,
+        orientation,
+        acceleration: glam::Vec3A::new(1., 2., 3.),
+    };
+    let (first, last) = (
+        sample(glam::Quat::IDENTITY),
+        sample(glam::Quat::from_xyzw(0., 1., 0., 0.)),
+    );
+    client
+        .send_tracker_samples(1_000_000, &[(20_000, &first), (10_000, &first), (0, &last)])
+        .await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    let tracker = main.trackers["69:42:00:00:00:11/0"].clone();
+    let (velocity, time) = {
+        let tracker = tracker.lock().unwrap();
+        assert_eq!(tracker.data().orientation, glam::quat(0., 1., 0., 0.));
+        assert_eq!(tracker.data().acceleration, glam::vec3a(1., 3., 2.));
+        (
+            tracker.internal.velocity,
+            tracker.internal.time_data_last_updated.unwrap(),
+        )
+    };
+
+    // Integrated with the time between the samples on the device instead of when they arrived
+    client
+        .send_tracker_samples(1_050_000, &[(10_000, &first), (0, &last)])
+        .await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(
+        tracker.internal.time_data_last_updated.unwrap() - time,
+        Duration::from_millis(50)
+    );
+    let expected = velocity + glam::vec3a(1., 3., 2.) * 0.05;
+    assert!(tracker.internal.velocity.abs_diff_eq(expected, 1e-4));
+    Ok(())
+}
+
+async fn test_compact_tracker_data() -> anyhow::Result<()> {
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+
+    let data = UdpTrackerData {
+        tracker_index: 0,
+        orientation: glam::Quat::from_euler(glam::EulerRot::YXZ, 1., -0.5, 2.5),
+        acceleration: glam::Vec3A::new(1.5, -9.81, 0.25),
+    };
+
+    for (i, (capabilities, encoding, epsilon)) in [
+        (CAPABILITY_COMPACT_DATA, DataEncoding::Compact, 1e-3),
+        (
+            CAPABILITY_COMPACT_DATA | CAPABILITY_COMPACT_DATA_32,
+            DataEncoding::Compact32,
+            1e-2,
+        ),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let mut client = UdpTrackerClient::new().await?;
+        let mac = [0x69, 0x42, 0, 0, 0x12, i as u8];
+        connect_device(&mut client, &mut main, &mut modules, mac, capabilities).await?;
+
+        let response = client.receive_packet(PACKET_HANDSHAKE).await?;
+        let negotiated = u32::from_le_bytes(response[6..10].try_into()?);
+        assert_eq!(negotiated, capabilities);
+
+        client.set_data_encoding(encoding);
+        client.send_tracker_data(&[&data]).await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        modules.udp_server.update(&mut main).await?;
+
+        let id = format!("69:42:00:00:12:{i:02x}/0");
+        let tracker = main.trackers[id.as_str()].lock().unwrap();
+        let expected = glam::Quat::from_xyzw(
+            -data.orientation.x,
+            data.orientation.y,
+            data.orientation.z,
+            -data.orientation.w,
+        );
+        assert!(tracker.data().orientation.dot(expected).abs() > 1. - epsilon);
+        let expected = glam::vec3a(1.5, 0.25, -9.81);
+        assert!(tracker.data().acceleration.abs_diff_eq(expected, 1e-2));
+    }
+    Ok(())
+}
+
+async fn test_device_diagnostics() -> anyhow::Result<()> {
+    let config_dir = set_config_dir("diagnostics_test");
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let mut client = UdpTrackerClient::new().await?;
+
+    client.send_handshake([0x69, 0x42, 0, 0, 0, 4]).await?;
+    client.send_tracker_status(0, TrackerStatus::Ok).await?;
+    client
+        .send_log(DeviceLogLevel::Warn, "IMU not found")
+        .await?;
+    client.send_error(0, 12).await?;
+    client
+        .send_diagnostics(-60, ResetReason::Watchdog, &[(0, 36.5)])
+        .await?;
+    // Only the first diagnostics after a reset should be logged
+    client
+        .send_diagnostics(-61, ResetReason::Watchdog, &[(0, 37.)])
+        .await?;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    let mac: Arc<str> = "69:42:00:00:00:04".into();
+    let log = |level, message: &str| DeviceLog {
+        mac: mac.clone(),
+        level,
+        message: message.into(),
+    };
+    let expected = [
+        log(DeviceLogLevel::Warn, "IMU not found"),
+        log(DeviceLogLevel::Error, "Tracker 0 error code 12"),
+        log(DeviceLogLevel::Warn, "Device started after reset: Watchdog"),
+    ];
+    assert_eq!(main.updates.device_logs, expected);
+
+    {
+        let tracker = main.trackers["69:42:00:00:00:04/0"].lock().unwrap();
+        assert_eq!(tracker.info().wifi_rssi, Some(-61));
+        assert_eq!(tracker.info().imu_temperature, Some(37.));
+    }
+
+    let log_file = std::fs::read_to_string(config_dir.join("logs/devices.log"))?;
+    assert_eq!(log_file.lines().count(), 3);
+    assert!(log_file.contains("69:42:00:00:00:04 Warn IMU not found"));
+
+    remove_config_dir(&config_dir)?;
+    Ok(())
+}
+
+async fn test_firmware_update() -> anyhow::Result<()> {
+    let config_dir = set_config_dir("firmware_test");
+
+    let firmware = (0..5000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    std::fs::write(get_firmware_dir()?.join("esp8266-0.2.0.bin"), &firmware)?;
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let mut client = UdpTrackerClient::new().await?;
+
+    // Devices with the original handshake can't be updated
+    client.send_handshake([0x69, 0x42, 0, 0, 0, 1]).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+    let mac: Arc<str> = "69:42:00:00:00:01".into();
+    main.requests.firmware_updates.push(mac.clone());
+    modules.udp_server.update(&mut main).await?;
+    assert!(matches!(
+        main.updates.firmware_update_statuses[&mac],
+        FirmwareUpdateStatus::Failed { .. }
+    ));
+
+    let info = DeviceInfo {
+        board: "esp8266".into(),
+        ..test_device_info()
+    };
+    client
+        .send_handshake_info([0x69, 0x42, 0, 0, 0, 1], &info, "0.1.0", CAPABILITY_OTA)
+        .await?;
+    client.send_tracker_status(0, TrackerStatus::Ok).await?;
+    client.send_firmware_version("esp8266", "0.1.0").await?;
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    let advertised = client.receive_packet(PACKET_FIRMWARE_VERSION).await?;
+    assert_eq!(advertised, b"\x050.2.0");
+    {
+        let tracker = main.trackers["69:42:00:00:00:01/0"].lock().unwrap();
+        assert_eq!(tracker.info().firmware_version.as_deref(), Some("0.1.0"));
+        assert_eq!(tracker.info().firmware_update.as_deref(), Some("0.2.0"));
+    }
+
+    main.requests.firmware_updates.push(mac.clone());
+    modules.udp_server.update(&mut main).await?;
+
+    let begin = client.receive_packet(PACKET_OTA_BEGIN).await?;
+    assert_eq!(begin[0..4], (firmware.len() as u32).to_le_bytes());
+    assert_eq!(begin[4..20], md5::compute(&firmware).0);
+    client
+        .send_ota_ack(PACKET_OTA_BEGIN, OtaAckStatus::Ok, 0)
+        .await?;
+
+    let mut received: Vec<u8> = Vec::new();
+    let mut dropped_chunk = false;
+    for _ in 0..100 {
+        if received.len() == firmware.len() {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        modules.udp_server.update(&mut main).await?;
+
+        loop {
+            let receive_chunk = client.receive_packet(PACKET_OTA_CHUNK);
+            let Ok(chunk) = tokio::time::timeout(Duration::from_millis(50), receive_chunk).await
+            else {
+                break;
+            };
+            let chunk = chunk?;
+            let offset = u32::from_le_bytes(chunk[0..4].try_into()?) as usize;
+
+            // Drop a chunk once to check that it gets sent again
+            if offset == 1024 && !dropped_chunk {
+                dropped_chunk = true;
+                continue;
+            }
+
+            if offset == received.len() {
+                received.extend(&chunk[4..]);
+            }
+
+            client
+                .send_ota_ack(PACKET_OTA_CHUNK, OtaAckStatus::Ok, received.len() as u32)
+                .await?;
+        }
+    }
+    assert!(dropped_chunk);
+    assert_eq!(received, firmware);
+
+    // Process the last acknowledgement and then send the end packet
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    modules.udp_server.update(&mut main).await?;
+    modules.udp_server.update(&mut main).await?;
+    let receive_end = client.receive_packet(PACKET_OTA_END);
+    tokio::time::timeout(Duration::from_millis(200), receive_end).await??;
+    client
+        .send_ota_ack(PACKET_OTA_END, OtaAckStatus::Ok, firmware.len() as u32)
+        .await?;
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    modules.udp_server.update(&mut main).await?;
+    assert_eq!(
+        main.updates.firmware_update_statuses[&mac],
+        FirmwareUpdateStatus::Done {
+            version: "0.2.0".into()
+        }
+    );
+
+    remove_config_dir(&config_dir)?;
+    Ok(())
+}
+
+type WebsocketClient =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+fn test_device_info() -> DeviceInfo {
+    DeviceInfo {
+        protocol_version: 1,
+        board: "esp32-c3".into(),
+        imu: "bmi160".into(),
+        sensor_count: 1,
+    }
+}
+
+/// Tests don't advertise the server on the network, only the discovery test does
+fn test_main_server() -> MainServer {
+    let mut main = MainServer::default();
+    main.config.udp.advertise_mdns = false;
+    main
+}
+
+/// Connects a device with a single sensor that negotiated the capabilities
+async fn connect_device(
+    client: &mut UdpTrackerClient,
+    main: &mut MainServer,
+    modules: &mut ServerModules,
+    mac: [u8; 6],
+    capabilities: u32,
+) -> anyhow::Result<()> {
+    client
+        .send_handshake_info(mac, &test_device_info(), "0.1.0", capabilities)
+        .await?;
+    client.send_tracker_status(0, TrackerStatus::Ok).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(main).await?;
+    Ok(())
+}
+
+/// Connects to the websocket server like the app does
+async fn connect_websocket(
+    main: &mut MainServer,
+    modules: &mut ServerModules,
+) -> anyhow::Result<WebsocketClient> {
+    let url = format!("ws://{}:{WEBSOCKET_PORT}", Ipv4Addr::LOCALHOST);
+    let connect = tokio::spawn(tokio_tungstenite::connect_async(url));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    main.tick(modules).await;
+    let (websocket, _) = connect.await??;
+    Ok(websocket)
+}
+
+/// Sends a message like the app does and runs the main loop until every module has seen it
+async fn send_websocket(
+    websocket: &mut WebsocketClient,
+    main: &mut MainServer,
+    modules: &mut ServerModules,
+    message: serde_json::Value,
+) -> anyhow::Result<()> {
+    websocket.send(Message::Text(message.to_string())).await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // Modules that are updated before the websocket only see the message in the next update
+    main.tick(modules).await;
+    main.tick(modules).await;
+    assert_eq!(main.updates.error, None);
+    Ok(())
+}
+
+/// Requests from the app go through the same main loop as the server
+async fn test_websocket_requests() -> anyhow::Result<()> {
+    let config_dir = set_config_dir("websocket_test");
+    std::fs::write(get_firmware_dir()?.join("esp32-c3-0.2.0.bin"), [0; 100])?;
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let mut client = UdpTrackerClient::new().await?;
+    let mut websocket = connect_websocket(&mut main, &mut modules).await?;
+
+    let mac = [0x69, 0x42, 0, 0, 0x13, 0];
+    let capabilities = CAPABILITY_OTA | CAPABILITY_COMMANDS;
+    connect_device(&mut client, &mut main, &mut modules, mac, capabilities).await?;
+
+    let message = serde_json::json!({
+        "type": "StartFirmwareUpdate",
+        "mac": "69:42:00:00:13:00",
+    });
+    send_websocket(&mut websocket, &mut main, &mut modules, message).await?;
+    let begin = client.receive_packet(PACKET_OTA_BEGIN);
+    let begin = tokio::time::timeout(Duration::from_millis(200), begin).await??;
+    assert_eq!(begin[0..4], 100_u32.to_le_bytes());
+
+    let message = serde_json::json!({
+        "type": "TrackerCommand",
+        "id": "69:42:00:00:13:00/0",
+        "command": { "type": "BlinkLed" },
+    });
+    send_websocket(&mut websocket, &mut main, &mut modules, message).await?;
+    let command = client.receive_packet(PACKET_COMMAND);
+    tokio::time::timeout(Duration::from_millis(200), command).await??;
+
+    remove_config_dir(&config_dir)?;
+    Ok(())
+}
+
+async fn test_device_commands() -> anyhow::Result<()> {
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let mut client = UdpTrackerClient::new().await?;
+
+    let info = DeviceInfo {
+        sensor_count: 2,
+        ..test_device_info()
+    };
+    client
+        .send_handshake_info(
+            [0x69, 0x42, 0, 0, 0, 5],
+            &info,
+            "0.3.0",
+            CAPABILITY_COMMANDS | CAPABILITY_RELIABLE,
+        )
+        .await?;
+    client.send_tracker_status(1, TrackerStatus::Ok).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    let id: Arc<str> = "69:42:00:00:00:05/1".into();
+    let sample_rate = DeviceCommand::SetSampleRate { rate_hz: 200 };
+    let commands = [
+        (id.clone(), sample_rate.clone()),
+        (id.clone(), DeviceCommand::Reboot),
+        ("69:42:00:00:00:06/0".into(), DeviceCommand::BlinkLed),
+    ];
+    main.requests.tracker_commands.extend(commands);
+    modules.udp_server.update(&mut main).await?;
+
+    let command = client.receive_packet(PACKET_COMMAND).await?;
+    assert_eq!(command, [0, 1, 0x02, 200, 0]);
+    // Rebooting affects the whole device
+    let command = client.receive_packet(PACKET_COMMAND).await?;
+    assert_eq!(command, [1, 0xff, 0x01]);
+
+    // Only acknowledge the status echo and the first command so the reliable channel sends the
+    // second one again
+    client.send_reliable_ack(0).await?;
+    client.send_reliable_ack(1).await?;
+    client.send_command_ack(0, CommandAckStatus::Ok).await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    modules.udp_server.update(&mut main).await?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    modules.udp_server.update(&mut main).await?;
+    let command = client.receive_packet(PACKET_COMMAND).await?;
+    assert_eq!(command, [1, 0xff, 0x01]);
+
+    client.send_reliable_ack(2).await?;
+    client
+        .send_command_ack(1, CommandAckStatus::Unsupported)
+        .await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    let results = std::mem::take(&mut main.updates.command_results);
+    assert_eq!(results.len(), 3);
+    assert_eq!(&*results[0].tracker_id, "69:42:00:00:00:06/0");
+    assert!(results[0].error.is_some());
+    assert_eq!(
+        results[1],
+        CommandResult {
+            tracker_id: id.clone(),
+            command: sample_rate,
+            error: None,
+        }
+    );
+    assert_eq!(results[2].command, DeviceCommand::Reboot);
+    assert!(results[2].error.is_some());
+
+    let command = DeviceCommand::SetSensorMode {
+        mode: SensorMode::NineAxis,
+    };
+    main.requests.tracker_commands.push((id, command));
+    modules.udp_server.update(&mut main).await?;
+    let command = client.receive_packet(PACKET_COMMAND).await?;
+    assert_eq!(command, [2, 1, 0x05, 1]);
+
+    // Devices without the reliable channel get the command sent again until it's acknowledged
+    let mut client = UdpTrackerClient::new().await?;
+    let mac = [0x69, 0x42, 0, 0, 0, 0x0e];
+    connect_device(
+        &mut client,
+        &mut main,
+        &mut modules,
+        mac,
+        CAPABILITY_COMMANDS,
+    )
+    .await?;
+    let id: Arc<str> = "69:42:00:00:00:0e/0".into();
+    main.requests
+        .tracker_commands
+        .push((id.clone(), DeviceCommand::BlinkLed));
+    modules.udp_server.update(&mut main).await?;
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    modules.udp_server.update(&mut main).await?;
+    for _ in 0..2 {
+        let command = client.receive_packet(PACKET_COMMAND).await?;
+        assert_eq!(command, [0, 0, 0x00]);
+    }
+
+    client.send_command_ack(0, CommandAckStatus::Ok).await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    modules.udp_server.update(&mut main).await?;
+    let results = std::mem::take(&mut main.updates.command_results);
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].tracker_id, id);
+    assert_eq!(results[0].error, None);
+    Ok(())
+}
+
+async fn test_reliable_packets() -> anyhow::Result<()> {
+    let config_dir = set_config_dir("reliable_test");
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let mut client = UdpTrackerClient::new().await?;
+
+    let info = test_device_info();
+    client
+        .send_handshake_info(
+            [0x69, 0x42, 0, 0, 0, 7],
+            &info,
+            "0.1.0",
+            CAPABILITY_RELIABLE,
+        )
+        .await?;
+    client.next_reliable(0);
+    client.send_tracker_status(0, TrackerStatus::Ok).await?;
+    // Same sequence again as if the acknowledgement got lost, so it should be ignored
+    client.next_reliable(0);
+    client.send_tracker_status(0, TrackerStatus::Off).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    // Every copy gets acknowledged
+    for _ in 0..2 {
+        assert_eq!(client.receive_packet(PACKET_RELIABLE_ACK).await?, [0, 0]);
+    }
+    let tracker = main.trackers["69:42:00:00:00:07/0"].clone();
+    assert_eq!(tracker.lock().unwrap().info().status, TrackerStatus::Ok);
+
+    // The status is echoed back reliably too
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    modules.udp_server.update(&mut main).await?;
+    let expected = [0, 0, PACKET_TRACKER_STATUS, 0, TrackerStatus::Ok as u8];
+    assert_eq!(client.receive_packet(PACKET_RELIABLE).await?, expected);
+    client.send_reliable_ack(0).await?;
+
+    // The firmware version response is sent until it gets acknowledged
+    client.send_firmware_version("esp32-c3", "0.1.0").await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+    let expected = [1, 0, PACKET_FIRMWARE_VERSION, 0];
+    assert_eq!(client.receive_packet(PACKET_RELIABLE).await?, expected);
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    modules.udp_server.update(&mut main).await?;
+    assert_eq!(client.receive_packet(PACKET_RELIABLE).await?, expected);
+
+    client.send_reliable_ack(1).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    modules.udp_server.update(&mut main).await?;
+    let resent = client.receive_packet(PACKET_RELIABLE);
+    assert!(tokio::time::timeout(Duration::from_millis(100), resent)
+        .await
+        .is_err());
+
+    remove_config_dir(&config_dir)?;
+    Ok(())
+}
+
+async fn test_paired_device() -> anyhow::Result<()> {
+    let config_dir = set_config_dir("pairing_test");
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    main.config.udp.require_pairing = true;
+    modules.udp_server.apply_config(&main.config).await?;
+
+    let mac: Arc<str> = "69:42:00:00:00:08".into();
+    let key = auth::generate_key();
+    main.requests.new_pairings.push((mac.clone(), key));
+
+    let info = test_device_info();
+
+    // Neither unpaired devices nor devices pretending to be the paired one can connect
+    let mut unpaired = UdpTrackerClient::new().await?;
+    let mut spoofer = UdpTrackerClient::new().await?;
+    for (client, mac) in [(&mut unpaired, 9), (&mut spoofer, 8)] {
+        client
+            .send_handshake_info([0x69, 0x42, 0, 0, 0, mac], &info, "0.1.0", 0)
+            .await?;
+        client.send_tracker_status(0, TrackerStatus::Ok).await?;
+    }
+
+    let mut client = UdpTrackerClient::new().await?;
+    client.set_pairing_key(key);
+    client
+        .send_handshake_info([0x69, 0x42, 0, 0, 0, 8], &info, "0.1.0", 0)
+        .await?;
+    let handshake = client.last_sent().to_vec();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    let response = client.receive_packet(PACKET_HANDSHAKE).await?;
+    assert_eq!(&response[0..5], b"MCSVR");
+    client.send_tracker_status(0, TrackerStatus::Ok).await?;
+    let tracker_status = client.last_sent().to_vec();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+    client.receive_packet(PACKET_TRACKER_STATUS).await?;
+
+    // Replaying the handshake and the packets after it doesn't move the device to the sender
+    spoofer.socket.send(&handshake).await?;
+    spoofer.socket.send(&tracker_status).await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    assert!(!main.trackers.contains_key("69:42:00:00:00:09/0"));
+    {
+        let tracker = main.trackers["69:42:00:00:00:08/0"].lock().unwrap();
+        assert!(tracker.info().authenticated);
+        assert_eq!(tracker.info().address, client.socket.local_addr().ok());
+    }
+
+    let mut websocket = connect_websocket(&mut main, &mut modules).await?;
+    let message = serde_json::json!({ "type": "UnpairDevice", "mac": mac });
+    send_websocket(&mut websocket, &mut main, &mut modules, message).await?;
+    let tracker = main.trackers["69:42:00:00:00:08/0"].lock().unwrap();
+    assert!(!tracker.info().authenticated);
+    let paired = std::fs::read_to_string(config_dir.join("paired_devices.json"))?;
+    assert!(!paired.contains("69:42:00:00:00:08"));
+
+    remove_config_dir(&config_dir)?;
+    Ok(())
+}
+
+async fn test_ignored_devices() -> anyhow::Result<()> {
+    let config_dir = set_config_dir("ignore_test");
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let mac = [0x69, 0x42, 0, 0, 0, 0x0a];
+
+    async fn connect(
+        mac: [u8; 6],
+        main: &mut MainServer,
+        modules: &mut ServerModules,
+    ) -> anyhow::Result<UdpTrackerClient> {
+        let mut client = UdpTrackerClient::new().await?;
+        client.send_handshake(mac).await?;
+        client.send_tracker_status(0, TrackerStatus::Ok).await?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        modules.udp_server.update(main).await?;
+        Ok(client)
+    }
+
+    connect(mac, &mut main, &mut modules).await?;
+    let id = "69:42:00:00:00:0a/0";
+    main.trackers[id]
+        .lock()
+        .unwrap()
+        .update_info()
+        .to_be_removed = true;
+
+    // Removing every tracker of the device ignores it
+    modules.udp_server.upkeep(&mut main).await?;
+    main.trackers.remove(id);
+    main.config = main.updates.config.take().unwrap();
+    assert_eq!(
+        main.config.udp.ignored_devices,
+        ["69:42:00:00:00:0a".into()]
+    );
+    modules.udp_server.apply_config(&main.config).await?;
+
+    // Even from a new address
+    connect(mac, &mut main, &mut modules).await?;
+    assert!(!main.trackers.contains_key(id));
+
+    main.config.udp.ignored_devices.clear();
+    modules.udp_server.apply_config(&main.config).await?;
+    connect(mac, &mut main, &mut modules).await?;
+    assert!(main.trackers.contains_key(id));
+
+    // Only the devices from before can connect
+    main.config.udp.only_known_devices = true;
+    modules.udp_server.apply_config(&main.config).await?;
+    connect([0x69, 0x42, 0, 0, 0, 0x0b], &mut main, &mut modules).await?;
+    assert!(!main.trackers.contains_key("69:42:00:00:00:0b/0"));
+    let client = connect(mac, &mut main, &mut modules).await?;
+    assert_eq!(
+        main.trackers[id].lock().unwrap().info().address,
+        client.socket.local_addr().ok()
+    );
+
+    remove_config_dir(&config_dir)?;
+    Ok(())
+}
+
+async fn test_device_approval() -> anyhow::Result<()> {
+    let config_dir = set_config_dir("approval_test");
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    main.config.udp.require_approval = true;
+    modules.udp_server.apply_config(&main.config).await?;
+
+    let mut client = UdpTrackerClient::new().await?;
+    let mut rejected_client = UdpTrackerClient::new().await?;
+    let info = test_device_info();
+    for (client, mac) in [(&mut client, 0x0c), (&mut rejected_client, 0x0d)] {
+        client
+            .send_handshake_info([0x69, 0x42, 0, 0, 0, mac], &info, "0.1.0", 0)
+            .await?;
+        client.send_tracker_status(0, TrackerStatus::Ok).await?;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+    modules.udp_server.upkeep(&mut main).await?;
+
+    // Pending devices don't get any trackers until they're approved
+    assert!(main.trackers.is_empty());
+    let mut pending = main.updates.pending_devices.take().unwrap();
+    pending.sort_by(|a, b| a.mac.cmp(&b.mac));
+    assert_eq!(pending.len(), 2);
+    assert_eq!(&*pending[0].mac, "69:42:00:00:00:0c");
+    assert_eq!(pending[0].address, client.socket.local_addr()?);
+    assert_eq!(pending[0].device_info, Some(info.clone()));
+    assert_eq!(pending[0].firmware_version.as_deref(), Some("0.1.0"));
+
+    let mut websocket = connect_websocket(&mut main, &mut modules).await?;
+    let approve = serde_json::json!({ "type": "ApproveDevice", "mac": "69:42:00:00:00:0c" });
+    send_websocket(&mut websocket, &mut main, &mut modules, approve).await?;
+
+    // A config from the user in the same update doesn't undo the rejection
+    let mac: Arc<str> = "69:42:00:00:00:0d".into();
+    main.requests.device_approvals.push((mac, false));
+    let mut config = main.config.clone();
+    config.main_loop.tick_rate = 90;
+    let update = serde_json::json!({ "type": "UpdateConfig", "config": config });
+    send_websocket(&mut websocket, &mut main, &mut modules, update).await?;
+    assert_eq!(main.config.main_loop.tick_rate, 90);
+    assert_eq!(
+        main.config.udp.approved_devices,
+        ["69:42:00:00:00:0c".into()]
+    );
+    assert_eq!(
+        main.config.udp.ignored_devices,
+        ["69:42:00:00:00:0d".into()]
+    );
+
+    for (client, mac) in [(&mut client, 0x0c), (&mut rejected_client, 0x0d)] {
+        client
+            .send_handshake_info([0x69, 0x42, 0, 0, 0, mac], &info, "0.1.0", 0)
+            .await?;
+        client.send_tracker_status(0, TrackerStatus::Ok).await?;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    assert!(main.trackers.contains_key("69:42:00:00:00:0c/0"));
+    assert!(!main.trackers.contains_key("69:42:00:00:00:0d/0"));
+    assert_eq!(main.updates.pending_devices, None);
+
+    remove_config_dir(&config_dir)?;
+    Ok(())
+}
+
+async fn test_udp_bind_config() -> anyhow::Result<()> {
+    let mut main = test_main_server();
+    main.config.udp.port = UDP_PORT + 1;
+    // Bound with the config right away
+    let mut modules = ServerModules::new(&main.config).await?;
+
+    main.config.udp.multicast_interface = Some("not an interface".into());
+    assert!(modules.udp_server.apply_config(&main.config).await.is_err());
+
+    // Binding to the same port with another address frees the port first
+    main.config.udp.multicast_interface = None;
+    main.config.udp.bind_address = Ipv6Addr::UNSPECIFIED.into();
+    modules.udp_server.apply_config(&main.config).await?;
+
+    // IPv4 devices can still connect to a server on IPv6
+    let mut v6_client =
+        UdpTrackerClient::connect((Ipv6Addr::LOCALHOST, UDP_PORT + 1).into()).await?;
+    let mut v4_client =
+        UdpTrackerClient::connect((Ipv4Addr::LOCALHOST, UDP_PORT + 1).into()).await?;
+    for (client, mac) in [(&mut v6_client, 0x0e), (&mut v4_client, 0x0f)] {
+        client.send_handshake([0x69, 0x42, 0, 0, 0, mac]).await?;
+        client.send_tracker_status(0, TrackerStatus::Ok).await?;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    let tracker = main.trackers["69:42:00:00:00:0e/0"].lock().unwrap();
+    assert_eq!(tracker.info().address, v6_client.socket.local_addr().ok());
+    assert!(main.trackers.contains_key("69:42:00:00:00:0f/0"));
+    Ok(())
+}
+
+async fn test_server_discovery() -> anyhow::Result<()> {
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    main.config.udp.require_pairing = true;
+    main.config.udp.advertise_mdns = true;
+    modules.udp_server.apply_config(&main.config).await?;
+
+    // Also answered when unauthenticated packets are rejected
+    let mut client = UdpTrackerClient::new().await?;
+    client.send_discovery().await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.udp_server.update(&mut main).await?;
+
+    let response = client.receive_packet(PACKET_DISCOVERY).await?;
+    assert_eq!(&response[0..5], b"MCSVR");
+    assert_eq!(response[5], PROTOCOL_VERSION);
+    assert_eq!(response[6..8], UDP_PORT.to_le_bytes());
+    assert_eq!(response[8..10], WEBSOCKET_PORT.to_le_bytes());
+
+    // Browse for the server like a companion app would
+    let browser = mdns_sd::ServiceDaemon::new()?;
+    let events = browser.browse(MDNS_SERVICE_TYPE)?;
+    // Other servers on the network are advertised with the same service
+    let fullname = format!("{}.{MDNS_SERVICE_TYPE}", discovery::instance_name());
+    let service = tokio::task::spawn_blocking(move || loop {
+        if let mdns_sd::ServiceEvent::ServiceResolved(service) =
+            events.recv_timeout(Duration::from_secs(5))?
+        {
+            if service.get_fullname() == fullname {
+                return anyhow::Ok(service);
+            }
+        }
+    })
+    .await??;
+    assert_eq!(service.get_port(), UDP_PORT);
+    let websocket_port = WEBSOCKET_PORT.to_string();
+    assert_eq!(
+        service.get_property_val_str("websocket_port"),
+        Some(&*websocket_port)
+    );
+    browser.shutdown()?;
+
     Ok(())
 }
 
 async fn test_config() -> anyhow::Result<()> {
-    let config_dir = std::env::current_dir()?.join("config_test");
-    std::env::set_var("MICAP_CONFIG_DIR", config_dir.clone());
+    let config_dir = set_config_dir("config_test");
 
     let tracker_config = TrackerConfig {
         name: Some("hello".to_string()),
@@ -82,14 +1107,314 @@ async fn test_config() -> anyhow::Result<()> {
 
     let mut global_config = GlobalConfig::default();
     global_config.trackers.insert("test".into(), tracker_config);
+    global_config.udp.advertise_mdns = false;
     global_config.save()?;
 
-    let mut main = MainServer::default();
-    let mut modules = ServerModules::new().await?;
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
     main.config = GlobalConfig::load()?;
     main.apply_config(&mut modules).await?;
 
     assert_eq!(main.config, global_config);
-    std::fs::remove_dir_all(config_dir)?;
+    remove_config_dir(&config_dir)?;
+    Ok(())
+}
+
+async fn test_steamvr() -> anyhow::Result<()> {
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+
+    main.config.steamvr.enabled = true;
+    let mut driver = SteamVrDriverClient::new(main.config.steamvr.send_port).await?;
+    main.apply_config(&mut modules).await?;
+
+    main.skeleton_manager.update();
+    main.outputs
+        .update(&main.skeleton_manager, &main.config)
+        .await?;
+
+    let poses = tokio::time::timeout(Duration::from_millis(200), driver.receive_poses()).await??;
+    assert_eq!(poses.len(), main.config.steamvr.bones_to_send.len());
+    for (pose, location) in poses.iter().zip(&main.config.steamvr.bones_to_send) {
+        let bone = &main.skeleton_manager.bones[location];
+        assert_eq!(pose.location, *location);
+        assert_eq!(pose.position, bone.tail_world_position);
+        assert_eq!(pose.orientation, bone.world_orientation);
+    }
+
+    Ok(())
+}
+
+async fn test_vmc_receiver() -> anyhow::Result<()> {
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+
+    main.config.vmc.receive_enabled = true;
+    main.apply_config(&mut modules).await?;
+
+    let orientation = glam::Quat::from_xyzw(0.5, 0.5, 0.5, 0.5);
+    let args = [
+        0.,
+        1.,
+        0.,
+        orientation.x,
+        orientation.y,
+        orientation.z,
+        orientation.w,
+    ];
+    let packet = rosc::OscPacket::Message(rosc::OscMessage {
+        addr: "/VMC/Ext/Tra/Pos".to_string(),
+        args: std::iter::once(rosc::OscType::String("tracker".to_string()))
+            .chain(args.map(rosc::OscType::Float))
+            .collect(),
+    });
+
+    let socket = tokio::net::UdpSocket::bind((std::net::Ipv4Addr::LOCALHOST, 0)).await?;
+    let address = (std::net::Ipv4Addr::LOCALHOST, main.config.vmc.receive_port);
+    socket
+        .send_to(&rosc::encoder::encode(&packet)?, address)
+        .await?;
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    for source in &mut modules.input_sources {
+        source.update(&mut main).await?;
+    }
+
+    let tracker = main.trackers["vmc/tracker"].lock().unwrap();
+    assert_eq!(tracker.info().source, Some(TrackerSource::Vmc));
+    assert_eq!(tracker.info().status, TrackerStatus::Ok);
+    assert_eq!(tracker.data().orientation, glam::quat(0.5, 0.5, -0.5, -0.5));
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn test_serial_tracker() -> anyhow::Result<()> {
+    use crate::{
+        serial::slip,
+        udp::packet::{
+            UdpPacketHandshake, PACKET_HANDSHAKE, PACKET_TRACKER_DATA, PACKET_TRACKER_STATUS,
+        },
+    };
+    use std::io::{Read, Write};
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let (mut device_port, server_port) = serialport::TTYPort::pair()?;
+    modules.serial_manager.add_port("test".into(), server_port);
+
+    // Packets have a zero packet number since it's not used over serial
+    let mut handshake = vec![PACKET_HANDSHAKE, 0, 0, 0, 0];
+    handshake.extend(b"MCDEV");
+    handshake.extend([0x69, 0x42, 0, 0, 0, 1]);
+    let status = [
+        PACKET_TRACKER_STATUS,
+        0,
+        0,
+        0,
+        0,
+        0,
+        TrackerStatus::Ok as u8,
+    ];
+    let mut data = vec![PACKET_TRACKER_DATA, 0, 0, 0, 0, 0];
+    data.extend(
+        [1_f32, 2., 3., 4., 1., 2., 3.]
+            .iter()
+            .flat_map(|x| x.to_le_bytes()),
+    );
+    data.push(0xff);
+
+    // Logs can be mixed in with the packets
+    let mut bytes = b"Booting\n".to_vec();
+    slip::encode(&handshake, &mut bytes);
+    bytes.extend(b"Connected\n");
+    slip::encode(&status, &mut bytes);
+    slip::encode(&data, &mut bytes);
+    device_port.write_all(&bytes)?;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.serial_manager.update(&mut main).await?;
+
+    let received = modules.serial_manager.take_received();
+    assert_eq!(
+        received["test"].logs,
+        [Box::from("Booting"), Box::from("Connected")]
+    );
+
+    {
+        let tracker = main.trackers["69:42:00:00:00:01/0"].lock().unwrap();
+        assert_eq!(tracker.info().source, Some(TrackerSource::Serial));
+        assert_eq!(tracker.info().status, TrackerStatus::Ok);
+        assert_eq!(tracker.data().orientation, glam::quat(-1., 2., 3., -4.));
+    }
+
+    let mut expected = Vec::new();
+    slip::encode(UdpPacketHandshake::SERVER_RESPONSE, &mut expected);
+    slip::encode(
+        &[PACKET_TRACKER_STATUS, 0, TrackerStatus::Ok as u8],
+        &mut expected,
+    );
+    let mut responses = vec![0; expected.len()];
+    device_port.read_exact(&mut responses)?;
+    assert_eq!(responses, expected);
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn test_serial_command() -> anyhow::Result<()> {
+    use crate::serial::command::{SerialCommand, SerialResponse};
+    use std::io::{Read, Write};
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let (mut device_port, server_port) = serialport::TTYPort::pair()?;
+    modules.serial_manager.add_port("test".into(), server_port);
+
+    modules
+        .serial_manager
+        .send_command("test", &SerialCommand::GetDeviceInfo)?;
+
+    // Act like the device
+    let mut command = [0; b"DeviceInfo\n".len()];
+    device_port.read_exact(&mut command)?;
+    assert_eq!(&command, b"DeviceInfo\n");
+    device_port.write_all(b"DeviceInfo\0aa:bb:cc:dd:ee:ff\x000.1.0\0esp8266\n")?;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.serial_manager.update(&mut main).await?;
+
+    let received = modules.serial_manager.take_received();
+    assert_eq!(
+        received["test"].responses,
+        [SerialResponse::DeviceInfo {
+            mac: "aa:bb:cc:dd:ee:ff".into(),
+            firmware_version: "0.1.0".into(),
+            board: "esp8266".into(),
+        }]
+    );
+
+    // Pairing gives the device a new key
+    modules.serial_manager.pair("test")?;
+    let mut command = [0; b"Pair\0\n".len() + 64];
+    device_port.read_exact(&mut command)?;
+    assert!(command.starts_with(b"Pair\0"));
+    device_port.write_all(b"Paired\0AA:BB:CC:DD:EE:FF\n")?;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    modules.serial_manager.update(&mut main).await?;
+    let (mac, key) = &main.requests.new_pairings[0];
+    assert_eq!(&**mac, "aa:bb:cc:dd:ee:ff");
+    assert_eq!(auth::key_to_hex(key).as_bytes(), &command[5..69]);
+
+    // The udp server saves the pairing in the next update
+    main.tick(&mut modules).await;
+    let paired = std::fs::read_to_string(config::get_config_dir()?.join("paired_devices.json"))?;
+    assert!(paired.contains("aa:bb:cc:dd:ee:ff"));
+
+    // Should time out when the device doesn't respond
+    modules
+        .serial_manager
+        .send_command("test", &SerialCommand::SelfTest)?;
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    modules.serial_manager.update(&mut main).await?;
+    let received = modules.serial_manager.take_received();
+    assert_eq!(
+        received["test"].responses.last(),
+        Some(&SerialResponse::CommandError {
+            command: "SelfTest".into(),
+            error: "Device did not respond".into(),
+        })
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn test_serial_flash() -> anyhow::Result<()> {
+    use crate::{
+        firmware::FirmwareImage,
+        serial::{flasher::*, slip},
+    };
+    use serialport::SerialPort;
+    use std::io::{Read, Write};
+
+    let mut main = test_main_server();
+    let mut modules = ServerModules::new(&main.config).await?;
+    let (mut device_port, server_port) = serialport::TTYPort::pair()?;
+    modules.serial_manager.add_port("test".into(), server_port);
+
+    let firmware = (0..3000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let image = FirmwareImage::new("0.2.0".into(), firmware.clone())?;
+    modules.serial_manager.flash_firmware("test", image)?;
+
+    // Act like the bootloader of an esp32-c3 and respond to every command
+    let mut decoder = slip::SlipDecoder::default();
+    let mut flashed: Vec<u8> = Vec::new();
+    let mut attached = false;
+    let mut status = None;
+    for _ in 0..100 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        modules.serial_manager.update(&mut main).await?;
+        if let Some(received) = modules.serial_manager.take_received().remove("test") {
+            status = received.flash_status.or(status);
+        }
+        if let Some(FirmwareUpdateStatus::Done { .. } | FirmwareUpdateStatus::Failed { .. }) =
+            status
+        {
+            break;
+        }
+
+        let mut bytes = vec![0; device_port.bytes_to_read()? as usize];
+        device_port.read_exact(&mut bytes)?;
+
+        let mut response = Vec::new();
+        for byte in bytes {
+            let Some(frame) = decoder.push(byte) else {
+                continue;
+            };
+
+            let command = frame[1];
+            let checksum = u32::from_le_bytes(frame[4..8].try_into()?);
+            let data = &frame[8..];
+            if command == COMMAND_FLASH_DATA {
+                let block = &data[16..];
+                assert_eq!(
+                    checksum,
+                    block.iter().fold(0xef, |sum, byte| sum ^ byte) as u32
+                );
+                flashed.extend(block);
+            }
+
+            let value = match command {
+                COMMAND_READ_REG => {
+                    assert_eq!(data, CHIP_DETECT_MAGIC_REG.to_le_bytes());
+                    0x6921_506f_u32
+                }
+                _ => 0,
+            };
+            attached |= command == COMMAND_SPI_ATTACH;
+            // The flash has to be attached and the begin command has the encrypted flag
+            let valid = command != COMMAND_FLASH_BEGIN || (attached && data.len() == 20);
+            let (status, error) = if valid { (0, 0) } else { (1, 0x05) };
+
+            // The bootloader responds to sync more than once
+            let count = if command == COMMAND_SYNC { 2 } else { 1 };
+            for _ in 0..count {
+                let mut frame = vec![0x01, command, 2, 0];
+                frame.extend(value.to_le_bytes());
+                frame.extend([status, error]);
+                slip::encode(&frame, &mut response);
+            }
+        }
+        device_port.write_all(&response)?;
+    }
+
+    assert_eq!(
+        status,
+        Some(FirmwareUpdateStatus::Done {
+            version: "0.2.0".into()
+        })
+    );
+    // The last block gets padded
+    assert_eq!(flashed[0..firmware.len()], firmware);
+    assert!(flashed[firmware.len()..].iter().all(|byte| *byte == 0xff));
     Ok(())
 }
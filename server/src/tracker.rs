@@ -14,6 +14,26 @@ pub enum TrackerStatus {
     TimedOut,
 }
 
+/// Where the tracker data is coming from
+#[derive(PartialEq, Clone, Copy, Serialize, Debug, TS)]
+pub enum TrackerSource {
+    Udp,
+    Serial,
+    Vmc,
+    Synthetic,
+    /// Playback of a BVH recording
+    Bvh,
+}
+
+/// Sent by the device when it connects
+#[derive(Clone, Debug, PartialEq, Serialize, TS)]
+pub struct DeviceInfo {
+    pub protocol_version: u8,
+    pub board: Box<str>,
+    pub imu: Box<str>,
+    pub sensor_count: u8,
+}
+
 #[derive(Clone, Debug, Default, Serialize, TS)]
 pub struct TrackerInfo {
     pub to_be_removed: bool,
@@ -23,6 +43,26 @@ pub struct TrackerInfo {
     pub battery_level: f32,
     #[ts(optional)]
     pub address: Option<SocketAddr>,
+    /// None when the tracker only exists in the config and hasn't connected yet
+    #[ts(optional)]
+    pub source: Option<TrackerSource>,
+    #[ts(optional)]
+    pub device: Option<DeviceInfo>,
+    #[ts(optional)]
+    pub firmware_version: Option<Box<str>>,
+    /// A newer firmware version that can be installed on the device
+    #[ts(optional)]
+    pub firmware_update: Option<Box<str>>,
+    #[ts(optional)]
+    pub wifi_rssi: Option<i8>,
+    /// In celsius
+    #[ts(optional)]
+    pub imu_temperature: Option<f32>,
+    /// Packets from the device are authenticated with its pairing key
+    pub authenticated: bool,
+    /// Fraction of the packets from the device that were lost since the last upkeep
+    #[ts(optional)]
+    pub packet_loss: Option<f32>,
 }
 
 #[derive(Default, Debug, Serialize, TS)]
@@ -37,7 +77,8 @@ pub struct TrackerData {
 
 #[derive(Debug)]
 pub struct TrackerInternal {
-    pub time_data_last_updated: Instant,
+    /// Time the latest data was measured at, None until the first data
+    pub time_data_last_updated: Option<Instant>,
     pub velocity: glam::Vec3A,
     pub was_updated: bool,
     /// Offset orientation from when skeleton orientation was reset
@@ -49,7 +90,7 @@ pub struct TrackerInternal {
 impl Default for TrackerInternal {
     fn default() -> Self {
         Self {
-            time_data_last_updated: Instant::now(),
+            time_data_last_updated: None,
             velocity: glam::Vec3A::default(),
             was_updated: false,
             orientation_offset: glam::Quat::IDENTITY,
@@ -72,16 +113,32 @@ pub struct Tracker {
 
 impl Tracker {
     pub fn update_data(&mut self, raw_acceleration: glam::Vec3A, raw_orientation: glam::Quat) {
+        self.update_data_at(raw_acceleration, raw_orientation, Instant::now());
+    }
+
+    /// Updates with data that was measured at the time instead of now, data older than the latest
+    /// is ignored since it arrived out of order
+    pub fn update_data_at(
+        &mut self,
+        raw_acceleration: glam::Vec3A,
+        raw_orientation: glam::Quat,
+        time: Instant,
+    ) {
+        let last_time = self.internal.time_data_last_updated.unwrap_or(time);
+        if time < last_time {
+            return;
+        }
+
         let mounted_orientation = raw_orientation * self.internal.mount_offset;
         self.internal.mounted_orientation = mounted_orientation;
         self.data.orientation = self.internal.orientation_offset * mounted_orientation;
         self.data.acceleration = raw_acceleration;
 
-        let delta = self.internal.time_data_last_updated.elapsed().as_secs_f32();
+        let delta = (time - last_time).as_secs_f32();
         self.internal.velocity += self.data.acceleration * delta;
         self.data.position += self.internal.velocity * delta;
 
-        self.internal.time_data_last_updated = Instant::now();
+        self.internal.time_data_last_updated = Some(time);
         self.internal.was_updated = true;
     }
 
@@ -142,3 +199,34 @@ pub struct TrackerConfig {
     #[ts(optional)]
     pub location: Option<BoneLocation>,
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn ignore_older_data() {
+        let mut tracker = Tracker::default();
+        let time = Instant::now();
+        let acceleration = glam::vec3a(1., 0., 0.);
+        tracker.update_data_at(acceleration, glam::Quat::IDENTITY, time);
+        tracker.update_data_at(
+            acceleration,
+            glam::Quat::IDENTITY,
+            time + Duration::from_secs(1),
+        );
+
+        // Arrived late so it would move the clock backwards
+        let late = glam::Quat::from_axis_angle(glam::Vec3::X, 1.);
+        tracker.update_data_at(glam::Vec3A::ZERO, late, time);
+        assert_eq!(tracker.data().orientation, glam::Quat::IDENTITY);
+        assert_eq!(tracker.data().acceleration, acceleration);
+        assert_eq!(
+            tracker.internal.time_data_last_updated,
+            Some(time + Duration::from_secs(1))
+        );
+        assert_eq!(tracker.internal.velocity, acceleration);
+    }
+}
@@ -0,0 +1,232 @@
+//! Authentication of the packets from and to paired devices
+//!
+//! A device gets paired over serial, where the server gives it a random key. After that every
+//! packet is wrapped as `PACKET_AUTHENTICATED`, the packet and a tag (16 bytes), where the tag is
+//! the start of the HMAC-SHA256 of the session nonce, the direction (0 from the device, 1 from
+//! the server) and everything before the tag. The direction keeps packets from being reflected
+//! back to whoever sent them. Packets from the device have their packet number before the
+//! wrapped packet id.
+//!
+//! The handshake has no session nonce yet, so the device appends a random nonce (8 bytes) to it.
+//! The server appends its own nonce to the handshake response, which is tagged with the device's
+//! nonce. The session nonce is then the device nonce followed by the server nonce, so packets
+//! from an earlier session can't be replayed. Since a recorded handshake can be sent again, the
+//! server only connects the device once it has received a packet in the new session.
+
+use std::{collections::HashMap, sync::Arc};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{config::get_config_dir, udp::packet::PACKET_AUTHENTICATED};
+
+pub const TAG_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 8;
+
+pub type PairingKey = [u8; 32];
+
+pub fn generate_key() -> PairingKey {
+    rand::random()
+}
+
+pub fn generate_nonce() -> [u8; NONCE_SIZE] {
+    rand::random()
+}
+
+pub fn key_to_hex(key: &PairingKey) -> String {
+    key.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn key_from_hex(hex: &str) -> Option<PairingKey> {
+    let mut key = [0; 32];
+    if hex.len() != key.len() * 2 {
+        return None;
+    }
+
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Keys of the paired devices, saved in the config directory
+#[derive(Default)]
+pub struct PairedDevices {
+    keys: HashMap<Arc<str>, PairingKey>,
+}
+
+impl PairedDevices {
+    const FILE_NAME: &str = "paired_devices.json";
+
+    pub fn load() -> anyhow::Result<Self> {
+        let path = get_config_dir()?.join(Self::FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let hex_keys: HashMap<Arc<str>, Box<str>> =
+            serde_json::from_reader(std::fs::File::open(path)?)?;
+        let keys = hex_keys
+            .into_iter()
+            .filter_map(|(mac, key)| Some((mac, key_from_hex(&key)?)))
+            .collect();
+        Ok(Self { keys })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let hex_keys: HashMap<_, _> = self
+            .keys
+            .iter()
+            .map(|(mac, key)| (mac, key_to_hex(key)))
+            .collect();
+        let file = std::fs::File::create(get_config_dir()?.join(Self::FILE_NAME))?;
+        serde_json::to_writer_pretty(file, &hex_keys)?;
+        Ok(())
+    }
+
+    pub fn get(&self, mac: &str) -> Option<&PairingKey> {
+        self.keys.get(mac)
+    }
+
+    pub fn insert(&mut self, mac: Arc<str>, key: PairingKey) -> anyhow::Result<()> {
+        log::info!("Paired device {mac}");
+        self.keys.insert(mac, key);
+        self.save()
+    }
+
+    pub fn remove(&mut self, mac: &str) -> anyhow::Result<()> {
+        if self.keys.remove(mac).is_some() {
+            log::info!("Unpaired device {mac}");
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+/// Who sent an authenticated packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    FromDevice = 0,
+    FromServer = 1,
+}
+
+#[derive(Clone)]
+pub struct AuthSession {
+    key: PairingKey,
+    nonce: Vec<u8>,
+}
+
+impl AuthSession {
+    pub fn new(key: PairingKey, nonce: &[u8]) -> Self {
+        Self {
+            key,
+            nonce: nonce.to_vec(),
+        }
+    }
+
+    fn mac(&self, direction: Direction, bytes: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("Any key size works");
+        mac.update(&self.nonce);
+        mac.update(&[direction as u8]);
+        mac.update(bytes);
+        mac
+    }
+
+    /// Wraps the packet and adds its tag
+    pub fn seal(&self, direction: Direction, packet: &[u8]) -> Vec<u8> {
+        let mut sealed = vec![PACKET_AUTHENTICATED];
+        sealed.extend(packet);
+        let tag = self.mac(direction, &sealed).finalize().into_bytes();
+        sealed.extend(&tag[0..TAG_SIZE]);
+        sealed
+    }
+
+    /// Checks the tag and returns what was wrapped
+    pub fn open<'a>(&self, direction: Direction, bytes: &'a [u8]) -> anyhow::Result<&'a [u8]> {
+        let (body, tag) = split_tag(bytes)?;
+        self.mac(direction, body)
+            .verify_truncated_left(tag)
+            .map_err(|_| anyhow::anyhow!("Packet failed authentication"))?;
+        Ok(&body[1..])
+    }
+}
+
+/// Splits an authenticated packet into everything that is tagged and the tag
+fn split_tag(bytes: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+    if bytes.first() != Some(&PACKET_AUTHENTICATED) || bytes.len() < TAG_SIZE + 1 {
+        anyhow::bail!("Packet is not authenticated");
+    }
+    Ok(bytes.split_at(bytes.len() - TAG_SIZE))
+}
+
+/// Returns what a device has wrapped without checking the tag
+pub fn peek(bytes: &[u8]) -> anyhow::Result<&[u8]> {
+    Ok(&split_tag(bytes)?.0[1..])
+}
+
+/// Returns the id of a packet, for authenticated packets the id of the one that was wrapped
+/// without checking the tag
+pub fn packet_id(bytes: &[u8]) -> Option<u8> {
+    match bytes.first() {
+        Some(&PACKET_AUTHENTICATED) => peek(bytes).ok()?.get(4).copied(),
+        packet_id => packet_id.copied(),
+    }
+}
+
+/// Turns what a device has wrapped back into a normal packet,
+/// which has the packet number after the packet id
+pub fn device_packet(body: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (Some(packet_number), Some((packet_id, data))) =
+        (body.get(0..4), body.get(4..).and_then(<[u8]>::split_first))
+    else {
+        anyhow::bail!("Authenticated packet is too short");
+    };
+
+    let mut packet = vec![*packet_id];
+    packet.extend(packet_number);
+    packet.extend(data);
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::udp::packet::PACKET_TRACKER_DATA;
+
+    #[test]
+    fn seal_open() {
+        let key = generate_key();
+        let session = AuthSession::new(key, b"nonce");
+        let sealed = session.seal(Direction::FromDevice, b"packet");
+        assert_eq!(
+            session.open(Direction::FromDevice, &sealed).unwrap(),
+            b"packet"
+        );
+
+        let mut tampered = sealed.clone();
+        tampered[2] ^= 1;
+        assert!(session.open(Direction::FromDevice, &tampered).is_err());
+        assert!(AuthSession::new(key, b"other")
+            .open(Direction::FromDevice, &sealed)
+            .is_err());
+        assert!(AuthSession::new(generate_key(), b"nonce")
+            .open(Direction::FromDevice, &sealed)
+            .is_err());
+        // Packets can't be reflected back to the one that sent them
+        assert!(session.open(Direction::FromServer, &sealed).is_err());
+
+        assert_eq!(key_from_hex(&key_to_hex(&key)), Some(key));
+    }
+
+    #[test]
+    fn wrapped_packet_id() {
+        let session = AuthSession::new(generate_key(), b"nonce");
+        let sealed = session.seal(Direction::FromDevice, &[1, 0, 0, 0, PACKET_TRACKER_DATA]);
+        assert_eq!(packet_id(&sealed), Some(PACKET_TRACKER_DATA));
+        assert_eq!(
+            packet_id(&[PACKET_TRACKER_DATA, 1]),
+            Some(PACKET_TRACKER_DATA)
+        );
+        assert_eq!(packet_id(&[]), None);
+    }
+}
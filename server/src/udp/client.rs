@@ -1,13 +1,19 @@
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 
 use tokio::net::UdpSocket;
 
 use crate::{
-    tracker::TrackerStatus,
+    tracker::{DeviceInfo, TrackerStatus},
     udp::{
+        auth::{self, AuthSession, Direction, PairingKey, NONCE_SIZE},
+        diagnostics::{DeviceLogLevel, ResetReason},
         packet::{
-            UdpTrackerData, PACKET_BATTERY_LEVEL, PACKET_HANDSHAKE, PACKET_PING_PONG,
-            PACKET_TRACKER_DATA, PACKET_TRACKER_STATUS,
+            CommandAckStatus, DataEncoding, OtaAckStatus, UdpTrackerData, ACCELERATION_SCALE,
+            PACKET_AUTHENTICATED, PACKET_BATTERY_LEVEL, PACKET_COMMAND_ACK, PACKET_DEVICE_ERROR,
+            PACKET_DEVICE_LOG, PACKET_DIAGNOSTICS, PACKET_DISCOVERY, PACKET_FIRMWARE_VERSION,
+            PACKET_HANDSHAKE, PACKET_OTA_ACK, PACKET_PING_PONG, PACKET_RELIABLE,
+            PACKET_RELIABLE_ACK, PACKET_TRACKER_DATA, PACKET_TRACKER_SAMPLES,
+            PACKET_TRACKER_STATUS,
         },
         server::UDP_PORT,
     },
@@ -18,16 +24,39 @@ pub struct UdpTrackerClient {
     pub socket: UdpSocket,
     packet_number: u32,
     buffer: Vec<u8>,
+    /// Sequence the next packet is sent reliably with
+    reliable_sequence: Option<u16>,
+    pairing_key: Option<PairingKey>,
+    /// Nonce sent in the handshake, until the server has responded with its nonce
+    handshake_nonce: Option<[u8; NONCE_SIZE]>,
+    session: Option<AuthSession>,
+    /// Encoding of the tracker data, has to match the capabilities sent in the handshake
+    data_encoding: DataEncoding,
+    /// Bytes of the last datagram that was sent
+    last_sent: Vec<u8>,
 }
 
 impl UdpTrackerClient {
     pub async fn new() -> anyhow::Result<Self> {
-        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
-        socket.connect((Ipv4Addr::LOCALHOST, UDP_PORT)).await?;
+        Self::connect((Ipv4Addr::LOCALHOST, UDP_PORT).into()).await
+    }
+
+    pub async fn connect(server: SocketAddr) -> anyhow::Result<Self> {
+        let socket = match server {
+            SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?,
+            SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).await?,
+        };
+        socket.connect(server).await?;
         Ok(Self {
             socket,
             packet_number: 0,
             buffer: Vec::new(),
+            reliable_sequence: None,
+            pairing_key: None,
+            handshake_nonce: None,
+            session: None,
+            data_encoding: DataEncoding::Full,
+            last_sent: Vec::new(),
         })
     }
 
@@ -38,14 +67,80 @@ impl UdpTrackerClient {
         self.send_buffer().await
     }
 
+    pub async fn send_discovery(&mut self) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_DISCOVERY);
+        self.buffer.extend(b"MCDIS");
+        self.send_buffer().await
+    }
+
+    /// Sends a handshake with the device info for protocol version 1 and above
+    pub async fn send_handshake_info(
+        &mut self,
+        mac: [u8; 6],
+        info: &DeviceInfo,
+        firmware_version: &str,
+        capabilities: u32,
+    ) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_HANDSHAKE);
+        self.buffer.extend(b"MCDEV");
+        self.buffer.extend(mac);
+        self.buffer.push(info.protocol_version);
+        self.buffer.extend(capabilities.to_le_bytes());
+        self.buffer.push(info.sensor_count);
+        for string in [&*info.board, &info.imu, firmware_version] {
+            self.push_string(string);
+        }
+
+        if self.pairing_key.is_some() {
+            let nonce = auth::generate_nonce();
+            self.buffer.extend(nonce);
+            self.handshake_nonce = Some(nonce);
+        }
+        self.send_buffer().await
+    }
+
+    pub async fn send_log(&mut self, level: DeviceLogLevel, message: &str) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_DEVICE_LOG);
+        self.buffer.push(level as u8);
+        self.buffer.extend(message.as_bytes());
+        self.send_buffer().await
+    }
+
+    pub async fn send_error(&mut self, tracker_index: u8, code: u16) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_DEVICE_ERROR);
+        self.buffer.push(tracker_index);
+        self.buffer.extend(code.to_le_bytes());
+        self.send_buffer().await
+    }
+
+    /// Temperatures are the tracker index and the imu temperature
+    pub async fn send_diagnostics(
+        &mut self,
+        wifi_rssi: i8,
+        reset_reason: ResetReason,
+        temperatures: &[(u8, f32)],
+    ) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_DIAGNOSTICS);
+        self.buffer.extend(wifi_rssi.to_le_bytes());
+        self.buffer.push(reset_reason as u8);
+        for (index, temperature) in temperatures {
+            self.buffer.push(*index);
+            self.buffer.extend(temperature.to_le_bytes());
+        }
+        self.buffer.push(0xff);
+        self.send_buffer().await
+    }
+
     pub async fn send_battery_level(&mut self, level: f32) -> anyhow::Result<()> {
         self.begin_packet(PACKET_BATTERY_LEVEL);
         self.buffer.extend(level.to_le_bytes());
         self.send_buffer().await
     }
 
+    /// Pongs are sent with packet number 0 like the firmware does
     pub async fn send_ping(&mut self, id: u8) -> anyhow::Result<()> {
-        self.begin_packet(PACKET_PING_PONG);
+        self.buffer.push(PACKET_PING_PONG);
+        self.buffer.extend(0_u32.to_le_bytes());
         self.buffer.push(id);
         self.send_buffer().await
     }
@@ -66,27 +161,239 @@ impl UdpTrackerClient {
 
         for data in datas {
             self.buffer.push(data.tracker_index);
-            let orien = data.orientation.to_array();
-            let accel = data.acceleration.to_array();
+            self.push_tracker_data(data);
+        }
+
+        self.buffer.push(0xff);
+        self.send_buffer().await
+    }
 
-            self.buffer
-                .extend(orien.iter().flat_map(|x| x.to_le_bytes()));
-            self.buffer
-                .extend(accel.iter().flat_map(|x| x.to_le_bytes()));
+    /// Sends the samples with how long before the timestamp they were measured,
+    /// samples of the same tracker next to each other are sent together
+    pub async fn send_tracker_samples(
+        &mut self,
+        timestamp_us: u64,
+        samples: &[(u32, &UdpTrackerData)],
+    ) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_TRACKER_SAMPLES);
+        self.buffer.extend(timestamp_us.to_le_bytes());
+
+        for tracker_samples in samples.chunk_by(|(_, a), (_, b)| a.tracker_index == b.tracker_index)
+        {
+            self.buffer.push(tracker_samples[0].1.tracker_index);
+            self.buffer.push(tracker_samples.len() as u8);
+            for (age_us, data) in tracker_samples {
+                self.buffer.extend(age_us.to_le_bytes());
+                self.push_tracker_data(data);
+            }
         }
 
         self.buffer.push(0xff);
         self.send_buffer().await
     }
 
+    pub async fn send_firmware_version(
+        &mut self,
+        board: &str,
+        version: &str,
+    ) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_FIRMWARE_VERSION);
+        self.push_string(board);
+        self.push_string(version);
+        self.send_buffer().await
+    }
+
+    pub async fn send_ota_ack(
+        &mut self,
+        packet_type: u8,
+        status: OtaAckStatus,
+        offset: u32,
+    ) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_OTA_ACK);
+        self.buffer.push(packet_type);
+        self.buffer.push(status as u8);
+        self.buffer.extend(offset.to_le_bytes());
+        self.send_buffer().await
+    }
+
+    pub async fn send_command_ack(
+        &mut self,
+        sequence: u8,
+        status: CommandAckStatus,
+    ) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_COMMAND_ACK);
+        self.buffer.push(sequence);
+        self.buffer.push(status as u8);
+        self.send_buffer().await
+    }
+
+    /// Authenticates the packets from the next handshake on
+    pub fn set_pairing_key(&mut self, key: PairingKey) {
+        self.pairing_key = Some(key);
+    }
+
+    pub fn set_data_encoding(&mut self, encoding: DataEncoding) {
+        self.data_encoding = encoding;
+    }
+
+    /// Wraps the next packet in a reliable packet with the sequence
+    pub fn next_reliable(&mut self, sequence: u16) {
+        self.reliable_sequence = Some(sequence);
+    }
+
+    pub async fn send_reliable_ack(&mut self, sequence: u16) -> anyhow::Result<()> {
+        self.begin_packet(PACKET_RELIABLE_ACK);
+        self.buffer.extend(sequence.to_le_bytes());
+        self.send_buffer().await
+    }
+
+    /// Waits for a packet from the server with the packet id, skipping any other packets
+    /// Reliable packets are unwrapped unless waiting on `PACKET_RELIABLE`, without acknowledging
+    pub async fn receive_packet(&mut self, id: u8) -> anyhow::Result<Vec<u8>> {
+        let mut buffer = [0; 2048];
+        loop {
+            let amount = self.socket.recv(&mut buffer).await?;
+            let mut packet = &buffer[0..amount];
+            if packet.first() == Some(&PACKET_AUTHENTICATED) {
+                packet = self.open(packet)?;
+            }
+            if id != PACKET_RELIABLE && packet.first() == Some(&PACKET_RELIABLE) {
+                packet = packet.get(3..).unwrap_or_default();
+            }
+
+            if packet.first() == Some(&id) {
+                return Ok(packet[1..].to_vec());
+            }
+        }
+    }
+
+    fn open<'a>(&mut self, bytes: &'a [u8]) -> anyhow::Result<&'a [u8]> {
+        let key = self
+            .pairing_key
+            .ok_or_else(|| anyhow::anyhow!("Client is not paired"))?;
+
+        // The handshake response is authenticated with the nonce from the handshake
+        let Some(device_nonce) = self.handshake_nonce.take() else {
+            let session = self
+                .session
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("No authenticated session"))?;
+            return session.open(Direction::FromServer, bytes);
+        };
+
+        let packet = AuthSession::new(key, &device_nonce).open(Direction::FromServer, bytes)?;
+        let server_nonce = &packet[packet.len().saturating_sub(NONCE_SIZE)..];
+        self.session = Some(AuthSession::new(
+            key,
+            &[&device_nonce, server_nonce].concat(),
+        ));
+        Ok(packet)
+    }
+
+    fn push_string(&mut self, string: &str) {
+        self.buffer.push(string.len() as u8);
+        self.buffer.extend(string.as_bytes());
+    }
+
+    /// Skips packet numbers as if the packets were lost
+    pub fn skip_packets(&mut self, amount: u32) {
+        self.packet_number = self.packet_number.wrapping_add(amount);
+    }
+
     fn begin_packet(&mut self, id: u8) {
-        self.buffer.push(id);
-        self.buffer.extend(self.packet_number.to_le_bytes());
+        self.packet_number = self.packet_number.wrapping_add(1);
+        match self.reliable_sequence.take() {
+            Some(sequence) => {
+                self.buffer.push(PACKET_RELIABLE);
+                self.buffer.extend(self.packet_number.to_le_bytes());
+                self.buffer.extend(sequence.to_le_bytes());
+                self.buffer.push(id);
+            }
+            None => {
+                self.buffer.push(id);
+                self.buffer.extend(self.packet_number.to_le_bytes());
+            }
+        }
+    }
+
+    fn push_tracker_data(&mut self, data: &UdpTrackerData) {
+        let orien = data.orientation.to_array();
+        let accel = data.acceleration.to_array();
+
+        match self.data_encoding {
+            DataEncoding::Full => {
+                self.buffer
+                    .extend(orien.iter().flat_map(|x| x.to_le_bytes()));
+                self.buffer
+                    .extend(accel.iter().flat_map(|x| x.to_le_bytes()));
+            }
+            DataEncoding::Compact => {
+                self.buffer
+                    .extend(&encode_quaternion(orien, 15).to_le_bytes()[0..6]);
+                self.push_compact_acceleration(accel);
+            }
+            DataEncoding::Compact32 => {
+                self.buffer
+                    .extend(&encode_quaternion(orien, 10).to_le_bytes()[0..4]);
+                self.push_compact_acceleration(accel);
+            }
+        }
+    }
+
+    fn push_compact_acceleration(&mut self, accel: [f32; 3]) {
+        self.buffer.extend(accel.iter().flat_map(|x| {
+            let value = (x * ACCELERATION_SCALE).round();
+            (value.clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_le_bytes()
+        }));
     }
 
     async fn send_buffer(&mut self) -> anyhow::Result<()> {
-        self.socket.send(&self.buffer).await?;
+        let session = match (self.pairing_key, self.buffer[0]) {
+            (Some(key), PACKET_HANDSHAKE) => Some(AuthSession::new(key, &[])),
+            _ => self.session.clone(),
+        };
+
+        self.last_sent = match session {
+            Some(session) => {
+                // Authenticated packets have the packet number before the packet id
+                let mut body = self.buffer[1..5].to_vec();
+                body.push(self.buffer[0]);
+                body.extend(&self.buffer[5..]);
+                session.seal(Direction::FromDevice, &body)
+            }
+            None => std::mem::take(&mut self.buffer),
+        };
+        self.socket.send(&self.last_sent).await?;
+
         self.buffer.clear();
         Ok(())
     }
+
+    /// Returns the bytes of the last datagram that was sent
+    pub fn last_sent(&self) -> &[u8] {
+        &self.last_sent
+    }
+}
+
+/// Encodes a quaternion as the index of its largest component followed by the other three
+/// components with `bits` bits each, the inverse of `packet::decode_quaternion`
+pub fn encode_quaternion(quat: [f32; 4], bits: u32) -> u64 {
+    let max = ((1 << bits) - 1) as f32;
+    let largest_index = (0..4)
+        .max_by(|&a, &b| quat[a].abs().total_cmp(&quat[b].abs()))
+        .unwrap_or(0);
+    // The negated quaternion is the same rotation, this keeps the largest component positive
+    let sign = quat[largest_index].signum();
+
+    let mut encoded = largest_index as u64;
+    let mut shift = 2;
+    for (i, component) in quat.iter().enumerate() {
+        if i != largest_index {
+            let normalized = (component * sign / std::f32::consts::FRAC_1_SQRT_2 + 1.) / 2.;
+            let value = (normalized.clamp(0., 1.) * max).round() as u64;
+            encoded |= value << shift;
+            shift += bits;
+        }
+    }
+    encoded
 }
@@ -0,0 +1,86 @@
+//! Estimates the offset between the clock of a device and the server
+//!
+//! Every timestamped packet gives the arrival time minus the device time, which is the clock
+//! offset plus however long the packet took to arrive. The smallest one in a recent window is
+//! the best estimate since that packet was delayed the least, and the window lets the estimate
+//! follow the clocks drifting apart.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Amount of recent packets the offset is estimated from
+const WINDOW: usize = 64;
+
+pub struct ClockSync {
+    /// Server times are in microseconds since this
+    epoch: Instant,
+    /// Server time minus device time of the recent packets
+    offsets: VecDeque<i64>,
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            offsets: VecDeque::with_capacity(WINDOW),
+        }
+    }
+}
+
+impl ClockSync {
+    pub fn add(&mut self, device_time_us: u64, received: Instant) {
+        let server_time_us = received.saturating_duration_since(self.epoch).as_micros() as i64;
+        if self.offsets.len() >= WINDOW {
+            self.offsets.pop_front();
+        }
+        self.offsets
+            .push_back(server_time_us - device_time_us as i64);
+    }
+
+    /// Server time minus device time in microseconds, None before any timestamped packet
+    pub fn offset_us(&self) -> Option<i64> {
+        self.offsets.iter().min().copied()
+    }
+
+    /// Converts a device time to when it was on the server
+    pub fn to_server_time(&self, device_time_us: u64) -> Option<Instant> {
+        let server_time_us = device_time_us as i64 + self.offset_us()?;
+        Some(self.epoch + Duration::from_micros(server_time_us.max(0) as u64))
+    }
+
+    /// The device clock starts over when it restarts
+    pub fn reset(&mut self) {
+        self.offsets.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn least_delayed_packet() {
+        let mut clock = ClockSync::default();
+        let epoch = clock.epoch;
+        assert_eq!(clock.to_server_time(0), None);
+
+        // The device clock is 5s behind and the packets take 3ms, 1ms and 8ms
+        let device_start = 1_000_000;
+        for (device_ms, delay_ms) in [(0, 3), (10, 1), (20, 8)] {
+            let device_time_us = device_start + device_ms * 1000;
+            let received = epoch + Duration::from_millis(5000 + 1000 + device_ms + delay_ms);
+            clock.add(device_time_us, received);
+        }
+
+        assert_eq!(clock.offset_us(), Some(5_001_000));
+        assert_eq!(
+            clock.to_server_time(device_start + 30_000),
+            Some(epoch + Duration::from_millis(6031))
+        );
+
+        clock.reset();
+        assert_eq!(clock.offset_us(), None);
+    }
+}
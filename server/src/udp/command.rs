@@ -0,0 +1,187 @@
+//! Commands sent from the server to a udp device
+//!
+//! The server sends `PACKET_COMMAND` with a sequence number, the tracker index (0xff for the
+//! whole device), the command id and its arguments. The device answers with `PACKET_COMMAND_ACK`
+//! containing the sequence number and a status. Devices with the reliable channel get each
+//! command sent once here, and it fails if the device hasn't answered by `RESPONSE_TIMEOUT`.
+//! Other devices get commands without an answer sent again after `ACK_TIMEOUT`, the device should
+//! ignore a sequence number it has just handled.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::udp::{
+    packet::{CommandAckStatus, UdpPacketCommandAck, PACKET_COMMAND},
+    reliable::RESPONSE_TIMEOUT,
+};
+
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+pub enum SensorMode {
+    /// Accelerometer and gyroscope only
+    SixAxis,
+    /// Also uses the magnetometer to correct the heading
+    NineAxis,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(tag = "type")]
+pub enum DeviceCommand {
+    /// Blinks the status led so the tracker can be found
+    BlinkLed,
+    Reboot,
+    SetSampleRate {
+        rate_hz: u16,
+    },
+    /// Keep the tracker still while it calibrates the gyroscope
+    StartCalibration,
+    PowerOff,
+    SetSensorMode {
+        mode: SensorMode,
+    },
+}
+
+impl DeviceCommand {
+    fn id(&self) -> u8 {
+        match self {
+            Self::BlinkLed => 0x00,
+            Self::Reboot => 0x01,
+            Self::SetSampleRate { .. } => 0x02,
+            Self::StartCalibration => 0x03,
+            Self::PowerOff => 0x04,
+            Self::SetSensorMode { .. } => 0x05,
+        }
+    }
+
+    /// Returns true if the command affects every tracker of the device
+    pub fn is_device_wide(&self) -> bool {
+        matches!(self, Self::Reboot | Self::PowerOff)
+    }
+
+    fn write_arguments(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Self::SetSampleRate { rate_hz } => bytes.extend(rate_hz.to_le_bytes()),
+            Self::SetSensorMode { mode } => bytes.push(*mode as u8),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+pub struct CommandResult {
+    pub tracker_id: Arc<str>,
+    pub command: DeviceCommand,
+    /// None if the device has run the command
+    pub error: Option<Box<str>>,
+}
+
+struct PendingCommand {
+    sequence: u8,
+    /// Tracker the command was requested for, even if it is sent to the whole device
+    tracker_index: u8,
+    command: DeviceCommand,
+    /// When the command was last sent, None if it still needs to be sent
+    sent_time: Option<Instant>,
+    attempts: u32,
+}
+
+impl PendingCommand {
+    fn to_packet(&self) -> Vec<u8> {
+        let tracker_index = match self.command.is_device_wide() {
+            true => 0xff,
+            false => self.tracker_index,
+        };
+
+        let mut packet = vec![
+            PACKET_COMMAND,
+            self.sequence,
+            tracker_index,
+            self.command.id(),
+        ];
+        self.command.write_arguments(&mut packet);
+        packet
+    }
+}
+
+/// Keeps track of the commands that the device has not acknowledged yet
+#[derive(Default)]
+pub struct CommandQueue {
+    pending: Vec<PendingCommand>,
+    next_sequence: u8,
+}
+
+impl CommandQueue {
+    pub fn push(&mut self, tracker_index: u8, command: DeviceCommand) {
+        self.pending.push(PendingCommand {
+            sequence: self.next_sequence,
+            tracker_index,
+            command,
+            sent_time: None,
+            attempts: 0,
+        });
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+    }
+
+    /// Returns the packets that should be sent to the device and the commands that have failed,
+    /// commands are only sent once when they go through the reliable channel
+    pub fn poll(&mut self, mac: &str, reliable: bool) -> (Vec<Vec<u8>>, Vec<CommandResult>) {
+        let mut packets = Vec::new();
+        let mut failed = Vec::new();
+        let (timeout, max_attempts) = match reliable {
+            true => (RESPONSE_TIMEOUT, 1),
+            false => (ACK_TIMEOUT, MAX_ATTEMPTS),
+        };
+
+        self.pending.retain_mut(|pending| {
+            if pending
+                .sent_time
+                .is_some_and(|time| time.elapsed() < timeout)
+            {
+                return true;
+            }
+
+            if pending.attempts >= max_attempts {
+                failed.push(result(mac, pending, Some("Device did not respond".into())));
+                return false;
+            }
+
+            packets.push(pending.to_packet());
+            pending.sent_time = Some(Instant::now());
+            pending.attempts += 1;
+            true
+        });
+
+        (packets, failed)
+    }
+
+    /// Returns the result of the command that was acknowledged
+    pub fn handle_ack(&mut self, mac: &str, ack: UdpPacketCommandAck) -> Option<CommandResult> {
+        let index = self
+            .pending
+            .iter()
+            .position(|pending| pending.sequence == ack.sequence)?;
+        let pending = self.pending.remove(index);
+
+        let error = match ack.status {
+            CommandAckStatus::Ok => None,
+            CommandAckStatus::Unsupported => Some("Device does not support the command".into()),
+            CommandAckStatus::Failed => Some("Device failed to run the command".into()),
+        };
+        Some(result(mac, &pending, error))
+    }
+}
+
+fn result(mac: &str, pending: &PendingCommand, error: Option<Box<str>>) -> CommandResult {
+    CommandResult {
+        tracker_id: format!("{mac}/{}", pending.tracker_index).into(),
+        command: pending.command.clone(),
+        error,
+    }
+}
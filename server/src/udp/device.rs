@@ -1,5 +1,6 @@
 use core::str;
 use std::{
+    borrow::Cow,
     net::SocketAddr,
     sync::{Arc, MutexGuard},
     time::{Duration, Instant},
@@ -7,20 +8,48 @@ use std::{
 
 use crate::{
     main_server::MainServer,
-    tracker::{Tracker, TrackerRef},
-    udp::packet::{
-        UdpPacketBatteryLevel, UdpPacketPingPong, UdpPacketTrackerStatus, UdpTrackerData,
+    tracker::{DeviceInfo, Tracker, TrackerRef, TrackerSource},
+    udp::{
+        auth::{AuthSession, Direction},
+        clock::ClockSync,
+        command::CommandQueue,
+        diagnostics::ResetReason,
+        link::{LinkMonitor, LinkQuality},
+        ota::FirmwareUpdate,
+        packet::{
+            DataEncoding, UdpPacketBatteryLevel, UdpPacketDiagnostics, UdpPacketFirmwareVersion,
+            UdpPacketPingPong, UdpPacketTrackerSamples, UdpPacketTrackerStatus, UdpTrackerData,
+        },
+        reliable::ReliableChannel,
+        sequence::PacketSequence,
     },
 };
 
 pub struct UdpDevice {
     pub(super) last_packet_received_time: Instant,
-    pub(super) last_packet_number: u32,
+    pub(super) sequence: PacketSequence,
+    pub(super) clock: ClockSync,
+    pub(super) link: LinkMonitor,
     pub(super) global_trackers: Vec<Option<TrackerRef>>,
     pub(super) mac: Arc<str>,
     pub(super) address: SocketAddr,
     current_ping_start_time: Option<Instant>,
     current_ping_id: u8,
+    /// None if the device uses the original handshake
+    device_info: Option<DeviceInfo>,
+    /// Capabilities negotiated in the handshake
+    capabilities: u32,
+    /// Set after the firmware version packet or the handshake
+    pub(super) firmware: Option<UdpPacketFirmwareVersion>,
+    /// Newer firmware version the server has for the device
+    available_firmware: Option<Box<str>>,
+    pub(super) firmware_update: Option<FirmwareUpdate>,
+    reset_reason: Option<ResetReason>,
+    /// Commands waiting on an acknowledgement from the device
+    pub(super) commands: CommandQueue,
+    pub(super) reliable: ReliableChannel,
+    /// Set if the device is paired and has authenticated its handshake
+    pub(super) auth: Option<AuthSession>,
 }
 
 impl UdpDevice {
@@ -32,9 +61,20 @@ impl UdpDevice {
             address,
             mac,
             last_packet_received_time: Instant::now(),
-            last_packet_number: 0,
+            sequence: PacketSequence::default(),
+            clock: ClockSync::default(),
+            link: LinkMonitor::default(),
             current_ping_id: 0,
             current_ping_start_time: None,
+            device_info: None,
+            capabilities: 0,
+            firmware: None,
+            available_firmware: None,
+            firmware_update: None,
+            reset_reason: None,
+            commands: CommandQueue::default(),
+            reliable: ReliableChannel::default(),
+            auth: None,
         }
     }
 
@@ -46,7 +86,7 @@ impl UdpDevice {
 
         // Register the tracker and add the id into the udp device array to know
         let id: Arc<str> = format!("{}/{}", self.mac, local_index).into();
-        self.global_trackers[local_index] = main.add_tracker(&id);
+        self.global_trackers[local_index] = main.add_tracker(&id, TrackerSource::Udp);
     }
 
     fn get_tracker(&self, local_index: u8) -> Option<MutexGuard<'_, Tracker>> {
@@ -63,20 +103,6 @@ impl UdpDevice {
             .filter_map(|tracker| tracker.as_ref()?.lock().ok())
     }
 
-    pub fn check_latest_packet_number(&mut self, packet_number: u32) -> bool {
-        // Pass through packet with 0 packet number (eg. handshakes)
-        if packet_number == 0 {
-            return true;
-        }
-
-        if packet_number <= self.last_packet_number {
-            false
-        } else {
-            self.last_packet_number = packet_number;
-            true
-        }
-    }
-
     pub fn is_timed_out(&self) -> bool {
         self.last_packet_received_time.elapsed() > Self::TIMEOUT
     }
@@ -87,6 +113,28 @@ impl UdpDevice {
         }
     }
 
+    /// Updates the packet loss of the trackers and returns the link quality since the last update
+    pub fn update_link_quality(&mut self) -> LinkQuality {
+        let stats = self.sequence.take_stats();
+        if stats.lost > 0 || stats.out_of_order > 0 {
+            log::debug!(
+                "{} lost {} and reordered {} of {} packets",
+                self.mac,
+                stats.lost,
+                stats.out_of_order,
+                stats.received + stats.lost
+            );
+        }
+
+        if let Some(loss_rate) = stats.loss_rate() {
+            for mut tracker in self.global_trackers_iter() {
+                tracker.update_info().packet_loss = Some(loss_rate);
+            }
+        }
+
+        self.link.take_quality(stats)
+    }
+
     // Gets the ping packet with the current ping id
     pub fn check_get_ping_packet(&mut self) -> UdpPacketPingPong {
         // If ping has been acknowledge (when set to none) start a new ping id
@@ -104,17 +152,41 @@ impl UdpDevice {
         }
 
         if let Some(start_time) = self.current_ping_start_time.take() {
+            // Measured to when the pong arrived instead of when it got handled
+            let latency = self
+                .last_packet_received_time
+                .saturating_duration_since(start_time)
+                / 2;
+            self.link.add_latency(latency);
             for mut tracker in self.global_trackers_iter() {
-                let latency = start_time.elapsed() / 2;
                 tracker.update_info().latency_ms = Some(latency.as_millis() as u32);
             }
         }
     }
 
-    pub fn update_tracker_data(&mut self, data: UdpTrackerData) {
+    /// The data is timed by when the packet was received instead of when it gets handled
+    pub fn update_tracker_data(&mut self, data: UdpTrackerData, received: Instant) {
         if let Some(mut tracker) = self.get_tracker(data.tracker_index) {
-            tracker.update_data(data.acceleration, data.orientation);
+            tracker.update_data_at(data.acceleration, data.orientation, received);
+        }
+    }
+
+    pub fn update_tracker_samples(
+        &mut self,
+        mut packet: UdpPacketTrackerSamples<impl std::io::Read>,
+        received: Instant,
+    ) -> anyhow::Result<()> {
+        self.clock.add(packet.sent_timestamp_us, received);
+        while let Some(sample) = packet.next_sample(self.data_encoding())? {
+            let time = self
+                .clock
+                .to_server_time(sample.timestamp_us)
+                .unwrap_or(received);
+            if let Some(mut tracker) = self.get_tracker(sample.data.tracker_index) {
+                tracker.update_data_at(sample.data.acceleration, sample.data.orientation, time);
+            }
         }
+        Ok(())
     }
 
     pub fn update_tracker_status(&mut self, main: &mut MainServer, packet: UdpPacketTrackerStatus) {
@@ -128,6 +200,57 @@ impl UdpDevice {
             tracker.update_info().status = packet.tracker_status;
             tracker.update_info().address = Some(address);
         }
+
+        self.update_device_info();
+    }
+
+    pub fn set_device_info(&mut self, device_info: Option<DeviceInfo>, capabilities: u32) {
+        self.device_info = device_info;
+        self.capabilities = capabilities;
+        self.update_device_info();
+    }
+
+    /// Always false for devices with the original handshake since they can't negotiate
+    pub fn has_negotiated(&self, capability: u32) -> bool {
+        self.capabilities & capability != 0
+    }
+
+    pub fn data_encoding(&self) -> DataEncoding {
+        DataEncoding::from_capabilities(self.capabilities)
+    }
+
+    pub fn set_firmware(
+        &mut self,
+        packet: UdpPacketFirmwareVersion,
+        available_firmware: Option<Box<str>>,
+    ) {
+        self.firmware = Some(packet);
+        self.available_firmware = available_firmware;
+        self.update_device_info();
+    }
+
+    pub fn set_auth(&mut self, auth: Option<AuthSession>) {
+        self.sequence.set_authenticated(auth.is_some());
+        self.auth = auth;
+        self.update_device_info();
+    }
+
+    /// Authenticates the packet if the device is paired
+    pub fn seal<'a>(&self, packet: &'a [u8]) -> Cow<'a, [u8]> {
+        match &self.auth {
+            Some(auth) => Cow::Owned(auth.seal(Direction::FromServer, packet)),
+            None => Cow::Borrowed(packet),
+        }
+    }
+
+    fn update_device_info(&self) {
+        let version = self.firmware.as_ref().map(|firmware| &firmware.version);
+        for mut tracker in self.global_trackers_iter() {
+            tracker.update_info().authenticated = self.auth.is_some();
+            tracker.update_info().device = self.device_info.clone();
+            tracker.update_info().firmware_version = version.cloned();
+            tracker.update_info().firmware_update = self.available_firmware.clone();
+        }
     }
 
     pub fn update_battery_level(&self, packet: UdpPacketBatteryLevel) {
@@ -136,6 +259,26 @@ impl UdpDevice {
         }
     }
 
+    /// Returns the reset reason if it changed, which means the device has restarted
+    pub fn update_diagnostics(
+        &mut self,
+        mut packet: UdpPacketDiagnostics<impl std::io::Read>,
+    ) -> anyhow::Result<Option<ResetReason>> {
+        for mut tracker in self.global_trackers_iter() {
+            tracker.update_info().wifi_rssi = Some(packet.wifi_rssi);
+        }
+
+        while let Some((index, temperature)) = packet.next_temperature()? {
+            if let Some(mut tracker) = self.get_tracker(index) {
+                tracker.update_info().imu_temperature = Some(temperature);
+            }
+        }
+
+        let changed = self.reset_reason != Some(packet.reset_reason);
+        self.reset_reason = Some(packet.reset_reason);
+        Ok(changed.then_some(packet.reset_reason))
+    }
+
     pub fn all_trackers_removed(&mut self) -> bool {
         let mut count = 0;
         let all_removed = self
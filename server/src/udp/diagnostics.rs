@@ -0,0 +1,108 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::config::get_config_dir;
+
+/// Size the log file can grow to before it gets moved to the backup file
+const MAX_LOG_FILE_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, TS)]
+pub enum DeviceLogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+impl DeviceLogLevel {
+    pub fn from_u8(level: u8) -> Self {
+        match level {
+            0 => Self::Info,
+            1 => Self::Warn,
+            _ => Self::Error,
+        }
+    }
+}
+
+/// Why the device last restarted, as reported by the chip
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, TS)]
+pub enum ResetReason {
+    PowerOn,
+    External,
+    Software,
+    Watchdog,
+    Panic,
+    Brownout,
+    DeepSleep,
+    Unknown,
+}
+
+impl ResetReason {
+    pub fn from_u8(reason: u8) -> Self {
+        match reason {
+            0 => Self::PowerOn,
+            1 => Self::External,
+            2 => Self::Software,
+            3 => Self::Watchdog,
+            4 => Self::Panic,
+            5 => Self::Brownout,
+            6 => Self::DeepSleep,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Returns true if the device restarted because something went wrong
+    pub fn is_crash(&self) -> bool {
+        matches!(self, Self::Watchdog | Self::Panic | Self::Brownout)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+pub struct DeviceLog {
+    pub mac: Arc<str>,
+    pub level: DeviceLogLevel,
+    pub message: Box<str>,
+}
+
+/// Writes the logs of every device into a file in the config directory
+/// The file gets moved to `devices.log.old` when it gets too big so only the recent logs are kept
+#[derive(Default)]
+pub struct DeviceLogFile {
+    file: Option<File>,
+}
+
+impl DeviceLogFile {
+    pub fn write(&mut self, log: &DeviceLog) -> anyhow::Result<()> {
+        let path = get_log_path()?;
+
+        let mut file = match self.file.take() {
+            Some(file) if file.metadata()?.len() < MAX_LOG_FILE_SIZE => file,
+            old_file => {
+                if old_file.is_some() {
+                    std::fs::rename(&path, path.with_extension("log.old"))?;
+                }
+                File::options().create(true).append(true).open(&path)?
+            }
+        };
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        writeln!(file, "{time} {} {:?} {}", log.mac, log.level, log.message)?;
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+fn get_log_path() -> anyhow::Result<PathBuf> {
+    let dir = get_config_dir()?.join("logs");
+    if !dir.exists() {
+        std::fs::create_dir_all(&dir)?;
+    }
+    Ok(dir.join("devices.log"))
+}
@@ -0,0 +1,85 @@
+//! Advertises the server with mDNS/DNS-SD so devices and apps can find it on networks that filter
+//! the multicast discovery, they can also send `PACKET_DISCOVERY` directly to the server.
+//!
+//! The service is `_micap._udp` on the udp port, with the protocol version and the websocket port
+//! in its TXT record.
+
+use mdns_sd::{IfKind, ServiceDaemon, ServiceInfo};
+
+use crate::{
+    udp::{packet::PROTOCOL_VERSION, server::UdpConfig},
+    websocket::WEBSOCKET_PORT,
+};
+
+pub const MDNS_SERVICE_TYPE: &str = "_micap._udp.local.";
+
+#[derive(Default)]
+pub struct MdnsAdvertiser {
+    daemon: Option<ServiceDaemon>,
+    /// Port and interface the service is advertised with
+    advertised: Option<(u16, Option<Box<str>>)>,
+}
+
+impl MdnsAdvertiser {
+    pub fn apply_config(&mut self, config: &UdpConfig) -> anyhow::Result<()> {
+        let advertised = config
+            .advertise_mdns
+            .then(|| (config.port, config.multicast_interface.clone()));
+        if advertised == self.advertised {
+            return Ok(());
+        }
+
+        self.stop();
+        if let Some((port, interface)) = &advertised {
+            self.daemon = Some(advertise(*port, interface.as_deref())?);
+        }
+        self.advertised = advertised;
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(daemon) = self.daemon.take() {
+            if let Err(err) = daemon.shutdown() {
+                log::warn!("Failed to stop mDNS advertisement: {err}");
+            }
+        }
+    }
+}
+
+impl Drop for MdnsAdvertiser {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The service is named after the host so servers on different computers can be told apart
+pub fn instance_name() -> String {
+    whoami::fallible::hostname().unwrap_or_else(|_| "micap".into())
+}
+
+fn advertise(port: u16, interface: Option<&str>) -> anyhow::Result<ServiceDaemon> {
+    let daemon = ServiceDaemon::new()?;
+    if let Some(interface) = interface {
+        daemon.disable_interface(IfKind::All)?;
+        daemon.enable_interface(interface)?;
+    }
+
+    let hostname = instance_name();
+    let properties = [
+        ("protocol_version", PROTOCOL_VERSION.to_string()),
+        ("websocket_port", WEBSOCKET_PORT.to_string()),
+    ];
+    let service = ServiceInfo::new(
+        MDNS_SERVICE_TYPE,
+        &hostname,
+        &format!("{hostname}.local."),
+        (),
+        port,
+        &properties[..],
+    )?
+    .enable_addr_auto();
+
+    log::info!("Advertising {} with mDNS", service.get_fullname());
+    daemon.register(service)?;
+    Ok(daemon)
+}
@@ -0,0 +1,123 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::udp::sequence::PacketStats;
+
+/// Amount of pings the latency statistics are calculated from
+const LATENCY_SAMPLES: usize = 30;
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+pub struct LatencyStats {
+    pub min_ms: f32,
+    pub avg_ms: f32,
+    pub p95_ms: f32,
+    /// Average difference between consecutive pings
+    pub jitter_ms: f32,
+}
+
+/// Health of the connection to a udp device since the last upkeep
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+pub struct LinkQuality {
+    #[ts(optional)]
+    pub latency: Option<LatencyStats>,
+    pub packets_per_sec: f32,
+    pub bytes_per_sec: f32,
+    pub loss_rate: f32,
+    pub out_of_order: u32,
+}
+
+/// Collects the latency and traffic of a udp device
+pub struct LinkMonitor {
+    latencies: VecDeque<Duration>,
+    received_bytes: usize,
+    since: Instant,
+}
+
+impl Default for LinkMonitor {
+    fn default() -> Self {
+        Self {
+            latencies: VecDeque::with_capacity(LATENCY_SAMPLES),
+            received_bytes: 0,
+            since: Instant::now(),
+        }
+    }
+}
+
+impl LinkMonitor {
+    pub fn add_latency(&mut self, latency: Duration) {
+        if self.latencies.len() >= LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+    }
+
+    pub fn add_received_bytes(&mut self, amount: usize) {
+        self.received_bytes += amount;
+    }
+
+    pub fn latency_stats(&self) -> Option<LatencyStats> {
+        let as_ms = |duration: &Duration| duration.as_secs_f32() * 1000.;
+
+        let mut sorted: Vec<f32> = self.latencies.iter().map(as_ms).collect();
+        sorted.sort_by(f32::total_cmp);
+        let min_ms = *sorted.first()?;
+        let avg_ms = sorted.iter().sum::<f32>() / sorted.len() as f32;
+        let p95_index = (sorted.len() as f32 * 0.95).ceil() as usize - 1;
+
+        let differences = self
+            .latencies
+            .iter()
+            .zip(self.latencies.iter().skip(1))
+            .map(|(a, b)| (as_ms(a) - as_ms(b)).abs());
+        let jitter_ms = differences.sum::<f32>() / (sorted.len() - 1).max(1) as f32;
+
+        Some(LatencyStats {
+            min_ms,
+            avg_ms,
+            p95_ms: sorted[p95_index],
+            jitter_ms,
+        })
+    }
+
+    /// Returns the quality since the last call, with the packet statistics of the same period
+    pub fn take_quality(&mut self, packets: PacketStats) -> LinkQuality {
+        let seconds = self.since.elapsed().as_secs_f32().max(f32::EPSILON);
+        let quality = LinkQuality {
+            latency: self.latency_stats(),
+            packets_per_sec: packets.received as f32 / seconds,
+            bytes_per_sec: self.received_bytes as f32 / seconds,
+            loss_rate: packets.loss_rate().unwrap_or_default(),
+            out_of_order: packets.out_of_order,
+        };
+
+        self.received_bytes = 0;
+        self.since = Instant::now();
+        quality
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn latency_stats() {
+        let mut monitor = LinkMonitor::default();
+        assert_eq!(monitor.latency_stats(), None);
+
+        for ms in [10, 20, 10, 40] {
+            monitor.add_latency(Duration::from_millis(ms));
+        }
+
+        let stats = monitor.latency_stats().unwrap();
+        assert_eq!(stats.min_ms, 10.);
+        assert_eq!(stats.avg_ms, 20.);
+        assert_eq!(stats.p95_ms, 40.);
+        assert_eq!(stats.jitter_ms, 50. / 3.);
+    }
+}
@@ -1,4 +1,16 @@
+pub mod auth;
 pub mod client;
+pub mod clock;
+pub mod command;
 pub mod device;
+pub mod diagnostics;
+pub mod discovery;
+pub mod link;
+pub mod ota;
 pub mod packet;
+pub mod pending;
+pub mod receiver;
+pub mod reliable;
+pub mod sequence;
 pub mod server;
+pub mod socket;
@@ -0,0 +1,167 @@
+//! Pushes a firmware image to a udp device
+//!
+//! The server sends `PACKET_OTA_BEGIN`, then the image in chunks and finally `PACKET_OTA_END`.
+//! The device acknowledges each of them with `PACKET_OTA_ACK` containing the amount of bytes it
+//! has received in order, so chunks that were lost are sent again after `ACK_TIMEOUT`.
+//! The begin and end packets go through the reliable channel instead so they are only sent once
+//! here. On `PACKET_OTA_END` the device checks the md5 of the image before acknowledging it.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    firmware::{FirmwareImage, ProgressReporter},
+    udp::{
+        packet::{
+            OtaAckStatus, UdpPacketOtaAck, PACKET_OTA_BEGIN, PACKET_OTA_CHUNK, PACKET_OTA_END,
+        },
+        reliable::RESPONSE_TIMEOUT,
+    },
+};
+
+const CHUNK_SIZE: usize = 1024;
+/// Amount of chunks that can be sent before waiting for an acknowledgement
+const WINDOW_SIZE: usize = 8;
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_RETRIES: u32 = 10;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum UpdateState {
+    Begin,
+    Upload,
+    End,
+}
+
+pub struct FirmwareUpdate {
+    image: FirmwareImage,
+    state: UpdateState,
+    /// Every byte before this has been received by the device
+    acked_offset: usize,
+    /// Every byte before this has been sent
+    sent_offset: usize,
+    /// When we started waiting on an acknowledgement, None if nothing is waiting
+    waiting_since: Option<Instant>,
+    retries: u32,
+    progress_reporter: ProgressReporter,
+}
+
+impl FirmwareUpdate {
+    pub fn new(image: FirmwareImage) -> Self {
+        Self {
+            image,
+            state: UpdateState::Begin,
+            acked_offset: 0,
+            sent_offset: 0,
+            waiting_since: None,
+            retries: 0,
+            progress_reporter: ProgressReporter::default(),
+        }
+    }
+
+    pub fn version(&self) -> &str {
+        &self.image.version
+    }
+
+    /// Returns the packets that should be sent to the device, only the chunks are streamed
+    pub fn poll(&mut self) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut packets = Vec::new();
+        match self.state {
+            UpdateState::Begin | UpdateState::End => match self.waiting_since {
+                None => {
+                    packets.push(self.control_packet());
+                    self.waiting_since = Some(Instant::now());
+                }
+                Some(time) if time.elapsed() > RESPONSE_TIMEOUT => {
+                    anyhow::bail!("Device stopped responding");
+                }
+                Some(_) => {}
+            },
+            UpdateState::Upload => {
+                if self
+                    .waiting_since
+                    .is_some_and(|time| time.elapsed() > ACK_TIMEOUT)
+                {
+                    self.retries += 1;
+                    if self.retries > MAX_RETRIES {
+                        anyhow::bail!("Device stopped responding");
+                    }
+
+                    // Go back and send everything that wasn't acknowledged again
+                    log::trace!("OTA retrying from offset {}", self.acked_offset);
+                    self.sent_offset = self.acked_offset;
+                    self.waiting_since = None;
+                }
+
+                let window_end = (self.acked_offset + WINDOW_SIZE * CHUNK_SIZE).min(self.len());
+                while self.sent_offset < window_end {
+                    let chunk_end = (self.sent_offset + CHUNK_SIZE).min(self.len());
+
+                    let mut packet = vec![PACKET_OTA_CHUNK];
+                    packet.extend((self.sent_offset as u32).to_le_bytes());
+                    packet.extend(&self.image.data[self.sent_offset..chunk_end]);
+                    packets.push(packet);
+
+                    self.sent_offset = chunk_end;
+                    self.waiting_since.get_or_insert_with(Instant::now);
+                }
+            }
+        }
+
+        Ok(packets)
+    }
+
+    /// Returns true when the device has received and verified the whole image
+    pub fn handle_ack(&mut self, ack: UdpPacketOtaAck) -> anyhow::Result<bool> {
+        match ack.status {
+            OtaAckStatus::Ok => {}
+            OtaAckStatus::WriteFailed => anyhow::bail!("Device failed to write the firmware"),
+            OtaAckStatus::VerifyFailed => anyhow::bail!("Firmware failed the integrity check"),
+        }
+
+        let offset = ack.offset as usize;
+        match (self.state, ack.packet_type) {
+            (UpdateState::Begin, PACKET_OTA_BEGIN) => {
+                self.state = UpdateState::Upload;
+                self.waiting_since = None;
+                self.retries = 0;
+            }
+            // Ignore acknowledgements for chunks that were sent again
+            (UpdateState::Upload, PACKET_OTA_CHUNK)
+                if offset > self.acked_offset && offset <= self.sent_offset =>
+            {
+                self.acked_offset = offset;
+                self.retries = 0;
+                self.waiting_since = (self.acked_offset != self.sent_offset).then(Instant::now);
+
+                if self.acked_offset == self.len() {
+                    self.state = UpdateState::End;
+                }
+            }
+            (UpdateState::End, PACKET_OTA_END) if offset == self.len() => return Ok(true),
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the progress if it changed by at least a percent since the last call
+    pub fn take_progress(&mut self) -> Option<f32> {
+        let progress = self.acked_offset as f32 / self.len() as f32;
+        self.progress_reporter.update(progress)
+    }
+
+    fn control_packet(&self) -> Vec<u8> {
+        match self.state {
+            UpdateState::Begin => {
+                let mut packet = vec![PACKET_OTA_BEGIN];
+                packet.extend((self.len() as u32).to_le_bytes());
+                packet.extend(self.image.md5);
+                packet
+            }
+            _ => vec![PACKET_OTA_END],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.image.data.len()
+    }
+}
@@ -1,45 +1,157 @@
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::{io::Read, sync::Arc};
 
-use crate::tracker::TrackerStatus;
+use crate::{
+    tracker::{DeviceInfo, TrackerStatus},
+    udp::{
+        auth::NONCE_SIZE,
+        diagnostics::{DeviceLogLevel, ResetReason},
+    },
+};
 
 pub const PACKET_PING_PONG: u8 = 0x00;
 pub const PACKET_HANDSHAKE: u8 = 0x01;
 pub const PACKET_TRACKER_STATUS: u8 = 0x02;
 pub const PACKET_TRACKER_DATA: u8 = 0x03;
 pub const PACKET_BATTERY_LEVEL: u8 = 0x04;
+pub const PACKET_FIRMWARE_VERSION: u8 = 0x05;
+pub const PACKET_OTA_BEGIN: u8 = 0x06;
+pub const PACKET_OTA_CHUNK: u8 = 0x07;
+pub const PACKET_OTA_END: u8 = 0x08;
+pub const PACKET_OTA_ACK: u8 = 0x09;
+pub const PACKET_DEVICE_LOG: u8 = 0x0a;
+pub const PACKET_DEVICE_ERROR: u8 = 0x0b;
+pub const PACKET_DIAGNOSTICS: u8 = 0x0c;
+pub const PACKET_COMMAND: u8 = 0x0d;
+pub const PACKET_COMMAND_ACK: u8 = 0x0e;
+pub const PACKET_RELIABLE: u8 = 0x0f;
+pub const PACKET_RELIABLE_ACK: u8 = 0x10;
+pub const PACKET_AUTHENTICATED: u8 = 0x11;
+pub const PACKET_DISCOVERY: u8 = 0x12;
+pub const PACKET_TRACKER_SAMPLES: u8 = 0x13;
+
+/// Version of the protocol the server speaks, devices with the original handshake are version 0
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// The device accepts the OTA firmware update packets
+pub const CAPABILITY_OTA: u32 = 1 << 0;
+/// The server accepts the log, error and diagnostics packets
+pub const CAPABILITY_DIAGNOSTICS: u32 = 1 << 1;
+/// The device accepts the command packets
+pub const CAPABILITY_COMMANDS: u32 = 1 << 2;
+/// Both sides wrap control packets with the reliable packet and acknowledge them
+pub const CAPABILITY_RELIABLE: u32 = 1 << 3;
+/// The server accepts the timestamped tracker samples packet
+pub const CAPABILITY_TRACKER_SAMPLES: u32 = 1 << 4;
+/// Tracker data uses the compact encoding with 48 bit quaternions
+pub const CAPABILITY_COMPACT_DATA: u32 = 1 << 5;
+/// Together with `CAPABILITY_COMPACT_DATA` the quaternions are 32 bits instead
+pub const CAPABILITY_COMPACT_DATA_32: u32 = 1 << 6;
+/// Capabilities the server supports, only the ones both sides support are used
+pub const SERVER_CAPABILITIES: u32 = CAPABILITY_OTA
+    | CAPABILITY_DIAGNOSTICS
+    | CAPABILITY_COMMANDS
+    | CAPABILITY_RELIABLE
+    | CAPABILITY_TRACKER_SAMPLES
+    | CAPABILITY_COMPACT_DATA
+    | CAPABILITY_COMPACT_DATA_32;
+
+/// Acceleration in the compact encoding is an i16 in 1/ACCELERATION_SCALE m/s²
+pub const ACCELERATION_SCALE: f32 = 128.;
+
+/// How the orientation and acceleration of a tracker are encoded in the tracker data packets
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DataEncoding {
+    /// Quaternion and acceleration as f32s, 28 bytes
+    #[default]
+    Full,
+    /// Smallest three quaternion in 48 bits and fixed point acceleration, 12 bytes
+    Compact,
+    /// Smallest three quaternion in 32 bits and fixed point acceleration, 10 bytes
+    Compact32,
+}
+
+impl DataEncoding {
+    pub fn from_capabilities(capabilities: u32) -> Self {
+        if capabilities & CAPABILITY_COMPACT_DATA == 0 {
+            Self::Full
+        } else if capabilities & CAPABILITY_COMPACT_DATA_32 == 0 {
+            Self::Compact
+        } else {
+            Self::Compact32
+        }
+    }
+}
 
 pub enum UdpPacket<'a, R: Read> {
     Handshake(UdpPacketHandshake),
     TrackerData(UdpPacketTrackerData<'a, R>),
+    TrackerSamples(UdpPacketTrackerSamples<'a, R>),
     TrackerStatus(UdpPacketTrackerStatus),
     BatteryLevel(UdpPacketBatteryLevel),
     PingPong(UdpPacketPingPong),
+    FirmwareVersion(UdpPacketFirmwareVersion),
+    OtaAck(UdpPacketOtaAck),
+    DeviceLog(UdpPacketDeviceLog),
+    DeviceError(UdpPacketDeviceError),
+    Diagnostics(UdpPacketDiagnostics<'a, R>),
+    CommandAck(UdpPacketCommandAck),
+    Reliable(UdpPacketReliable),
+    ReliableAck(UdpPacketReliableAck),
+    Discovery(UdpPacketDiscovery),
 }
 
 impl<'a, R: Read> UdpPacket<'a, R> {
     /// Returns (Self, packet_number)
     pub fn parse(bytes: &'a mut R) -> anyhow::Result<(Self, u32)> {
         let packet_type = bytes.read_u8()?;
-        let packet_number = bytes.read_u32::<LittleEndian>()?;
+        let packet_number = bytes
+            .read_u32::<LittleEndian>()
+            .map_err(check_truncated(packet_type))?;
+        let packet = Self::parse_body(packet_type, bytes).map_err(check_truncated(packet_type))?;
+        Ok((packet, packet_number))
+    }
 
+    fn parse_body(packet_type: u8, bytes: &'a mut R) -> std::io::Result<Self> {
         let packet = match packet_type {
             PACKET_HANDSHAKE => Self::Handshake(UdpPacketHandshake::from_bytes(bytes)?),
             PACKET_PING_PONG => Self::PingPong(UdpPacketPingPong::from_bytes(bytes)?),
             PACKET_TRACKER_DATA => Self::TrackerData(UdpPacketTrackerData::from_bytes(bytes)?),
+            PACKET_TRACKER_SAMPLES => {
+                Self::TrackerSamples(UdpPacketTrackerSamples::from_bytes(bytes)?)
+            }
             PACKET_TRACKER_STATUS => {
                 Self::TrackerStatus(UdpPacketTrackerStatus::from_bytes(bytes)?)
             }
             PACKET_BATTERY_LEVEL => Self::BatteryLevel(UdpPacketBatteryLevel::from_bytes(bytes)?),
-            _ => anyhow::bail!("Invalid packet id"),
+            PACKET_FIRMWARE_VERSION => {
+                Self::FirmwareVersion(UdpPacketFirmwareVersion::from_bytes(bytes)?)
+            }
+            PACKET_OTA_ACK => Self::OtaAck(UdpPacketOtaAck::from_bytes(bytes)?),
+            PACKET_DEVICE_LOG => Self::DeviceLog(UdpPacketDeviceLog::from_bytes(bytes)?),
+            PACKET_DEVICE_ERROR => Self::DeviceError(UdpPacketDeviceError::from_bytes(bytes)?),
+            PACKET_DIAGNOSTICS => Self::Diagnostics(UdpPacketDiagnostics::from_bytes(bytes)?),
+            PACKET_COMMAND_ACK => Self::CommandAck(UdpPacketCommandAck::from_bytes(bytes)?),
+            PACKET_RELIABLE => Self::Reliable(UdpPacketReliable::from_bytes(bytes)?),
+            PACKET_RELIABLE_ACK => Self::ReliableAck(UdpPacketReliableAck::from_bytes(bytes)?),
+            PACKET_DISCOVERY => Self::Discovery(UdpPacketDiscovery::from_bytes(bytes)?),
+            _ => return Err(std::io::Error::other("Invalid packet id")),
         };
 
-        Ok((packet, packet_number))
+        Ok(packet)
     }
 }
 
+/// MCDEV + mac address, followed by the device info if the protocol version is 1 or above:
+/// protocol version (u8), capabilities (u32), sensor count (u8), board, imu, firmware version
+/// and a nonce if the device is paired
 pub struct UdpPacketHandshake {
     pub mac_address: Arc<str>,
+    /// None if the device uses the original handshake
+    pub device_info: Option<DeviceInfo>,
+    pub firmware_version: Option<Box<str>>,
+    pub capabilities: u32,
+    pub auth_nonce: Option<[u8; NONCE_SIZE]>,
 }
 
 impl UdpPacketHandshake {
@@ -52,9 +164,60 @@ impl UdpPacketHandshake {
         bytes.read_exact(&mut mac_bytes)?;
         let mac_string = mac_bytes.map(|b| format!("{b:02x}")).join(":");
 
-        Ok(Self {
+        let mut packet = Self {
             mac_address: mac_string.into(),
-        })
+            device_info: None,
+            firmware_version: None,
+            capabilities: 0,
+            auth_nonce: None,
+        };
+
+        // Original handshakes end after the mac address
+        let protocol_version = match bytes.read_u8() {
+            Ok(version) => version,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(packet),
+            Err(err) => return Err(err),
+        };
+
+        packet.capabilities = bytes.read_u32::<LittleEndian>()?;
+        let sensor_count = bytes.read_u8()?;
+        packet.device_info = Some(DeviceInfo {
+            protocol_version,
+            sensor_count,
+            board: read_string(bytes)?,
+            imu: read_string(bytes)?,
+        });
+        packet.firmware_version = Some(read_string(bytes)?);
+
+        let mut nonce = [0; NONCE_SIZE];
+        match bytes.read_exact(&mut nonce) {
+            Ok(()) => packet.auth_nonce = Some(nonce),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {}
+            Err(err) => return Err(err),
+        }
+        Ok(packet)
+    }
+
+    pub fn protocol_version(&self) -> u8 {
+        self.device_info
+            .as_ref()
+            .map_or(0, |info| info.protocol_version)
+    }
+
+    /// Capabilities that both the device and the server support
+    pub fn negotiated_capabilities(&self) -> u32 {
+        self.capabilities & SERVER_CAPABILITIES
+    }
+
+    /// Old devices get the original response, newer ones also get the
+    /// protocol version (u8) that will be used and the negotiated capabilities (u32)
+    pub fn to_response(&self) -> Vec<u8> {
+        let mut bytes = Self::SERVER_RESPONSE.to_vec();
+        if self.protocol_version() > 0 {
+            bytes.push(self.protocol_version().min(PROTOCOL_VERSION));
+            bytes.extend(self.negotiated_capabilities().to_le_bytes());
+        }
+        bytes
     }
 
     /// Represents a server handshake response
@@ -82,6 +245,66 @@ impl UdpPacketPingPong {
     }
 }
 
+/// Tracker data measured by the device at the timestamp
+pub struct UdpTrackerSample {
+    /// Microseconds on the clock of the device
+    pub timestamp_us: u64,
+    pub data: UdpTrackerData,
+}
+
+/// When the packet was sent (u64, microseconds on the device clock), followed by the samples of
+/// each tracker: tracker index (u8), sample count (u8) and for each sample how long before the
+/// packet was sent it was measured (u32, microseconds), orientation and acceleration like in
+/// `PACKET_TRACKER_DATA`. 0xff where the tracker index would go ends the packet.
+pub struct UdpPacketTrackerSamples<'a, R: Read> {
+    pub sent_timestamp_us: u64,
+    bytes: &'a mut R,
+    /// Tracker index and amount of samples left of the tracker being read
+    current_tracker: Option<(u8, u8)>,
+}
+
+impl<'a, R: Read> UdpPacketTrackerSamples<'a, R> {
+    fn from_bytes(bytes: &'a mut R) -> std::io::Result<Self> {
+        Ok(Self {
+            sent_timestamp_us: bytes.read_u64::<LittleEndian>()?,
+            bytes,
+            current_tracker: None,
+        })
+    }
+
+    pub fn next_sample(
+        &mut self,
+        encoding: DataEncoding,
+    ) -> anyhow::Result<Option<UdpTrackerSample>> {
+        self.read_sample(encoding)
+            .map_err(check_truncated(PACKET_TRACKER_SAMPLES))
+    }
+
+    fn read_sample(&mut self, encoding: DataEncoding) -> std::io::Result<Option<UdpTrackerSample>> {
+        let (tracker_index, remaining) = loop {
+            match self.current_tracker {
+                Some((tracker_index, remaining)) if remaining > 0 => {
+                    break (tracker_index, remaining)
+                }
+                _ => {
+                    let tracker_index = self.bytes.read_u8()?;
+                    if tracker_index == 0xff {
+                        return Ok(None);
+                    }
+                    self.current_tracker = Some((tracker_index, self.bytes.read_u8()?));
+                }
+            }
+        };
+        self.current_tracker = Some((tracker_index, remaining - 1));
+
+        let age_us = self.bytes.read_u32::<LittleEndian>()?;
+        Ok(Some(UdpTrackerSample {
+            timestamp_us: self.sent_timestamp_us.saturating_sub(age_us as u64),
+            data: read_tracker_data(self.bytes, tracker_index, encoding)?,
+        }))
+    }
+}
+
 pub struct UdpPacketTrackerStatus {
     pub tracker_index: u8,
     pub tracker_status: TrackerStatus,
@@ -124,27 +347,56 @@ impl<'a, R: Read> UdpPacketTrackerData<'a, R> {
         Ok(Self { bytes })
     }
 
-    pub fn next_data(&mut self) -> std::io::Result<Option<UdpTrackerData>> {
+    pub fn next_data(&mut self, encoding: DataEncoding) -> anyhow::Result<Option<UdpTrackerData>> {
+        self.read_data(encoding)
+            .map_err(check_truncated(PACKET_TRACKER_DATA))
+    }
+
+    fn read_data(&mut self, encoding: DataEncoding) -> std::io::Result<Option<UdpTrackerData>> {
         let tracker_index = self.bytes.read_u8()?;
         // 0xff where the tracker id would usually go signifies the end of the packet
         if tracker_index == 0xff {
             return Ok(None);
         }
 
-        let mut array = [0_f32; 4];
-        self.bytes.read_f32_into::<LittleEndian>(&mut array)?;
-        let orientation = glam::Quat::from_xyzw(-array[0], array[1], array[2], -array[3]);
-
-        let mut vec = [0_f32; 3];
-        self.bytes.read_f32_into::<LittleEndian>(&mut vec)?;
-        let acceleration = glam::Vec3A::new(vec[0], vec[2], vec[1]);
+        read_tracker_data(self.bytes, tracker_index, encoding).map(Some)
+    }
+}
 
-        Ok(Some(UdpTrackerData {
-            tracker_index,
-            orientation,
-            acceleration,
-        }))
+/// Reads the orientation and acceleration of a tracker
+fn read_tracker_data(
+    bytes: &mut impl Read,
+    tracker_index: u8,
+    encoding: DataEncoding,
+) -> std::io::Result<UdpTrackerData> {
+    let mut array = [0_f32; 4];
+    let mut vec = [0_f32; 3];
+    match encoding {
+        DataEncoding::Full => {
+            bytes.read_f32_into::<LittleEndian>(&mut array)?;
+            bytes.read_f32_into::<LittleEndian>(&mut vec)?;
+        }
+        DataEncoding::Compact | DataEncoding::Compact32 => {
+            array = match encoding {
+                DataEncoding::Compact32 => {
+                    decode_quaternion(bytes.read_u32::<LittleEndian>()? as u64, 10)
+                }
+                _ => decode_quaternion(bytes.read_u48::<LittleEndian>()?, 15),
+            };
+            for value in &mut vec {
+                *value = bytes.read_i16::<LittleEndian>()? as f32 / ACCELERATION_SCALE;
+            }
+        }
     }
+
+    let orientation = glam::Quat::from_xyzw(-array[0], array[1], array[2], -array[3]);
+    let acceleration = glam::Vec3A::new(vec[0], vec[2], vec[1]);
+
+    Ok(UdpTrackerData {
+        tracker_index,
+        orientation,
+        acceleration,
+    })
 }
 
 #[derive(Debug)]
@@ -160,6 +412,285 @@ impl UdpPacketBatteryLevel {
     }
 }
 
+pub struct UdpPacketFirmwareVersion {
+    pub board: Box<str>,
+    pub version: Box<str>,
+}
+
+impl UdpPacketFirmwareVersion {
+    fn from_bytes(bytes: &mut impl Read) -> std::io::Result<Self> {
+        Ok(Self {
+            board: read_string(bytes)?,
+            version: read_string(bytes)?,
+        })
+    }
+
+    /// Advertises the newest firmware version the server has for the device's board
+    /// An empty version means there is no firmware available
+    pub fn to_response(latest_version: Option<&str>) -> Vec<u8> {
+        let version = latest_version.unwrap_or_default().as_bytes();
+        let version = &version[0..version.len().min(u8::MAX as usize)];
+        let mut bytes = vec![PACKET_FIRMWARE_VERSION, version.len() as u8];
+        bytes.extend(version);
+        bytes
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OtaAckStatus {
+    Ok = 0,
+    /// The device failed to write the firmware to flash
+    WriteFailed = 1,
+    /// The md5 of the received firmware didn't match
+    VerifyFailed = 2,
+}
+
+/// Sent by the device for every OTA packet it has processed
+#[derive(Debug)]
+pub struct UdpPacketOtaAck {
+    /// The type of OTA packet that is being acknowledged
+    pub packet_type: u8,
+    pub status: OtaAckStatus,
+    /// Amount of bytes of the firmware the device has received
+    pub offset: u32,
+}
+
+impl UdpPacketOtaAck {
+    fn from_bytes(bytes: &mut impl Read) -> std::io::Result<Self> {
+        Ok(Self {
+            packet_type: bytes.read_u8()?,
+            status: match bytes.read_u8()? {
+                0 => OtaAckStatus::Ok,
+                1 => OtaAckStatus::WriteFailed,
+                2 => OtaAckStatus::VerifyFailed,
+                _ => return Err(std::io::ErrorKind::InvalidData)?,
+            },
+            offset: bytes.read_u32::<LittleEndian>()?,
+        })
+    }
+}
+
+/// A log line, the message is the rest of the packet
+pub struct UdpPacketDeviceLog {
+    pub level: DeviceLogLevel,
+    pub message: Box<str>,
+}
+
+impl UdpPacketDeviceLog {
+    fn from_bytes(bytes: &mut impl Read) -> std::io::Result<Self> {
+        let level = DeviceLogLevel::from_u8(bytes.read_u8()?);
+        let mut message = Vec::new();
+        bytes.read_to_end(&mut message)?;
+        Ok(Self {
+            level,
+            message: String::from_utf8_lossy(&message).into(),
+        })
+    }
+}
+
+pub struct UdpPacketDeviceError {
+    /// 0xff if the error is not from a specific tracker
+    pub tracker_index: u8,
+    /// Error code defined by the firmware
+    pub code: u16,
+}
+
+impl UdpPacketDeviceError {
+    fn from_bytes(bytes: &mut impl Read) -> std::io::Result<Self> {
+        Ok(Self {
+            tracker_index: bytes.read_u8()?,
+            code: bytes.read_u16::<LittleEndian>()?,
+        })
+    }
+}
+
+/// Wifi rssi (i8), reset reason (u8) followed by the imu temperature of every tracker
+pub struct UdpPacketDiagnostics<'a, R: Read> {
+    pub wifi_rssi: i8,
+    pub reset_reason: ResetReason,
+    bytes: &'a mut R,
+}
+
+impl<'a, R: Read> UdpPacketDiagnostics<'a, R> {
+    fn from_bytes(bytes: &'a mut R) -> std::io::Result<Self> {
+        Ok(Self {
+            wifi_rssi: bytes.read_i8()?,
+            reset_reason: ResetReason::from_u8(bytes.read_u8()?),
+            bytes,
+        })
+    }
+
+    /// Returns the tracker index and its imu temperature in celsius
+    pub fn next_temperature(&mut self) -> anyhow::Result<Option<(u8, f32)>> {
+        self.read_temperature()
+            .map_err(check_truncated(PACKET_DIAGNOSTICS))
+    }
+
+    fn read_temperature(&mut self) -> std::io::Result<Option<(u8, f32)>> {
+        let tracker_index = self.bytes.read_u8()?;
+        // 0xff where the tracker id would usually go signifies the end of the packet
+        if tracker_index == 0xff {
+            return Ok(None);
+        }
+
+        Ok(Some((
+            tracker_index,
+            self.bytes.read_f32::<LittleEndian>()?,
+        )))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CommandAckStatus {
+    Ok = 0,
+    /// The firmware doesn't know the command or the tracker index
+    Unsupported = 1,
+    Failed = 2,
+}
+
+/// Sent by the device after it has handled a command
+#[derive(Debug)]
+pub struct UdpPacketCommandAck {
+    /// Sequence number of the command that is being acknowledged
+    pub sequence: u8,
+    pub status: CommandAckStatus,
+}
+
+impl UdpPacketCommandAck {
+    fn from_bytes(bytes: &mut impl Read) -> std::io::Result<Self> {
+        Ok(Self {
+            sequence: bytes.read_u8()?,
+            status: match bytes.read_u8()? {
+                0 => CommandAckStatus::Ok,
+                1 => CommandAckStatus::Unsupported,
+                2 => CommandAckStatus::Failed,
+                _ => return Err(std::io::ErrorKind::InvalidData)?,
+            },
+        })
+    }
+}
+
+/// Sequence (u16) followed by the id and the data of the wrapped packet
+pub struct UdpPacketReliable {
+    pub sequence: u16,
+    packet_id: u8,
+    data: Vec<u8>,
+}
+
+impl UdpPacketReliable {
+    fn from_bytes(bytes: &mut impl Read) -> std::io::Result<Self> {
+        let sequence = bytes.read_u16::<LittleEndian>()?;
+        let packet_id = bytes.read_u8()?;
+        if packet_id == PACKET_RELIABLE {
+            return Err(std::io::ErrorKind::InvalidData)?;
+        }
+
+        let mut data = Vec::new();
+        bytes.read_to_end(&mut data)?;
+        Ok(Self {
+            sequence,
+            packet_id,
+            data,
+        })
+    }
+
+    /// Returns the wrapped packet, the packet number is left at 0 since only the
+    /// packet number of the reliable packet gets checked
+    pub fn to_packet(&self) -> Vec<u8> {
+        let mut packet = vec![self.packet_id];
+        packet.extend(0_u32.to_le_bytes());
+        packet.extend(&self.data);
+        packet
+    }
+}
+
+pub struct UdpPacketReliableAck {
+    pub sequence: u16,
+}
+
+impl UdpPacketReliableAck {
+    fn from_bytes(bytes: &mut impl Read) -> std::io::Result<Self> {
+        Ok(Self {
+            sequence: bytes.read_u16::<LittleEndian>()?,
+        })
+    }
+}
+
+/// MCDIS from anything looking for the server, which doesn't have to be connected
+pub struct UdpPacketDiscovery;
+
+impl UdpPacketDiscovery {
+    fn from_bytes(bytes: &mut impl Read) -> std::io::Result<Self> {
+        if !bytes_equal(bytes, b"MCDIS") {
+            return Err(std::io::ErrorKind::InvalidData)?;
+        }
+        Ok(Self)
+    }
+
+    /// MCSVR + protocol version (u8), udp port (u16) and websocket port (u16)
+    pub fn to_response(&self, udp_port: u16, websocket_port: u16) -> Vec<u8> {
+        let mut bytes = vec![PACKET_DISCOVERY];
+        bytes.extend(b"MCSVR");
+        bytes.push(PROTOCOL_VERSION);
+        bytes.extend(udp_port.to_le_bytes());
+        bytes.extend(websocket_port.to_le_bytes());
+        bytes
+    }
+}
+
+/// Decodes a smallest three quaternion, which has the index of the largest component in the
+/// lowest 2 bits followed by the other components with `bits` bits each. The largest component
+/// is positive so it can be calculated from the others.
+pub fn decode_quaternion(encoded: u64, bits: u32) -> [f32; 4] {
+    let max = ((1 << bits) - 1) as f32;
+    let largest_index = (encoded & 0b11) as usize;
+
+    let mut quat = [0.; 4];
+    let mut shift = 2;
+    for (i, component) in quat.iter_mut().enumerate() {
+        if i != largest_index {
+            let value = ((encoded >> shift) & ((1 << bits) - 1)) as f32;
+            *component = (value / max * 2. - 1.) * std::f32::consts::FRAC_1_SQRT_2;
+            shift += bits;
+        }
+    }
+
+    let sum: f32 = quat.iter().map(|c| c * c).sum();
+    quat[largest_index] = (1. - sum).max(0.).sqrt();
+    quat
+}
+
+/// The packet ended before everything in it could be read
+#[derive(Debug, PartialEq)]
+pub struct TruncatedPacket {
+    pub packet_id: u8,
+}
+
+impl std::fmt::Display for TruncatedPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Packet 0x{:02x} is truncated", self.packet_id)
+    }
+}
+
+impl std::error::Error for TruncatedPacket {}
+
+/// Turns running out of bytes into a `TruncatedPacket` error
+fn check_truncated(packet_id: u8) -> impl FnOnce(std::io::Error) -> anyhow::Error {
+    move |err| match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => TruncatedPacket { packet_id }.into(),
+        _ => err.into(),
+    }
+}
+
+/// Reads a string prefixed with its length
+fn read_string(bytes: &mut impl Read) -> std::io::Result<Box<str>> {
+    let mut string = vec![0; bytes.read_u8()? as usize];
+    bytes.read_exact(&mut string)?;
+    String::from_utf8(string)
+        .map(Box::from)
+        .map_err(|_| std::io::ErrorKind::InvalidData.into())
+}
+
 fn bytes_equal(bytes: &mut impl Read, slice: &[u8]) -> bool {
     for expected in slice {
         if bytes.read_u8().ok() != Some(*expected) {
@@ -169,3 +700,111 @@ fn bytes_equal(bytes: &mut impl Read, slice: &[u8]) -> bool {
 
     true
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::udp::client;
+
+    #[test]
+    fn truncated_tracker_data() {
+        let mut bytes = vec![PACKET_TRACKER_DATA, 1, 0, 0, 0];
+        for tracker_index in 0..16 {
+            bytes.push(tracker_index);
+            bytes.extend([0; 7 * 4]);
+        }
+
+        let mut truncated = &bytes[..bytes.len() - 3];
+        let (UdpPacket::TrackerData(mut packet), _) = UdpPacket::parse(&mut truncated).unwrap()
+        else {
+            panic!("Not parsed as tracker data");
+        };
+        for _ in 0..15 {
+            assert!(packet.next_data(DataEncoding::Full).unwrap().is_some());
+        }
+        let err = packet.next_data(DataEncoding::Full).err().unwrap();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&TruncatedPacket {
+                packet_id: PACKET_TRACKER_DATA
+            })
+        );
+
+        // The end marker is missing
+        let mut complete = &bytes[..];
+        let (UdpPacket::TrackerData(mut packet), _) = UdpPacket::parse(&mut complete).unwrap()
+        else {
+            panic!("Not parsed as tracker data");
+        };
+        while let Ok(Some(_)) = packet.next_data(DataEncoding::Full) {}
+        assert!(packet.next_data(DataEncoding::Full).is_err());
+    }
+
+    #[test]
+    fn tracker_samples() {
+        let mut bytes = vec![PACKET_TRACKER_SAMPLES, 1, 0, 0, 0];
+        bytes.extend(1_000_000_u64.to_le_bytes());
+        for (tracker_index, ages_us) in [(0, &[20_000_u32, 10_000, 0][..]), (3, &[5_000])] {
+            bytes.extend([tracker_index, ages_us.len() as u8]);
+            for age_us in ages_us {
+                bytes.extend(age_us.to_le_bytes());
+                bytes.extend([0; 7 * 4]);
+            }
+        }
+        bytes.push(0xff);
+
+        let mut bytes = bytes.as_slice();
+        let (UdpPacket::TrackerSamples(mut packet), _) = UdpPacket::parse(&mut bytes).unwrap()
+        else {
+            panic!("Not parsed as tracker samples");
+        };
+        let mut samples = Vec::new();
+        while let Some(sample) = packet.next_sample(DataEncoding::Full).unwrap() {
+            samples.push((sample.data.tracker_index, sample.timestamp_us));
+        }
+        assert_eq!(
+            samples,
+            [(0, 980_000), (0, 990_000), (0, 1_000_000), (3, 995_000)]
+        );
+    }
+
+    #[test]
+    fn compact_quaternion() {
+        let quats = [
+            glam::Quat::IDENTITY,
+            glam::Quat::from_xyzw(0., -1., 0., 0.),
+            glam::Quat::from_euler(glam::EulerRot::YXZ, 1., -0.5, 2.5),
+            glam::Quat::from_euler(glam::EulerRot::YXZ, -3., 0.2, -1.),
+        ];
+        for quat in quats {
+            for (bits, epsilon) in [(15, 1e-4), (10, 2e-3)] {
+                let encoded = client::encode_quaternion(quat.to_array(), bits);
+                assert!(encoded < 1 << (2 + 3 * bits));
+
+                let decoded = glam::Quat::from_array(decode_quaternion(encoded, bits));
+                // The quaternion can come back negated which is the same rotation
+                assert!(
+                    quat.dot(decoded).abs() > 1. - epsilon,
+                    "{quat} decoded as {decoded} with {bits} bits"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_header() {
+        let err = UdpPacket::parse(&mut &[PACKET_HANDSHAKE, 1, 0][..])
+            .err()
+            .unwrap();
+        assert!(err.is::<TruncatedPacket>());
+
+        let mut bytes: &[u8] = &[PACKET_BATTERY_LEVEL, 1, 0, 0, 0, 0];
+        let err = UdpPacket::parse(&mut bytes).err().unwrap();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&TruncatedPacket {
+                packet_id: PACKET_BATTERY_LEVEL
+            })
+        );
+    }
+}
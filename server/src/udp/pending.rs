@@ -0,0 +1,81 @@
+//! Devices that are waiting for the user to approve them
+//!
+//! While `UdpConfig::require_approval` is set, handshakes from devices that aren't known are not
+//! answered. The device keeps sending handshakes, which keeps it in the pending list until the
+//! user approves or rejects it.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::{tracker::DeviceInfo, udp::packet::UdpPacketHandshake};
+
+/// Pending devices are forgotten when they stop sending handshakes
+const PENDING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+pub struct PendingDevice {
+    pub mac: Arc<str>,
+    pub address: SocketAddr,
+    #[ts(optional)]
+    pub device_info: Option<DeviceInfo>,
+    #[ts(optional)]
+    pub firmware_version: Option<Box<str>>,
+}
+
+#[derive(Default)]
+pub struct PendingDevices {
+    devices: HashMap<Arc<str>, (PendingDevice, Instant)>,
+    changed: bool,
+}
+
+impl PendingDevices {
+    pub fn insert(&mut self, packet: &UdpPacketHandshake, address: SocketAddr) {
+        let device = PendingDevice {
+            mac: packet.mac_address.clone(),
+            address,
+            device_info: packet.device_info.clone(),
+            firmware_version: packet.firmware_version.clone(),
+        };
+
+        let previous = self
+            .devices
+            .insert(device.mac.clone(), (device.clone(), Instant::now()));
+        if previous.map(|(previous, _)| previous) != Some(device) {
+            log::info!("Device {} is waiting for approval", packet.mac_address);
+            self.changed = true;
+        }
+    }
+
+    pub fn remove(&mut self, mac: &str) -> Option<PendingDevice> {
+        let (device, _) = self.devices.remove(mac)?;
+        self.changed = true;
+        Some(device)
+    }
+
+    pub fn remove_stale(&mut self) {
+        let count = self.devices.len();
+        self.devices
+            .retain(|_, (_, last_seen)| last_seen.elapsed() < PENDING_TIMEOUT);
+        self.changed |= self.devices.len() != count;
+    }
+
+    /// Returns every pending device if they have changed since the last call
+    pub fn take_changed(&mut self) -> Option<Vec<PendingDevice>> {
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+        Some(
+            self.devices
+                .values()
+                .map(|(device, _)| device.clone())
+                .collect(),
+        )
+    }
+}
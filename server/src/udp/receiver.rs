@@ -0,0 +1,133 @@
+//! Receiving from the udp socket on its own task
+//!
+//! The main loop only runs every few milliseconds, so the datagrams are read as soon as they
+//! arrive and timestamped before being queued for the server. This keeps the socket buffer from
+//! overflowing while the loop is busy and lets the timing of the packets be based on when they
+//! actually arrived.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        mpsc::{Receiver, TrySendError},
+        Arc, Weak,
+    },
+    task::Poll,
+    time::Instant,
+};
+
+use tokio::{io::ReadBuf, net::UdpSocket, sync::Notify, task::JoinHandle};
+
+use crate::udp::{
+    auth,
+    packet::{PACKET_TRACKER_DATA, PACKET_TRACKER_SAMPLES},
+};
+
+/// Largest payload a udp datagram can have
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+/// Datagrams queued for the main loop before new ones get dropped
+const QUEUE_SIZE: usize = 4096;
+
+pub struct Datagram {
+    pub bytes: Vec<u8>,
+    pub peer_addr: SocketAddr,
+    pub received: Instant,
+}
+
+/// Receives datagrams from the socket until dropped
+pub struct DatagramReceiver {
+    rx: Receiver<std::io::Result<Datagram>>,
+    task: JoinHandle<()>,
+}
+
+impl DatagramReceiver {
+    /// Notifies `received` every time a datagram with tracker data has been queued
+    pub fn spawn(socket: Arc<UdpSocket>, received: Arc<Notify>) -> Self {
+        let (tx, rx) = std::sync::mpsc::sync_channel(QUEUE_SIZE);
+        let socket = Arc::downgrade(&socket);
+        let task = tokio::spawn(async move {
+            let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+            loop {
+                // Stop when the socket has been dropped
+                let Some(result) = receive(&socket, &mut buffer).await else {
+                    return;
+                };
+
+                let has_tracker_data =
+                    matches!(&result, Ok(datagram) if has_tracker_data(&datagram.bytes));
+                match tx.try_send(result) {
+                    Ok(()) if has_tracker_data => received.notify_one(),
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        log::trace!("Udp receive queue is full, dropping datagram");
+                    }
+                    // Stop when the server has been dropped
+                    Err(TrySendError::Disconnected(_)) => return,
+                }
+            }
+        });
+
+        Self { rx, task }
+    }
+
+    /// Returns the next datagram that has been received, None if there are none queued
+    pub fn try_next(&mut self) -> Option<std::io::Result<Datagram>> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// Pings and status packets don't need the main loop to run early, only new tracker data does
+fn has_tracker_data(bytes: &[u8]) -> bool {
+    matches!(
+        auth::packet_id(bytes),
+        Some(PACKET_TRACKER_DATA | PACKET_TRACKER_SAMPLES)
+    )
+}
+
+/// Only holds on to the socket while polling so the port is freed as soon as the server
+/// drops the socket, otherwise binding it again right after could fail
+async fn receive(socket: &Weak<UdpSocket>, buffer: &mut [u8]) -> Option<std::io::Result<Datagram>> {
+    std::future::poll_fn(|cx| {
+        let Some(socket) = socket.upgrade() else {
+            return Poll::Ready(None);
+        };
+
+        let mut buffer = ReadBuf::new(buffer);
+        socket.poll_recv_from(cx, &mut buffer).map(|result| {
+            Some(result.map(|peer_addr| Datagram {
+                bytes: buffer.filled().to_vec(),
+                peer_addr,
+                received: Instant::now(),
+            }))
+        })
+    })
+    .await
+}
+
+impl Drop for DatagramReceiver {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::udp::packet::{PACKET_PING_PONG, PACKET_TRACKER_STATUS};
+
+    #[test]
+    fn only_tracker_data() {
+        assert!(has_tracker_data(&[PACKET_TRACKER_DATA, 1, 0, 0, 0]));
+        assert!(has_tracker_data(&[PACKET_TRACKER_SAMPLES, 1, 0, 0, 0]));
+        assert!(!has_tracker_data(&[PACKET_PING_PONG, 0, 0, 0, 0, 1]));
+        assert!(!has_tracker_data(&[PACKET_TRACKER_STATUS, 1, 0, 0, 0]));
+        assert!(!has_tracker_data(&[]));
+
+        // Authenticated packets are checked by what they wrap
+        let session = auth::AuthSession::new(auth::generate_key(), b"nonce");
+        let sealed = session.seal(
+            auth::Direction::FromDevice,
+            &[1, 0, 0, 0, PACKET_TRACKER_DATA],
+        );
+        assert!(has_tracker_data(&sealed));
+    }
+}
@@ -0,0 +1,148 @@
+//! Reliable delivery of control packets for devices that negotiated `CAPABILITY_RELIABLE`
+//!
+//! A reliable packet is `PACKET_RELIABLE` + sequence (u16) followed by the wrapped packet.
+//! Packets from the device also have the packet number after `PACKET_RELIABLE` like any other
+//! packet, the wrapped packet doesn't have its own. The receiver answers every reliable packet with
+//! `PACKET_RELIABLE_ACK` + sequence (u16), including duplicates since the previous ack could
+//! have been lost. The sender retransmits with backoff until it gets the ack.
+//! Streaming packets like `PACKET_TRACKER_DATA` are never sent reliably.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::udp::packet::{PACKET_RELIABLE, PACKET_RELIABLE_ACK};
+
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(200);
+const MAX_TIMEOUT: Duration = Duration::from_millis(2000);
+const MAX_ATTEMPTS: u32 = 8;
+/// How long to wait for the response to a packet sent with the channel before giving up,
+/// longer than all the attempts take
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(12);
+/// Amount of received sequence numbers remembered to drop duplicates
+const RECEIVED_HISTORY: usize = 64;
+
+struct PendingPacket {
+    sequence: u16,
+    packet: Vec<u8>,
+    sent_time: Option<Instant>,
+    timeout: Duration,
+    attempts: u32,
+}
+
+#[derive(Default)]
+pub struct ReliableChannel {
+    pending: Vec<PendingPacket>,
+    next_sequence: u16,
+    received: VecDeque<u16>,
+}
+
+impl ReliableChannel {
+    /// Wraps the packet so it gets sent until the device acknowledges it
+    pub fn push(&mut self, packet: &[u8]) {
+        let mut wrapped = vec![PACKET_RELIABLE];
+        wrapped.extend(self.next_sequence.to_le_bytes());
+        wrapped.extend(packet);
+
+        self.pending.push(PendingPacket {
+            sequence: self.next_sequence,
+            packet: wrapped,
+            sent_time: None,
+            timeout: INITIAL_TIMEOUT,
+            attempts: 0,
+        });
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+    }
+
+    /// Returns the packets that should be sent now, packets that ran out of attempts are dropped
+    pub fn poll(&mut self, mac: &str) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+
+        self.pending.retain_mut(|pending| {
+            match pending.sent_time {
+                Some(time) if time.elapsed() < pending.timeout => return true,
+                // Back off so a device with a bad connection doesn't get flooded
+                Some(_) => pending.timeout = (pending.timeout * 2).min(MAX_TIMEOUT),
+                None => {}
+            }
+
+            if pending.attempts >= MAX_ATTEMPTS {
+                log::warn!(
+                    "{mac} did not acknowledge packet 0x{:02x}",
+                    pending.packet[3]
+                );
+                return false;
+            }
+
+            packets.push(pending.packet.clone());
+            pending.sent_time = Some(Instant::now());
+            pending.attempts += 1;
+            true
+        });
+
+        packets
+    }
+
+    pub fn handle_ack(&mut self, sequence: u16) {
+        self.pending.retain(|pending| pending.sequence != sequence);
+    }
+
+    /// Returns false if the packet with the sequence was already received
+    pub fn receive(&mut self, sequence: u16) -> bool {
+        if self.received.contains(&sequence) {
+            return false;
+        }
+
+        if self.received.len() >= RECEIVED_HISTORY {
+            self.received.pop_front();
+        }
+        self.received.push_back(sequence);
+        true
+    }
+}
+
+pub fn ack_packet(sequence: u16) -> [u8; 3] {
+    let [low, high] = sequence.to_le_bytes();
+    [PACKET_RELIABLE_ACK, low, high]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retransmit_until_ack() {
+        let mut channel = ReliableChannel::default();
+        channel.push(&[0x05, 1, 2]);
+        channel.push(&[0x02, 0, 0]);
+
+        let packets = channel.poll("test");
+        assert_eq!(
+            packets,
+            [
+                [PACKET_RELIABLE, 0, 0, 0x05, 1, 2],
+                [PACKET_RELIABLE, 1, 0, 0x02, 0, 0]
+            ]
+        );
+        // Nothing is sent again before the timeout
+        assert!(channel.poll("test").is_empty());
+
+        channel.handle_ack(0);
+        std::thread::sleep(INITIAL_TIMEOUT);
+        assert_eq!(channel.poll("test"), [[PACKET_RELIABLE, 1, 0, 0x02, 0, 0]]);
+
+        channel.handle_ack(1);
+        assert!(channel.pending.is_empty());
+    }
+
+    #[test]
+    fn drop_duplicates() {
+        let mut channel = ReliableChannel::default();
+        assert!(channel.receive(u16::MAX));
+        assert!(channel.receive(0));
+        assert!(!channel.receive(u16::MAX));
+        assert!(channel.receive(1));
+        assert!(!channel.receive(0));
+    }
+}
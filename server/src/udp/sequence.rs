@@ -0,0 +1,182 @@
+//! Tracks the packet numbers of a device to drop stale packets and measure packet loss
+//!
+//! Packet numbers are compared with wraparound so a device can keep counting past `u32::MAX`.
+//! Packets that arrive late are dropped since newer data has already been used, but they are
+//! counted as out of order instead of lost. Packet number 0 is used by packets that aren't
+//! numbered, like pongs, so they are let through without being counted. A number far behind the
+//! latest one means the device has restarted without a handshake so the sequence starts over.
+//! Authenticated devices have to do a handshake for a new session anyway, so for them this would
+//! only let replayed packets through.
+
+/// Amount of packets before the latest one that are remembered
+const WINDOW: u32 = 64;
+/// Packets this far behind the latest one are from a device that has restarted
+const RESTART_DISTANCE: u32 = 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PacketStats {
+    pub received: u32,
+    pub lost: u32,
+    pub out_of_order: u32,
+}
+
+impl PacketStats {
+    /// Fraction of the packets that were lost, None if nothing was sent
+    pub fn loss_rate(&self) -> Option<f32> {
+        let total = self.received + self.lost;
+        (total > 0).then(|| self.lost as f32 / total as f32)
+    }
+}
+
+#[derive(Default)]
+pub struct PacketSequence {
+    latest: Option<u32>,
+    /// Bit n is set if the packet n before the latest one was received
+    received_mask: u64,
+    stats: PacketStats,
+    /// Don't start over without a handshake
+    authenticated: bool,
+}
+
+impl PacketSequence {
+    /// Returns true if the packet is newer than every packet before it
+    pub fn check(&mut self, number: u32) -> bool {
+        if number == 0 {
+            return true;
+        }
+
+        let Some(latest) = self.latest else {
+            self.latest = Some(number);
+            self.received_mask = 1;
+            self.stats.received += 1;
+            return true;
+        };
+
+        let ahead = number.wrapping_sub(latest);
+        if ahead == 0 {
+            return false;
+        }
+
+        if ahead < u32::MAX / 2 {
+            // Packet number 0 is skipped when wrapping around since it's never counted
+            let skipped = if number < latest { 2 } else { 1 };
+            self.stats.lost += ahead.saturating_sub(skipped);
+            self.stats.received += 1;
+            self.received_mask = self.received_mask.checked_shl(ahead).unwrap_or(0) | 1;
+            self.latest = Some(number);
+            return true;
+        }
+
+        let behind = latest.wrapping_sub(number);
+        if behind < WINDOW {
+            let bit = 1 << behind;
+            if self.received_mask & bit == 0 {
+                // It was counted as lost when the packets after it arrived
+                self.received_mask |= bit;
+                self.stats.lost = self.stats.lost.saturating_sub(1);
+                self.stats.received += 1;
+                self.stats.out_of_order += 1;
+            }
+        } else if behind > RESTART_DISTANCE && !self.authenticated {
+            log::info!("Packet #{number} is far behind #{latest}, assuming the device restarted");
+            self.reset();
+            return self.check(number);
+        } else {
+            self.stats.out_of_order += 1;
+        }
+
+        false
+    }
+
+    pub fn set_authenticated(&mut self, authenticated: bool) {
+        self.authenticated = authenticated;
+    }
+
+    pub fn reset(&mut self) {
+        self.latest = None;
+        self.received_mask = 0;
+    }
+
+    /// Returns the statistics since the last call
+    pub fn take_stats(&mut self) -> PacketStats {
+        std::mem::take(&mut self.stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wraparound() {
+        let mut sequence = PacketSequence::default();
+        assert!(sequence.check(u32::MAX - 1));
+        assert!(sequence.check(u32::MAX));
+        assert!(sequence.check(0));
+        assert!(sequence.check(1));
+        assert!(!sequence.check(u32::MAX));
+        assert!(!sequence.check(1));
+        assert_eq!(sequence.take_stats().lost, 0);
+    }
+
+    #[test]
+    fn loss_and_reorder() {
+        let mut sequence = PacketSequence::default();
+        for number in [1, 2, 5, 4, 6, 10] {
+            sequence.check(number);
+        }
+
+        let stats = sequence.take_stats();
+        assert_eq!(
+            stats,
+            PacketStats {
+                received: 6,
+                lost: 4,
+                out_of_order: 1,
+            }
+        );
+        assert_eq!(stats.loss_rate(), Some(0.4));
+        // Duplicates aren't counted again
+        assert!(!sequence.check(4));
+        assert_eq!(sequence.take_stats(), PacketStats::default());
+    }
+
+    #[test]
+    fn restart() {
+        let mut sequence = PacketSequence::default();
+        assert!(sequence.check(50000));
+        assert!(!sequence.check(49990));
+        assert!(sequence.check(1));
+        assert!(sequence.check(2));
+    }
+
+    #[test]
+    fn unnumbered_packets() {
+        let mut sequence = PacketSequence::default();
+        assert!(sequence.check(1));
+        assert!(sequence.check(2));
+        // Pongs are always #0, they don't restart the sequence
+        assert!(sequence.check(0));
+        assert!(sequence.check(3));
+        assert!(sequence.check(0));
+        assert!(!sequence.check(2));
+        assert!(sequence.check(4));
+        assert_eq!(
+            sequence.take_stats(),
+            PacketStats {
+                received: 4,
+                lost: 0,
+                out_of_order: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn authenticated_no_restart() {
+        let mut sequence = PacketSequence::default();
+        sequence.set_authenticated(true);
+        assert!(sequence.check(50000));
+        assert!(!sequence.check(1));
+        assert!(sequence.check(50001));
+    }
+}
@@ -1,163 +1,654 @@
-use futures_util::FutureExt;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
-    net::{Ipv4Addr, SocketAddr},
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::Arc,
     time::{Duration, Instant},
 };
 
+use tokio::sync::Notify;
+use ts_rs::TS;
+
 use crate::{
+    config::GlobalConfig,
+    firmware::FirmwareFiles,
+    firmware::FirmwareUpdateStatus,
+    input::InputSource,
     main_server::MainServer,
     udp::{
+        auth::{self, AuthSession, Direction, PairedDevices},
+        command::{CommandResult, DeviceCommand},
         device::UdpDevice,
-        packet::{UdpPacket, UdpPacketHandshake},
+        diagnostics::{DeviceLog, DeviceLogFile, DeviceLogLevel},
+        discovery::MdnsAdvertiser,
+        ota::FirmwareUpdate,
+        packet::{
+            TruncatedPacket, UdpPacket, UdpPacketFirmwareVersion, UdpPacketHandshake,
+            CAPABILITY_COMMANDS, CAPABILITY_OTA, CAPABILITY_RELIABLE, PACKET_AUTHENTICATED,
+            PACKET_DISCOVERY, PACKET_OTA_CHUNK,
+        },
+        pending::PendingDevices,
+        receiver::DatagramReceiver,
+        reliable::{self, ReliableChannel},
+        socket,
     },
+    websocket::WEBSOCKET_PORT,
 };
 
 pub const UDP_PORT: u16 = 5828;
-pub const MULTICAST_IP: Ipv4Addr = Ipv4Addr::new(239, 255, 0, 123);
 
 const UPKEEP_INTERVAL: Duration = Duration::from_millis(1000);
+/// Max amount of device logs kept if they're not being taken
+const MAX_DEVICE_LOGS: usize = 100;
+/// How long a paired device has to answer the handshake response before it has to start over
+const PENDING_SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(default)]
+pub struct UdpConfig {
+    /// Use `::` to also accept IPv6 devices
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// Name of the network interface to receive multicast on, the system chooses if not set
+    #[ts(optional)]
+    pub multicast_interface: Option<Box<str>>,
+    /// Advertise the server with mDNS for networks that filter the multicast discovery
+    pub advertise_mdns: bool,
+    /// Only accept devices that have been paired over serial
+    pub require_pairing: bool,
+    /// Mac addresses of the devices that are not allowed to connect
+    pub ignored_devices: Vec<Arc<str>>,
+    /// Only accept devices that have trackers from before, have been paired or approved
+    pub only_known_devices: bool,
+    /// New devices have to be approved by the user before they can connect
+    pub require_approval: bool,
+    /// Mac addresses of the devices the user has approved
+    pub approved_devices: Vec<Arc<str>>,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: Ipv4Addr::UNSPECIFIED.into(),
+            port: UDP_PORT,
+            multicast_interface: None,
+            advertise_mdns: true,
+            require_pairing: false,
+            ignored_devices: Vec::new(),
+            only_known_devices: false,
+            require_approval: false,
+            approved_devices: Vec::new(),
+        }
+    }
+}
+
+impl UdpConfig {
+    /// Returns true if the socket has to be bound again to apply the other config
+    fn socket_changed(&self, other: &UdpConfig) -> bool {
+        self.bind_address != other.bind_address
+            || self.port != other.port
+            || self.multicast_interface != other.multicast_interface
+    }
+
+    pub fn is_ignored(&self, mac: &str) -> bool {
+        self.ignored_devices.iter().any(|ignored| &**ignored == mac)
+    }
+
+    /// Makes the same changes to the ignored and approved devices as from `old` to `changed`,
+    /// so a config from the user doesn't undo what the server has just changed
+    pub fn merge_device_lists(&mut self, old: &UdpConfig, changed: &UdpConfig) {
+        merge_list(
+            &mut self.ignored_devices,
+            &old.ignored_devices,
+            &changed.ignored_devices,
+        );
+        merge_list(
+            &mut self.approved_devices,
+            &old.approved_devices,
+            &changed.approved_devices,
+        );
+    }
+}
+
+fn merge_list(list: &mut Vec<Arc<str>>, old: &[Arc<str>], changed: &[Arc<str>]) {
+    list.retain(|mac| !old.contains(mac) || changed.contains(mac));
+    for mac in changed {
+        if !old.contains(mac) && !list.contains(mac) {
+            list.push(mac.clone());
+        }
+    }
+}
+
+/// Handshake of a paired device that is only connected once it sends a packet in the new session,
+/// so a recorded handshake that is sent again can't take over the device
+struct PendingSession {
+    handshake: UdpPacketHandshake,
+    session: AuthSession,
+    created: Instant,
+}
 
 pub struct UdpServer {
     // Maps a network address to a udp device
     devices_map: HashMap<SocketAddr, UdpDevice>,
     mac_to_address_map: HashMap<Arc<str>, SocketAddr>,
-    socket: tokio::net::UdpSocket,
-    /// Set of address that should not be allowed to connect
-    /// This is to allow for servers to ignore ignored trackers that are trying to connect
-    address_blacklist: HashSet<SocketAddr>,
+    socket: Arc<tokio::net::UdpSocket>,
+    receiver: DatagramReceiver,
+    /// Notified when a datagram with tracker data has been received
+    received: Arc<Notify>,
     last_upkeep_time: Instant,
+    log_file: DeviceLogFile,
+    config: UdpConfig,
+    paired_devices: PairedDevices,
+    pending_devices: PendingDevices,
+    pending_sessions: HashMap<SocketAddr, PendingSession>,
+    firmware_files: FirmwareFiles,
+    mdns: MdnsAdvertiser,
 }
 
 impl UdpServer {
-    pub async fn new() -> anyhow::Result<Self> {
-        let socket = tokio::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, UDP_PORT)).await?;
-        socket.join_multicast_v4(MULTICAST_IP, Ipv4Addr::UNSPECIFIED)?;
-        log::info!("Started UDP server on {}", socket.local_addr()?);
+    pub async fn new(config: &UdpConfig) -> anyhow::Result<Self> {
+        let config = config.clone();
+        let socket = Arc::new(socket::bind(&config)?);
+        let received = Arc::new(Notify::new());
+
+        let mut firmware_files = FirmwareFiles::default();
+        firmware_files.rescan();
 
         Ok(Self {
             devices_map: HashMap::new(),
             mac_to_address_map: HashMap::new(),
-            address_blacklist: HashSet::new(),
             last_upkeep_time: Instant::now(),
+            log_file: DeviceLogFile::default(),
+            config,
+            paired_devices: PairedDevices::load().unwrap_or_else(|err| {
+                log::warn!("Failed to load paired devices: {err}");
+                PairedDevices::default()
+            }),
+            pending_devices: PendingDevices::default(),
+            pending_sessions: HashMap::new(),
+            firmware_files,
+            mdns: MdnsAdvertiser::default(),
+            receiver: DatagramReceiver::spawn(socket.clone(), received.clone()),
+            received,
             socket,
         })
     }
 
-    pub async fn update(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
-        if self.last_upkeep_time.elapsed() > UPKEEP_INTERVAL {
-            self.upkeep().await?;
-            self.last_upkeep_time = Instant::now();
-        }
-
-        let mut buffer = [0; 256];
-        loop {
-            // Try and get all the packets that were received
-            match self.socket.recv_from(&mut buffer).now_or_never() {
-                Some(Ok((amount, peer_addr))) => {
-                    if self.address_blacklist.contains(&peer_addr) {
-                        continue;
-                    }
-
-                    log::trace!(
-                        "Received {amount} bytes from {peer_addr} (0x{:02x})",
-                        buffer[0]
-                    );
-
-                    // Only pass through the amount received
-                    let bytes = &buffer[0..amount];
-                    if let Err(err) = self.handle_packet(bytes, peer_addr, main).await {
-                        log::trace!("Received invalid packet 0x{:02x}: {err:?}", bytes[0]);
-                    }
-                }
-                // No new data currently
-                None => return Ok(()),
-                Some(Err(e)) => return Err(e)?,
-            }
-        }
+    /// Notified every time tracker data has been received, stays the same when the socket is bound again
+    pub fn packet_received(&self) -> Arc<Notify> {
+        self.received.clone()
     }
 
-    pub(crate) async fn upkeep(&mut self) -> anyhow::Result<()> {
+    pub(crate) async fn upkeep(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
         let mut to_remove = None;
 
         for device in self.devices_map.values_mut() {
             device.update_timed_out(device.is_timed_out());
+            let quality = device.update_link_quality();
+            main.updates
+                .link_qualities
+                .insert(device.mac.clone(), quality);
 
             let bytes = device.check_get_ping_packet().to_response();
-            self.socket.send_to(&bytes, device.address).await?;
+            send_to_device(&self.socket, device, &bytes).await?;
 
             // When the user has removed every tracker from the device prevent it from connecting anymore
             if device.all_trackers_removed() {
-                self.mac_to_address_map.remove(&device.mac);
-                self.address_blacklist.insert(device.address);
-                log::info!("Added {} to blacklist", device.address);
                 to_remove = Some(device.address);
             }
         }
 
+        self.pending_devices.remove_stale();
+        self.firmware_files.rescan_if_stale();
+        self.pending_sessions
+            .retain(|_, pending| pending.created.elapsed() < PENDING_SESSION_TIMEOUT);
+        if let Some(devices) = self.pending_devices.take_changed() {
+            main.updates.pending_devices = Some(devices);
+        }
+
         if let Some(address) = to_remove {
-            self.devices_map.remove(&address);
+            if let Some(device) = self.devices_map.remove(&address) {
+                self.mac_to_address_map.remove(&device.mac);
+                main.updates.link_qualities.remove(&device.mac);
+                log::info!("Ignoring device {}", device.mac);
+
+                let config = main
+                    .updates
+                    .config
+                    .get_or_insert_with(|| main.config.clone());
+                if !config.udp.is_ignored(&device.mac) {
+                    config.udp.ignored_devices.push(device.mac);
+                }
+            }
         }
 
         Ok(())
     }
 
+    async fn receive_packets(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+        loop {
+            // Handle all the packets that were received since the last update
+            match self.receiver.try_next() {
+                Some(Ok(datagram)) => {
+                    let (bytes, peer_addr) = (&datagram.bytes, datagram.peer_addr);
+                    let amount = bytes.len();
+                    let Some(&packet_id) = bytes.first() else {
+                        continue;
+                    };
+                    log::trace!("Received {amount} bytes from {peer_addr} (0x{packet_id:02x})");
+
+                    let result = self
+                        .handle_packet(bytes, peer_addr, datagram.received, main)
+                        .await;
+                    if let Err(err) = result {
+                        if err.is::<TruncatedPacket>() {
+                            log::warn!("Received {amount} bytes from {peer_addr}: {err}");
+                        } else {
+                            log::trace!("Received invalid packet 0x{packet_id:02x}: {err:?}");
+                        }
+                    }
+                }
+                // No new data currently
+                None => return Ok(()),
+                Some(Err(e)) => return Err(e)?,
+            }
+        }
+    }
+
     async fn handle_packet(
         &mut self,
-        mut bytes: &[u8],
+        bytes: &[u8],
         peer_addr: SocketAddr,
+        received: Instant,
         main: &mut MainServer,
     ) -> anyhow::Result<()> {
-        let mut device = self
-            .devices_map
-            .get_mut(&peer_addr)
-            .ok_or_else(|| anyhow::anyhow!("No device with address: {peer_addr}"));
-
+        let size = bytes.len();
+        let bytes = self.authenticate(bytes, peer_addr)?;
+        let mut bytes = bytes.as_ref();
         let (packet, packet_number) = UdpPacket::parse(&mut bytes)?;
 
-        if let Ok(device) = device.as_mut() {
-            device.last_packet_received_time = Instant::now();
+        if let Some(device) = self.devices_map.get_mut(&peer_addr) {
+            device.last_packet_received_time = received;
+            device.link.add_received_bytes(size);
 
-            // Discard the packet if not the latest
-            if !device.check_latest_packet_number(packet_number) {
+            // Discard the packet if not the latest, handshakes start a new sequence
+            let is_handshake = matches!(packet, UdpPacket::Handshake(_));
+            if !is_handshake && !device.sequence.check(packet_number) {
                 anyhow::bail!("Out of order #{packet_number}");
             }
         }
 
+        self.handle_parsed_packet(packet, peer_addr, main).await
+    }
+
+    /// Checks that packets of paired devices are authenticated and returns the packet inside
+    fn authenticate<'a>(
+        &mut self,
+        bytes: &'a [u8],
+        peer_addr: SocketAddr,
+    ) -> anyhow::Result<Cow<'a, [u8]>> {
+        let device = self.devices_map.get(&peer_addr);
+
+        // Anyone can look for the server
+        if bytes.first() == Some(&PACKET_DISCOVERY) {
+            return Ok(Cow::Borrowed(bytes));
+        }
+
+        if bytes.first() != Some(&PACKET_AUTHENTICATED) {
+            if self.config.require_pairing {
+                anyhow::bail!("Unauthenticated packet while pairing is required");
+            }
+            if device.is_some_and(|device| device.auth.is_some()) {
+                anyhow::bail!("Unauthenticated packet from a paired device");
+            }
+            // Don't let anyone else take over the mac address of a paired device
+            if let Ok((UdpPacket::Handshake(packet), _)) = UdpPacket::parse(&mut &bytes[..]) {
+                if self.paired_devices.get(&packet.mac_address).is_some() {
+                    anyhow::bail!("Unauthenticated handshake for {}", packet.mac_address);
+                }
+            }
+            return Ok(Cow::Borrowed(bytes));
+        }
+
+        let session = device.and_then(|device| device.auth.as_ref());
+        if let Some(Ok(body)) = session.map(|session| session.open(Direction::FromDevice, bytes)) {
+            return Ok(Cow::Owned(auth::device_packet(body)?));
+        }
+
+        // The first packet in the session started by the handshake connects the device
+        let pending = self.pending_sessions.get(&peer_addr);
+        if let Some(Ok(body)) =
+            pending.map(|pending| pending.session.open(Direction::FromDevice, bytes))
+        {
+            let packet = auth::device_packet(body)?;
+            let pending = self.pending_sessions.remove(&peer_addr).unwrap();
+            self.connect_device(pending.handshake, peer_addr, Some(pending.session))?;
+            return Ok(Cow::Owned(packet));
+        }
+
+        // Otherwise it has to be a handshake that starts a new session
+        let packet = auth::device_packet(auth::peek(bytes)?)?;
+        let (UdpPacket::Handshake(handshake), _) = UdpPacket::parse(&mut packet.as_slice())? else {
+            anyhow::bail!("Packet failed authentication");
+        };
+        let key = self
+            .paired_devices
+            .get(&handshake.mac_address)
+            .ok_or_else(|| anyhow::anyhow!("Device {} is not paired", handshake.mac_address))?;
+        AuthSession::new(*key, &[]).open(Direction::FromDevice, bytes)?;
+        Ok(Cow::Owned(packet))
+    }
+
+    async fn handle_parsed_packet(
+        &mut self,
+        packet: UdpPacket<'_, &[u8]>,
+        peer_addr: SocketAddr,
+        main: &mut MainServer,
+    ) -> anyhow::Result<()> {
+        let device = self
+            .devices_map
+            .get_mut(&peer_addr)
+            .ok_or_else(|| anyhow::anyhow!("No device with address: {peer_addr}"));
+
         match packet {
             UdpPacket::Handshake(packet) => {
-                let bytes = UdpPacketHandshake::SERVER_RESPONSE;
-                self.socket.send_to(bytes, peer_addr).await?;
-                self.handle_handshake(packet, peer_addr);
+                self.check_accepted(&packet.mac_address, main)?;
+                if self.config.require_approval && !self.is_known(&packet.mac_address, main) {
+                    self.pending_devices.insert(&packet, peer_addr);
+                    return Ok(());
+                }
+
+                let response = self.handle_handshake(packet, peer_addr)?;
+                self.socket.send_to(&response, peer_addr).await?;
             }
             UdpPacket::PingPong(packet) => {
                 device?.handle_pong(packet);
             }
             UdpPacket::TrackerData(mut packet) => {
                 let device = device?;
-                while let Some(data) = packet.next_data()? {
-                    device.update_tracker_data(data);
+                let received = device.last_packet_received_time;
+                while let Some(data) = packet.next_data(device.data_encoding())? {
+                    device.update_tracker_data(data, received);
                 }
             }
+            UdpPacket::TrackerSamples(packet) => {
+                let device = device?;
+                let received = device.last_packet_received_time;
+                device.update_tracker_samples(packet, received)?;
+            }
             UdpPacket::TrackerStatus(packet) => {
-                let bytes = packet.to_response();
-                self.socket.send_to(&bytes, peer_addr).await?;
-                device?.update_tracker_status(main, packet);
+                let device = device?;
+                send_control(&self.socket, device, &packet.to_response()).await?;
+                device.update_tracker_status(main, packet);
             }
             UdpPacket::BatteryLevel(packet) => {
                 device?.update_battery_level(packet);
             }
+            UdpPacket::FirmwareVersion(packet) => {
+                let device = device?;
+                let latest = self.firmware_files.find_latest(&packet.board);
+                let bytes = UdpPacketFirmwareVersion::to_response(latest.map(|f| &*f.version));
+                send_control(&self.socket, device, &bytes).await?;
+
+                let available = latest
+                    .filter(|file| file.is_newer_than(&packet.version))
+                    .map(|file| file.version.clone());
+                device.set_firmware(packet, available);
+            }
+            UdpPacket::OtaAck(packet) => {
+                let device = device?;
+                let update = device
+                    .firmware_update
+                    .as_mut()
+                    .ok_or_else(|| anyhow::anyhow!("No firmware update in progress"))?;
+
+                let status = match update.handle_ack(packet) {
+                    Ok(false) => return Ok(()),
+                    Ok(true) => {
+                        log::info!("Firmware update of {} finished", device.mac);
+                        FirmwareUpdateStatus::Done {
+                            version: update.version().into(),
+                        }
+                    }
+                    Err(err) => {
+                        log::warn!("Firmware update of {} failed: {err}", device.mac);
+                        FirmwareUpdateStatus::Failed {
+                            error: err.to_string().into(),
+                        }
+                    }
+                };
+
+                device.firmware_update = None;
+                main.updates
+                    .firmware_update_statuses
+                    .insert(device.mac.clone(), status);
+            }
+            UdpPacket::DeviceLog(packet) => {
+                let log = DeviceLog {
+                    mac: device?.mac.clone(),
+                    level: packet.level,
+                    message: packet.message,
+                };
+                push_device_log(&mut self.log_file, main, log);
+            }
+            UdpPacket::DeviceError(packet) => {
+                let message = match packet.tracker_index {
+                    0xff => format!("Error code {}", packet.code),
+                    index => format!("Tracker {index} error code {}", packet.code),
+                };
+                let log = DeviceLog {
+                    mac: device?.mac.clone(),
+                    level: DeviceLogLevel::Error,
+                    message: message.into(),
+                };
+                push_device_log(&mut self.log_file, main, log);
+            }
+            UdpPacket::Diagnostics(packet) => {
+                let device = device?;
+                if let Some(reason) = device.update_diagnostics(packet)? {
+                    let log = DeviceLog {
+                        mac: device.mac.clone(),
+                        level: match reason.is_crash() {
+                            true => DeviceLogLevel::Warn,
+                            false => DeviceLogLevel::Info,
+                        },
+                        message: format!("Device started after reset: {reason:?}").into(),
+                    };
+                    push_device_log(&mut self.log_file, main, log);
+                }
+            }
+            UdpPacket::CommandAck(packet) => {
+                let device = device?;
+                let result = device
+                    .commands
+                    .handle_ack(&device.mac, packet)
+                    .ok_or_else(|| anyhow::anyhow!("No command waiting on the acknowledgement"))?;
+                main.updates.command_results.push(result);
+            }
+            UdpPacket::Reliable(packet) => {
+                let device = device?;
+                let ack = reliable::ack_packet(packet.sequence);
+                send_to_device(&self.socket, device, &ack).await?;
+
+                if !device.reliable.receive(packet.sequence) {
+                    log::trace!("Dropped duplicate reliable packet #{}", packet.sequence);
+                    return Ok(());
+                }
+
+                // The reliable packet has already been checked so the wrapped one isn't
+                let bytes = packet.to_packet();
+                let mut bytes = bytes.as_slice();
+                let (packet, _) = UdpPacket::parse(&mut bytes)?;
+                Box::pin(self.handle_parsed_packet(packet, peer_addr, main)).await?;
+            }
+            UdpPacket::ReliableAck(packet) => {
+                device?.reliable.handle_ack(packet.sequence);
+            }
+            UdpPacket::Discovery(packet) => {
+                let response = packet.to_response(self.config.port, WEBSOCKET_PORT);
+                self.socket.send_to(&response, peer_addr).await?;
+            }
         }
 
         Ok(())
     }
 
-    fn handle_handshake(&mut self, packet: UdpPacketHandshake, peer_addr: SocketAddr) {
+    /// Returns an error with the reason when the device isn't allowed to connect, because it's
+    /// ignored or only known devices are allowed
+    fn check_accepted(&self, mac: &str, main: &MainServer) -> anyhow::Result<()> {
+        if self.config.is_ignored(mac) {
+            anyhow::bail!("Device {mac} is ignored");
+        }
+        if self.config.only_known_devices && !self.is_known(mac, main) {
+            anyhow::bail!("Device {mac} is not known");
+        }
+        Ok(())
+    }
+
+    /// Returns true if the device is connected, has trackers from before, is paired or approved
+    fn is_known(&self, mac: &str, main: &MainServer) -> bool {
+        let tracker_prefix = format!("{mac}/");
+        self.mac_to_address_map.contains_key(mac)
+            || main
+                .trackers
+                .keys()
+                .any(|id| id.starts_with(&tracker_prefix))
+            || self.paired_devices.get(mac).is_some()
+            || self
+                .config
+                .approved_devices
+                .iter()
+                .any(|approved| &**approved == mac)
+    }
+
+    fn handle_handshake(
+        &mut self,
+        packet: UdpPacketHandshake,
+        peer_addr: SocketAddr,
+    ) -> anyhow::Result<Vec<u8>> {
+        if let Some(info) = &packet.device_info {
+            log::info!(
+                "Device {} is a {} with {} {} sensors on protocol {}",
+                packet.mac_address,
+                info.board,
+                info.sensor_count,
+                info.imu,
+                info.protocol_version
+            );
+        }
+
+        // Paired devices get a nonce for the new session in the response
+        let mut response = packet.to_response();
+        let key = self.paired_devices.get(&packet.mac_address);
+        match (key, packet.auth_nonce) {
+            (Some(key), Some(device_nonce)) => {
+                let server_nonce = auth::generate_nonce();
+                response.extend(server_nonce);
+                response =
+                    AuthSession::new(*key, &device_nonce).seal(Direction::FromServer, &response);
+                let session = AuthSession::new(*key, &[device_nonce, server_nonce].concat());
+                self.pending_sessions.insert(
+                    peer_addr,
+                    PendingSession {
+                        handshake: packet,
+                        session,
+                        created: Instant::now(),
+                    },
+                );
+            }
+            (Some(_), None) => anyhow::bail!("Paired device did not send a nonce"),
+            (None, _) => self.connect_device(packet, peer_addr, None)?,
+        }
+
+        Ok(response)
+    }
+
+    fn connect_device(
+        &mut self,
+        packet: UdpPacketHandshake,
+        peer_addr: SocketAddr,
+        auth: Option<AuthSession>,
+    ) -> anyhow::Result<()> {
+        let firmware = match (&packet.device_info, &packet.firmware_version) {
+            (Some(info), Some(version)) => Some(UdpPacketFirmwareVersion {
+                board: info.board.clone(),
+                version: version.clone(),
+            }),
+            _ => None,
+        };
+        let available_firmware = match &firmware {
+            Some(firmware) => self
+                .firmware_files
+                .find_latest(&firmware.board)
+                .filter(|file| file.is_newer_than(&firmware.version))
+                .map(|file| file.version.clone()),
+            None => None,
+        };
+
+        let capabilities = packet.negotiated_capabilities();
+        let device_info = packet.device_info.clone();
+        let device = self.add_device(packet.mac_address, peer_addr);
+        device.set_device_info(device_info, capabilities);
+        device.set_auth(auth);
+        // The device starts its sequences over after a handshake
+        device.reliable = ReliableChannel::default();
+        if let Some(firmware) = firmware {
+            device.set_firmware(firmware, available_firmware);
+        }
+
+        Ok(())
+    }
+
+    fn update_pairings(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+        for (mac, key) in std::mem::take(&mut main.requests.new_pairings) {
+            self.paired_devices.insert(mac, key)?;
+        }
+
+        for mac in std::mem::take(&mut main.requests.unpair_requests) {
+            self.paired_devices.remove(&mac)?;
+
+            // The device has to connect again without authentication
+            let device = self
+                .mac_to_address_map
+                .get(&mac)
+                .and_then(|address| self.devices_map.get_mut(address));
+            if let Some(device) = device {
+                device.set_auth(None);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn update_approvals(&mut self, main: &mut MainServer) {
+        for (mac, approved) in std::mem::take(&mut main.requests.device_approvals) {
+            if self.pending_devices.remove(&mac).is_none() {
+                continue;
+            }
+
+            let config = main
+                .updates
+                .config
+                .get_or_insert_with(|| main.config.clone());
+            if approved {
+                log::info!("Approved device {mac}");
+                config.udp.approved_devices.push(mac);
+            } else {
+                log::info!("Rejected device {mac}");
+                config.udp.ignored_devices.push(mac);
+            }
+        }
+    }
+
+    /// Gets the device with the mac address or creates it, moving it over if it has a new address
+    fn add_device(&mut self, mac: Arc<str>, peer_addr: SocketAddr) -> &mut UdpDevice {
         // Check if the device already has connected with a mac address
-        if let Some(address) = self.mac_to_address_map.get(&packet.mac_address) {
+        if let Some(address) = self.mac_to_address_map.get(&mac) {
             let device = self.devices_map.get_mut(address).unwrap();
-            device.last_packet_number = 0;
+            device.sequence.reset();
+            device.clock.reset();
 
             // Move over to the new address if the device has a new ip
             if *address != peer_addr {
@@ -167,22 +658,235 @@ impl UdpServer {
                 // Swap in the map
                 let device = self.devices_map.remove(address).unwrap();
                 self.devices_map.insert(peer_addr, device);
-                self.mac_to_address_map
-                    .insert(packet.mac_address, peer_addr);
+                self.mac_to_address_map.insert(mac, peer_addr);
             } else if device.is_timed_out() {
                 log::info!("Reconnected from {peer_addr}");
             } else {
                 log::warn!("Received handshake packet while already connected");
             }
 
-            return;
+            return self.devices_map.get_mut(&peer_addr).unwrap();
         }
 
         // Create a new udp device
-        let device = UdpDevice::new(peer_addr, packet.mac_address.clone());
-        self.mac_to_address_map
-            .insert(packet.mac_address, peer_addr);
-        self.devices_map.insert(peer_addr, device);
+        let device = UdpDevice::new(peer_addr, mac.clone());
+        self.mac_to_address_map.insert(mac, peer_addr);
         log::info!("New udp device connected from {peer_addr}");
+        self.devices_map.insert(peer_addr, device);
+        self.devices_map.get_mut(&peer_addr).unwrap()
+    }
+
+    fn start_firmware_update(&mut self, mac: &str) -> anyhow::Result<()> {
+        // The user may have just added the firmware file
+        self.firmware_files.rescan();
+        let device = self
+            .mac_to_address_map
+            .get(mac)
+            .and_then(|address| self.devices_map.get_mut(address))
+            .ok_or_else(|| anyhow::anyhow!("Device {mac} is not connected"))?;
+
+        if device.firmware_update.is_some() {
+            anyhow::bail!("Device {mac} is already being updated");
+        }
+
+        if !device.has_negotiated(CAPABILITY_OTA) {
+            anyhow::bail!("Device {mac} does not support firmware updates");
+        }
+
+        let board = &device
+            .firmware
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Device {mac} has not sent its firmware version"))?
+            .board;
+        let file = self
+            .firmware_files
+            .find_latest(board)
+            .ok_or_else(|| anyhow::anyhow!("No firmware found for {board}"))?;
+
+        log::info!("Updating {mac} to firmware {}", file.version);
+        device.firmware_update = Some(FirmwareUpdate::new(file.load()?));
+        Ok(())
+    }
+
+    async fn update_firmware_updates(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+        let statuses = &mut main.updates.firmware_update_statuses;
+
+        for mac in std::mem::take(&mut main.requests.firmware_updates) {
+            if let Err(err) = self.start_firmware_update(&mac) {
+                let error = err.to_string().into();
+                statuses.insert(mac, FirmwareUpdateStatus::Failed { error });
+            }
+        }
+
+        for device in self.devices_map.values_mut() {
+            let Some(update) = device.firmware_update.as_mut() else {
+                continue;
+            };
+
+            match update.poll() {
+                Ok(packets) => {
+                    if let Some(progress) = update.take_progress() {
+                        let status = FirmwareUpdateStatus::Uploading { progress };
+                        statuses.insert(device.mac.clone(), status);
+                    }
+
+                    for packet in packets {
+                        match packet[0] {
+                            PACKET_OTA_CHUNK => {
+                                send_to_device(&self.socket, device, &packet).await?
+                            }
+                            _ => send_control(&self.socket, device, &packet).await?,
+                        }
+                    }
+                }
+                Err(err) => {
+                    log::warn!("Firmware update of {} failed: {err}", device.mac);
+                    let error = err.to_string().into();
+                    statuses.insert(device.mac.clone(), FirmwareUpdateStatus::Failed { error });
+                    device.firmware_update = None;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queues the command on the device of the tracker, the tracker id is `<mac>/<index>`
+    fn queue_command(&mut self, tracker_id: &str, command: DeviceCommand) -> anyhow::Result<()> {
+        let (mac, index) = tracker_id
+            .rsplit_once('/')
+            .and_then(|(mac, index)| Some((mac, index.parse().ok()?)))
+            .ok_or_else(|| anyhow::anyhow!("Tracker {tracker_id} is not a udp tracker"))?;
+
+        let device = self
+            .mac_to_address_map
+            .get(mac)
+            .and_then(|address| self.devices_map.get_mut(address))
+            .ok_or_else(|| anyhow::anyhow!("Device {mac} is not connected"))?;
+
+        if !device.has_negotiated(CAPABILITY_COMMANDS) {
+            anyhow::bail!("Device {mac} does not support commands");
+        }
+
+        log::info!("Sending {command:?} to {tracker_id}");
+        device.commands.push(index, command);
+        Ok(())
+    }
+
+    async fn update_commands(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+        let results = &mut main.updates.command_results;
+
+        for (tracker_id, command) in std::mem::take(&mut main.requests.tracker_commands) {
+            if let Err(err) = self.queue_command(&tracker_id, command.clone()) {
+                results.push(CommandResult {
+                    tracker_id,
+                    command,
+                    error: Some(err.to_string().into()),
+                });
+            }
+        }
+
+        for device in self.devices_map.values_mut() {
+            let reliable = device.has_negotiated(CAPABILITY_RELIABLE);
+            let (packets, failed) = device.commands.poll(&device.mac, reliable);
+            for packet in packets {
+                send_control(&self.socket, device, &packet).await?;
+            }
+            results.extend(failed);
+
+            // Send again whatever hasn't been acknowledged
+            for packet in device.reliable.poll(&device.mac) {
+                send_to_device(&self.socket, device, &packet).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends a control packet, reliably if the device has negotiated it
+async fn send_control(
+    socket: &tokio::net::UdpSocket,
+    device: &mut UdpDevice,
+    packet: &[u8],
+) -> std::io::Result<()> {
+    if !device.has_negotiated(CAPABILITY_RELIABLE) {
+        return send_to_device(socket, device, packet).await;
+    }
+
+    device.reliable.push(packet);
+    for packet in device.reliable.poll(&device.mac) {
+        send_to_device(socket, device, &packet).await?;
+    }
+    Ok(())
+}
+
+/// Sends the packet to the device, authenticated if the device is paired
+async fn send_to_device(
+    socket: &tokio::net::UdpSocket,
+    device: &UdpDevice,
+    packet: &[u8],
+) -> std::io::Result<()> {
+    socket.send_to(&device.seal(packet), device.address).await?;
+    Ok(())
+}
+
+fn push_device_log(log_file: &mut DeviceLogFile, main: &mut MainServer, log: DeviceLog) {
+    log::debug!("{} {:?}: {}", log.mac, log.level, log.message);
+    if let Err(err) = log_file.write(&log) {
+        log::warn!("Failed to write device log: {err}");
+    }
+
+    let logs = &mut main.updates.device_logs;
+    logs.push(log);
+    let excess = logs.len().saturating_sub(MAX_DEVICE_LOGS);
+    logs.drain(0..excess);
+}
+
+#[async_trait]
+impl InputSource for UdpServer {
+    async fn apply_config(&mut self, config: &GlobalConfig) -> anyhow::Result<()> {
+        if self.config.socket_changed(&config.udp) {
+            // Close the old socket first since the new one can be on the same port
+            self.socket = Arc::new(socket::unbound()?);
+            let socket = match socket::bind(&config.udp) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    // Keep receiving on the old address
+                    self.socket = Arc::new(socket::bind(&self.config)?);
+                    self.receiver =
+                        DatagramReceiver::spawn(self.socket.clone(), self.received.clone());
+                    return Err(err);
+                }
+            };
+            self.socket = Arc::new(socket);
+            self.receiver = DatagramReceiver::spawn(self.socket.clone(), self.received.clone());
+        }
+        self.mdns.apply_config(&config.udp)?;
+        self.config = config.udp.clone();
+        self.firmware_files.rescan();
+
+        // Disconnect the devices that were just ignored
+        for mac in &self.config.ignored_devices {
+            if let Some(address) = self.mac_to_address_map.remove(mac) {
+                if let Some(mut device) = self.devices_map.remove(&address) {
+                    device.update_timed_out(true);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn update(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+        if self.last_upkeep_time.elapsed() > UPKEEP_INTERVAL {
+            self.upkeep(main).await?;
+            self.last_upkeep_time = Instant::now();
+        }
+
+        self.update_pairings(main)?;
+        self.update_approvals(main);
+        self.update_firmware_updates(main).await?;
+        self.update_commands(main).await?;
+        self.receive_packets(main).await
     }
 }
@@ -0,0 +1,92 @@
+//! Binding the udp server socket and joining the multicast groups devices discover the server with
+//!
+//! Binding to an IPv6 address also accepts IPv4 devices where the system supports dual-stack
+//! sockets. IPv6 devices discover the server with the link-local multicast group, which needs the
+//! interface index when the machine has more than one network interface.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::udp::server::UdpConfig;
+
+pub const MULTICAST_IP: Ipv4Addr = Ipv4Addr::new(239, 255, 0, 123);
+pub const MULTICAST_IP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x123);
+
+/// Network interface that multicast is received on
+#[derive(Debug, Default)]
+struct MulticastInterface {
+    address: Option<Ipv4Addr>,
+    /// 0 lets the system choose the interface
+    index: u32,
+}
+
+impl MulticastInterface {
+    fn find(name: Option<&str>) -> anyhow::Result<Self> {
+        let Some(name) = name else {
+            return Ok(Self::default());
+        };
+
+        let interfaces: Vec<_> = if_addrs::get_if_addrs()?
+            .into_iter()
+            .filter(|interface| interface.name == name)
+            .collect();
+        let Some(first) = interfaces.first() else {
+            anyhow::bail!("No network interface named {name}");
+        };
+
+        let address = interfaces
+            .iter()
+            .find_map(|interface| match interface.ip() {
+                IpAddr::V4(address) => Some(address),
+                IpAddr::V6(_) => None,
+            });
+        Ok(Self {
+            address,
+            index: first.index.unwrap_or_default(),
+        })
+    }
+}
+
+/// Socket that isn't bound to any port, used while the server socket is being replaced
+pub fn unbound() -> anyhow::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+pub fn bind(config: &UdpConfig) -> anyhow::Result<UdpSocket> {
+    let address = SocketAddr::new(config.bind_address, config.port);
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    if address.is_ipv6() {
+        // Also accept IPv4 devices, not every system allows it
+        if let Err(err) = socket.set_only_v6(false) {
+            log::warn!("Failed to accept IPv4 on {address}: {err}");
+        }
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    let socket = UdpSocket::from_std(socket.into())?;
+
+    let interface = MulticastInterface::find(config.multicast_interface.as_deref())?;
+    let v4_interface = interface.address.unwrap_or(Ipv4Addr::UNSPECIFIED);
+    match config.bind_address {
+        IpAddr::V4(_) => socket.join_multicast_v4(MULTICAST_IP, v4_interface)?,
+        IpAddr::V6(_) => {
+            if let Err(err) = socket.join_multicast_v6(&MULTICAST_IP_V6, interface.index) {
+                log::warn!("Failed to join multicast group {MULTICAST_IP_V6}: {err}");
+            }
+            if let Err(err) = socket.join_multicast_v4(MULTICAST_IP, v4_interface) {
+                log::warn!("Failed to join multicast group {MULTICAST_IP}: {err}");
+            }
+        }
+    }
+
+    log::info!("Started UDP server on {}", socket.local_addr()?);
+    Ok(socket)
+}
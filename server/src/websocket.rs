@@ -13,11 +13,22 @@ use ts_rs::TS;
 
 use crate::{
     config::GlobalConfig,
+    firmware::{FirmwareImage, FirmwareUpdateStatus},
+    looper::LoopMetrics,
     main_server::MainServer,
     record::BvhSaver,
-    serial::SerialPortManager,
+    serial::{
+        command::{SerialCommand, SerialResponse},
+        SerialPortInfo, SerialPortManager,
+    },
     skeleton::{Bone, BoneLocation},
     tracker::TrackerRef,
+    udp::{
+        command::{CommandResult, DeviceCommand},
+        diagnostics::DeviceLog,
+        link::LinkQuality,
+        pending::PendingDevice,
+    },
 };
 
 pub const WEBSOCKET_PORT: u16 = 8298;
@@ -30,9 +41,8 @@ pub enum WebsocketServerMessage<'a> {
     },
     InitialState {
         config: &'a GlobalConfig,
-        #[ts(optional)]
-        port_name: Option<Box<str>>,
-        default_config: GlobalConfig,
+        serial_ports: Vec<SerialPortInfo>,
+        default_config: Box<GlobalConfig>,
         trackers: &'a HashMap<Arc<str>, TrackerRef>,
     },
     SkeletonUpdate {
@@ -42,11 +52,40 @@ pub enum WebsocketServerMessage<'a> {
         config: &'a GlobalConfig,
     },
     SerialLog {
+        port_name: &'a str,
         log: &'a str,
     },
-    SerialPortChanged {
-        #[ts(optional)]
-        port_name: Option<Box<str>>,
+    SerialResponse {
+        port_name: &'a str,
+        response: SerialResponse,
+    },
+    SerialFlash {
+        port_name: &'a str,
+        status: FirmwareUpdateStatus,
+    },
+    SerialPortsUpdate {
+        ports: Vec<SerialPortInfo>,
+    },
+    FirmwareUpdate {
+        mac: Arc<str>,
+        status: FirmwareUpdateStatus,
+    },
+    DeviceLog {
+        log: DeviceLog,
+    },
+    CommandResult {
+        result: CommandResult,
+    },
+    /// Maps the mac address of a udp device to its link quality
+    LinkQualityUpdate {
+        qualities: HashMap<Arc<str>, LinkQuality>,
+    },
+    /// Every udp device that is waiting for approval
+    PendingDevices {
+        devices: Vec<PendingDevice>,
+    },
+    LoopMetrics {
+        metrics: LoopMetrics,
     },
     Error {
         error: &'a str,
@@ -57,18 +96,69 @@ pub enum WebsocketServerMessage<'a> {
 #[derive(Deserialize, TS)]
 #[serde(tag = "type")]
 pub enum WebsocketClientMessage {
-    SerialSend { data: Box<str> },
-    RemoveTracker { id: Box<str> },
-    UpdateConfig { config: GlobalConfig },
+    SerialSend {
+        port_name: Box<str>,
+        data: Box<str>,
+    },
+    SerialCommand {
+        port_name: Box<str>,
+        command: SerialCommand,
+    },
+    /// Flashes the firmware binary through the bootloader of the device
+    FlashSerialFirmware {
+        port_name: Box<str>,
+        path: PathBuf,
+    },
+    /// Pairs the device so its udp packets get authenticated
+    PairSerialDevice {
+        port_name: Box<str>,
+    },
+    UnpairDevice {
+        mac: Arc<str>,
+    },
+    /// Allows a device from the ignored devices of the udp config to connect again
+    UnignoreDevice {
+        mac: Arc<str>,
+    },
+    /// Lets a pending device connect
+    ApproveDevice {
+        mac: Arc<str>,
+    },
+    /// Adds a pending device to the ignored devices
+    RejectDevice {
+        mac: Arc<str>,
+    },
+    ConnectSerialPort {
+        port_name: Box<str>,
+    },
+    DisconnectSerialPort {
+        port_name: Box<str>,
+    },
+    RemoveTracker {
+        id: Box<str>,
+    },
+    /// Updates the udp device to the newest firmware in the firmware folder
+    StartFirmwareUpdate {
+        mac: Arc<str>,
+    },
+    /// Sends the command to the device of the udp tracker
+    TrackerCommand {
+        id: Arc<str>,
+        command: DeviceCommand,
+    },
+    UpdateConfig {
+        config: Box<GlobalConfig>,
+    },
     ResetTrackerOrientations,
     StartRecord,
-    StopRecord { save_path: PathBuf },
+    StopRecord {
+        save_path: PathBuf,
+    },
 }
 
 pub struct WebsocketServer {
     listener: TcpListener,
     ws_stream: Option<WebSocketStream<TcpStream>>,
-    serial_manager: SerialPortManager,
 }
 
 impl WebsocketServer {
@@ -79,30 +169,37 @@ impl WebsocketServer {
         Ok(Self {
             listener,
             ws_stream: None,
-            serial_manager: SerialPortManager::default(),
         })
     }
 
-    pub async fn update(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+    pub async fn update(
+        &mut self,
+        main: &mut MainServer,
+        serial_manager: &mut SerialPortManager,
+    ) -> anyhow::Result<()> {
         if self.ws_stream.is_some() {
-            self.try_get_ws_messages(main).await?;
-            self.send_ws_messages(main).await?;
+            self.try_get_ws_messages(main, serial_manager).await?;
+            self.send_ws_messages(main, serial_manager).await?;
         } else {
-            self.try_receive_ws_connection(main).await?;
+            self.try_receive_ws_connection(main, serial_manager).await?;
         }
 
         Ok(())
     }
 
-    async fn try_receive_ws_connection(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+    async fn try_receive_ws_connection(
+        &mut self,
+        main: &mut MainServer,
+        serial_manager: &mut SerialPortManager,
+    ) -> anyhow::Result<()> {
         match self.listener.accept().now_or_never() {
             Some(Ok((stream, peer_addr))) => {
                 let mut ws_stream = tokio_tungstenite::accept_async(stream).await?;
 
                 let message = WebsocketServerMessage::InitialState {
                     config: &main.config,
-                    port_name: self.serial_manager.port_name(),
-                    default_config: GlobalConfig::default(),
+                    serial_ports: serial_manager.ports(),
+                    default_config: Box::default(),
                     trackers: &main.trackers,
                 };
                 feed_ws_message(&mut ws_stream, message).await?;
@@ -117,7 +214,11 @@ impl WebsocketServer {
         }
     }
 
-    async fn try_get_ws_messages(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+    async fn try_get_ws_messages(
+        &mut self,
+        main: &mut MainServer,
+        serial_manager: &mut SerialPortManager,
+    ) -> anyhow::Result<()> {
         let ws_stream = match self.ws_stream.as_mut() {
             Some(ws_stream) => ws_stream,
             None => return Ok(()),
@@ -127,7 +228,7 @@ impl WebsocketServer {
         match ws_stream.next().now_or_never() {
             Some(Some(Ok(message))) => {
                 if let Ok(text) = message.to_text() {
-                    self.handle_ws_message(text, main)?;
+                    self.handle_ws_message(text, main, serial_manager)?;
                 }
             }
             Some(None) | Some(Some(Err(_))) => {
@@ -141,22 +242,77 @@ impl WebsocketServer {
         Ok(())
     }
 
-    async fn send_ws_messages(&mut self, main: &mut MainServer) -> anyhow::Result<()> {
+    async fn send_ws_messages(
+        &mut self,
+        main: &mut MainServer,
+        serial_manager: &mut SerialPortManager,
+    ) -> anyhow::Result<()> {
         let ws_stream = match self.ws_stream.as_mut() {
             Some(ws_stream) => ws_stream,
             None => return Ok(()),
         };
 
         // Send the serial stuff
-        if self.serial_manager.check_port().await {
-            let message = WebsocketServerMessage::SerialPortChanged {
-                port_name: self.serial_manager.port_name(),
+        if serial_manager.take_ports_changed() {
+            let message = WebsocketServerMessage::SerialPortsUpdate {
+                ports: serial_manager.ports(),
             };
             feed_ws_message(ws_stream, message).await?;
         }
 
-        if let Some(log) = self.serial_manager.read_line() {
-            feed_ws_message(ws_stream, WebsocketServerMessage::SerialLog { log }).await?;
+        for (port_name, received) in serial_manager.take_received() {
+            for log in received.logs {
+                let message = WebsocketServerMessage::SerialLog {
+                    port_name: &port_name,
+                    log: &log,
+                };
+                feed_ws_message(ws_stream, message).await?;
+            }
+
+            for response in received.responses {
+                let message = WebsocketServerMessage::SerialResponse {
+                    port_name: &port_name,
+                    response,
+                };
+                feed_ws_message(ws_stream, message).await?;
+            }
+
+            if let Some(status) = received.flash_status {
+                let message = WebsocketServerMessage::SerialFlash {
+                    port_name: &port_name,
+                    status,
+                };
+                feed_ws_message(ws_stream, message).await?;
+            }
+        }
+
+        for (mac, status) in std::mem::take(&mut main.updates.firmware_update_statuses) {
+            let message = WebsocketServerMessage::FirmwareUpdate { mac, status };
+            feed_ws_message(ws_stream, message).await?;
+        }
+
+        for log in std::mem::take(&mut main.updates.device_logs) {
+            feed_ws_message(ws_stream, WebsocketServerMessage::DeviceLog { log }).await?;
+        }
+
+        for result in std::mem::take(&mut main.updates.command_results) {
+            feed_ws_message(ws_stream, WebsocketServerMessage::CommandResult { result }).await?;
+        }
+
+        if !main.updates.link_qualities.is_empty() {
+            let qualities = std::mem::take(&mut main.updates.link_qualities);
+            let message = WebsocketServerMessage::LinkQualityUpdate { qualities };
+            feed_ws_message(ws_stream, message).await?;
+        }
+
+        if let Some(devices) = main.updates.pending_devices.take() {
+            let message = WebsocketServerMessage::PendingDevices { devices };
+            feed_ws_message(ws_stream, message).await?;
+        }
+
+        if let Some(metrics) = main.updates.loop_metrics.take() {
+            let message = WebsocketServerMessage::LoopMetrics { metrics };
+            feed_ws_message(ws_stream, message).await?;
         }
 
         if let Some(error) = main.updates.error.as_ref() {
@@ -188,23 +344,72 @@ impl WebsocketServer {
         Ok(())
     }
 
-    fn handle_ws_message(&mut self, message: &str, main: &mut MainServer) -> anyhow::Result<()> {
+    fn handle_ws_message(
+        &mut self,
+        message: &str,
+        main: &mut MainServer,
+        serial_manager: &mut SerialPortManager,
+    ) -> anyhow::Result<()> {
         if message.is_empty() {
             return Ok(());
         }
 
         match serde_json::from_str(message)? {
-            WebsocketClientMessage::SerialSend { data } => {
-                log::info!("Writing {data:?} to port");
-                self.serial_manager.write(data.as_bytes())?;
+            WebsocketClientMessage::SerialSend { port_name, data } => {
+                log::info!("Writing {data:?} to {port_name}");
+                serial_manager.write(&port_name, data.as_bytes())?;
+            }
+            WebsocketClientMessage::SerialCommand { port_name, command } => {
+                serial_manager.send_command(&port_name, &command)?;
+            }
+            WebsocketClientMessage::FlashSerialFirmware { port_name, path } => {
+                let image = FirmwareImage::load_file(&path)?;
+                serial_manager.flash_firmware(&port_name, image)?;
+            }
+            WebsocketClientMessage::PairSerialDevice { port_name } => {
+                serial_manager.pair(&port_name)?;
+            }
+            WebsocketClientMessage::UnpairDevice { mac } => {
+                main.requests.unpair_requests.push(mac);
+            }
+            WebsocketClientMessage::UnignoreDevice { mac } => {
+                let config = main
+                    .updates
+                    .config
+                    .get_or_insert_with(|| main.config.clone());
+                config.udp.ignored_devices.retain(|ignored| *ignored != mac);
+            }
+            WebsocketClientMessage::ApproveDevice { mac } => {
+                main.requests.device_approvals.push((mac, true));
+            }
+            WebsocketClientMessage::RejectDevice { mac } => {
+                main.requests.device_approvals.push((mac, false));
+            }
+            WebsocketClientMessage::ConnectSerialPort { port_name } => {
+                serial_manager.connect(&port_name)?;
+            }
+            WebsocketClientMessage::DisconnectSerialPort { port_name } => {
+                serial_manager.disconnect(&port_name);
             }
             WebsocketClientMessage::RemoveTracker { id } => {
                 if let Some(tracker) = main.trackers.get(&*id) {
                     tracker.lock().unwrap().update_info().to_be_removed = true;
                 }
             }
-            WebsocketClientMessage::UpdateConfig { config } => {
-                main.updates.config = Some(config);
+            WebsocketClientMessage::StartFirmwareUpdate { mac } => {
+                main.requests.firmware_updates.push(mac);
+            }
+            WebsocketClientMessage::TrackerCommand { id, command } => {
+                main.requests.tracker_commands.push((id, command));
+            }
+            WebsocketClientMessage::UpdateConfig { mut config } => {
+                // Keep the devices the server has ignored or approved in this update
+                if let Some(changed) = &main.updates.config {
+                    config
+                        .udp
+                        .merge_device_lists(&main.config.udp, &changed.udp);
+                }
+                main.updates.config = Some(*config);
             }
             WebsocketClientMessage::ResetTrackerOrientations => {
                 for tracker in main.trackers.values() {